@@ -11,7 +11,7 @@ check_file() {
 
 echo "Checking the existence of the necessary files..."
 # Daemon
-check_file "$HOME/.local/share/razercontrol"
+check_file "${XDG_CONFIG_HOME:-$HOME/.config}/razercontrol"
 check_file "/usr/share/razercontrol/daemon"
 check_file "/usr/share/razercontrol/laptops.json"
 check_file "/etc/udev/rules.d/99-hidraw-permissions.rules"
@@ -22,6 +22,9 @@ check_file "/usr/bin/razer-settings"
 check_file "/usr/share/applications/razer-settings.desktop"
 echo "All files are present"
 
+printf "Checking the razercontrol group for the LED and charge limit: "
+getent group razercontrol
+
 printf "Checking that the service is enabled: "
 systemctl --user is-enabled razercontrol.service
 
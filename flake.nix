@@ -85,12 +85,14 @@
             services.upower.enable = true;
             environment.systemPackages = [ cfg.package ];
             services.udev.packages = [ cfg.package ];
+            # Members may write the keyboard backlight LED and the battery charge limit, see 99-hidraw-permissions.rules
+            users.groups.razercontrol = { };
 
             systemd.user.services."razerdaemon" = {
               description = "Razer laptop control daemon";
               serviceConfig = {
                 Type = "simple";
-                ExecStartPre = "${pkgs.coreutils}/bin/mkdir -p %h/.local/share/razercontrol";
+                ExecStartPre = "${pkgs.coreutils}/bin/mkdir -p %E/razercontrol %S/razercontrol";
                 ExecStart = "${cfg.package}/libexec/daemon";
               };
               wantedBy = [ "default.target" ];
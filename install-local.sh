@@ -40,6 +40,11 @@ elif [ -f "$BUILD_DIR/data/gui/com.github.encomjp.razercontrol.svg" ]; then
     sudo install -Dm644 "$BUILD_DIR/data/gui/com.github.encomjp.razercontrol.svg" /usr/share/icons/hicolor/scalable/apps/com.github.encomjp.razercontrol.svg
 fi
 
+# Members may write the sysfs files the udev rules hand to the daemon
+echo "Adding $USER to the razercontrol group..."
+sudo groupadd -f razercontrol
+sudo usermod -aG razercontrol "$USER"
+
 # Install udev rules
 echo "Installing udev rules..."
 sudo install -Dm644 "$BUILD_DIR/data/udev/99-hidraw-permissions.rules" /etc/udev/rules.d/99-hidraw-permissions.rules
@@ -54,7 +59,7 @@ sudo mkdir -p /usr/share/razercontrol
 sudo install -Dm644 "$BUILD_DIR/data/devices/laptops.json" /usr/share/razercontrol/laptops.json
 
 # Create config directory
-mkdir -p ~/.local/share/razercontrol
+mkdir -p "${XDG_CONFIG_HOME:-$HOME/.config}/razercontrol" "${XDG_STATE_HOME:-$HOME/.local/state}/razercontrol"
 
 # Reload udev and systemd
 echo "Reloading udev rules..."
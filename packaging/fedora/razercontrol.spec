@@ -15,6 +15,7 @@ BuildRequires:  hidapi-devel
 BuildRequires:  gtk4-devel
 BuildRequires:  libadwaita-devel
 BuildRequires:  systemd-devel
+BuildRequires:  systemd-rpm-macros
 BuildRequires:  glib2-devel
 BuildRequires:  graphene-devel
 BuildRequires:  pango-devel
@@ -47,6 +48,7 @@ install -D -m 644 razer_control_gui/data/gui/icon.png $RPM_BUILD_ROOT%{_datadir}
 install -D -m 644 razer_control_gui/data/gui/icon.png $RPM_BUILD_ROOT%{_datadir}/icons/hicolor/512x512/apps/com.github.encomjp.razercontrol.png
 install -D -m 644 razer_control_gui/data/devices/laptops.json $RPM_BUILD_ROOT%{_datadir}/razercontrol/laptops.json
 install -D -m 644 razer_control_gui/data/udev/99-hidraw-permissions.rules $RPM_BUILD_ROOT%{_udevrulesdir}/99-hidraw-permissions.rules
+install -D -m 644 razer_control_gui/data/sysusers/razercontrol.conf $RPM_BUILD_ROOT%{_sysusersdir}/razercontrol.conf
 install -D -m 644 razer_control_gui/data/services/systemd/razercontrol.service $RPM_BUILD_ROOT%{_userunitdir}/razercontrol.service
 
 %files
@@ -58,13 +60,18 @@ install -D -m 644 razer_control_gui/data/services/systemd/razercontrol.service $
 %{_datadir}/icons/hicolor/512x512/apps/com.github.encomjp.razercontrol.png
 %{_datadir}/razercontrol/laptops.json
 %{_udevrulesdir}/99-hidraw-permissions.rules
+%{_sysusersdir}/razercontrol.conf
 %{_userunitdir}/razercontrol.service
 %license LICENSE
 %doc README.md
 
+%pre
+getent group razercontrol >/dev/null || groupadd -r razercontrol
+
 %post
 udevadm control --reload-rules
 udevadm trigger
+echo "To let the daemon drive the backlight LED and charge limit, add yourself to the razercontrol group: sudo usermod -aG razercontrol \$USER"
 %systemd_user_post razercontrol.service
 
 %preun
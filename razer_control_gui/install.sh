@@ -32,7 +32,7 @@ install() {
 
     # Install the files
     echo "Installing the files..."
-    mkdir -p ~/.local/share/razercontrol
+    mkdir -p "${XDG_CONFIG_HOME:-$HOME/.config}/razercontrol" "${XDG_STATE_HOME:-$HOME/.local/state}/razercontrol"
     sudo bash <<EOF
         mkdir -p /usr/share/razercontrol
         cp target/release/razer-cli /usr/bin/
@@ -44,6 +44,9 @@ install() {
         fi
         cp target/release/daemon /usr/bin/razer-daemon
         cp data/devices/laptops.json /usr/share/razercontrol/
+        # Members may write the sysfs files the udev rules hand to the daemon
+        groupadd -f razercontrol
+        usermod -aG razercontrol $USER
         cp data/udev/99-hidraw-permissions.rules /etc/udev/rules.d/
         udevadm control --reload-rules
 EOF
@@ -86,7 +89,9 @@ uninstall() {
         rm -f /usr/bin/razer-daemon
         rm -f /usr/share/razercontrol/laptops.json
         rm -f /etc/udev/rules.d/99-hidraw-permissions.rules
+        rm -f /etc/udev/rules.d/99-razercontrol-key-input.rules
         udevadm control --reload-rules
+        groupdel razercontrol 2>/dev/null
 EOF
 
     if [ $? -ne 0 ]; then
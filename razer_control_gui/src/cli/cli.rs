@@ -29,10 +29,60 @@ enum Args {
     /// Write a custom effect
     Effect {
         #[command(subcommand)]
-        effect: Effect,
+        effect: EffectCommand,
+    },
+    /// Paint single keys, e.g. keys set F1=ff0000 Esc=00ff00
+    Keys {
+        #[command(subcommand)]
+        action: KeysAction,
+    },
+    /// Manage the daemon configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Validate the configuration file and report problems
+    Check,
+    /// Apply the configuration files without restarting the daemon
+    Reload,
+    /// Print all settings and effects as a bundle for another laptop
+    Export,
+    /// Apply a bundle written by export
+    Import {
+        /// Bundle file
+        file: std::path::PathBuf,
     },
 }
 
+#[derive(Subcommand)]
+enum KeysAction {
+    /// Paint keys over all layers, or on a per-key layer with --layer
+    Set(KeysSetParams),
+    /// Remove the painted keys, or turn all keys of a per-key layer off
+    Clear {
+        /// per-key layer instead of the script overlay
+        #[arg(long)]
+        layer: Option<usize>,
+    },
+}
+
+#[derive(Parser)]
+struct KeysSetParams {
+    /// per-key layer to paint instead of the script overlay, see effect layer
+    #[arg(long)]
+    layer: Option<usize>,
+    /// ms until the keys are transparent again (default 0 = never)
+    #[arg(long)]
+    timeout: Option<u32>,
+    /// KEY=RRGGBB, keys by name (Esc, F1, Digit1, Q, Space, ...) or index 0-89, see read keys
+    #[arg(required = true)]
+    keys: Vec<String>,
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 enum OnOff {
     On,
@@ -63,6 +113,22 @@ enum ReadAttr {
     FanRpm,
     /// Read GPU status information
     Gpu,
+    /// Read the low battery rules
+    BatteryRules,
+    /// Read the power-profiles-daemon integration
+    PowerProfiles,
+    /// Read the keyboard brightness fade timings
+    Fade,
+    /// Read the charge limit in effect and the kernel battery thresholds
+    ChargeThreshold,
+    /// Read the battery statistics recorded by the daemon
+    BatteryHistory,
+    /// Read the temperature, power and load sensors
+    Sensors,
+    /// Read the sensor, fan and power mode history recorded by the daemon
+    Telemetry(TelemetryParams),
+    /// Read the key names of the keyboard layout and their matrix index
+    Keys,
 }
 
 #[derive(Subcommand)]
@@ -83,6 +149,16 @@ enum WriteAttr {
     RuntimePm(RuntimePmParams),
     /// Set GPU mode via envycontrol (hybrid, integrated, nvidia)
     GpuMode(GpuModeParams),
+    /// Set the low battery rules
+    BatteryRules(BatteryRulesParams),
+    /// Follow the desktop power profile (power-profiles-daemon)
+    PowerProfiles(PowerProfilesParams),
+    /// Set the power mode used for a desktop power profile
+    ProfileMap(ProfileMapParams),
+    /// Set the keyboard brightness fade timings
+    Fade(FadeParams),
+    /// Temporarily charge past the battery health optimizer threshold
+    ChargeOverride(ChargeOverrideParams),
 }
 
 #[derive(Parser)]
@@ -145,6 +221,107 @@ struct GpuModeParams {
     mode: String,
 }
 
+#[derive(Parser)]
+struct BatteryRulesParams {
+    /// turn keyboard and logo lighting off below this battery percentage (0 = never)
+    no_light: u8,
+    /// switch to a lower power mode below this battery percentage (0 = never)
+    low_power: u8,
+    /// power mode used below the low power percentage (0, 1, 2 or 3)
+    power_mode: Option<u8>,
+}
+
+#[derive(Parser)]
+struct PowerProfilesParams {
+    /// apply the mapped power mode when the desktop profile changes
+    follow: OnOff,
+    /// also change the desktop profile when the power mode is set here
+    sync_back: Option<OnOff>,
+}
+
+#[derive(ValueEnum, Clone)]
+enum DesktopProfile {
+    PowerSaver,
+    Balanced,
+    Performance,
+}
+
+impl DesktopProfile {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DesktopProfile::PowerSaver => "power-saver",
+            DesktopProfile::Balanced => "balanced",
+            DesktopProfile::Performance => "performance",
+        }
+    }
+}
+
+#[derive(Parser)]
+struct ProfileMapParams {
+    /// desktop power profile
+    profile: DesktopProfile,
+    /// power mode (0, 1, 2, 3 or 4)
+    pwr: u8,
+    /// cpu boost (0, 1, 2 or 3)
+    cpu_mode: Option<u8>,
+    /// gpu boost (0, 1 or 2)
+    gpu_mode: Option<u8>,
+    /// fan speed in RPM (0 = auto)
+    fan: Option<i32>,
+}
+
+#[derive(ValueEnum, Clone)]
+enum ChargeOverrideMode {
+    /// keep to the battery health optimizer threshold
+    Off,
+    /// charge to 100% once, then return to the threshold
+    FullOnce,
+    /// no threshold until AC is unplugged
+    UntilUnplug,
+    /// be full at the given time, return to the threshold at the next unplug
+    FullBy,
+}
+
+impl ChargeOverrideMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ChargeOverrideMode::Off => "off",
+            ChargeOverrideMode::FullOnce => "full-once",
+            ChargeOverrideMode::UntilUnplug => "until-unplug",
+            ChargeOverrideMode::FullBy => "full-by",
+        }
+    }
+}
+
+#[derive(Parser)]
+struct ChargeOverrideParams {
+    mode: ChargeOverrideMode,
+    /// time of day for full-by, HH:MM
+    time: Option<String>,
+}
+
+#[derive(Parser)]
+struct FadeParams {
+    /// fade out time in ms when the lights go off
+    fade_out_ms: u32,
+    /// fade in time in ms when the lights come back
+    fade_in_ms: u32,
+    /// brightness percentage to dim to when idle, before going off (0 = straight off)
+    idle_level: Option<u8>,
+    /// seconds to stay dimmed before going off
+    idle_dim_secs: Option<u32>,
+    /// fade time in ms for brightness changes
+    slider_fade_ms: Option<u32>,
+}
+
+#[derive(Parser)]
+struct TelemetryParams {
+    /// minutes of history to show (default 10, up to a day is kept)
+    minutes: Option<u64>,
+    /// seconds per line, temperatures are the peak and power the average (default 60, 0 = every sample)
+    resolution: Option<u64>,
+}
+
 #[derive(ValueEnum, Clone)]
 enum AcState {
     /// battery
@@ -235,12 +412,122 @@ struct StarlightParams {
     blue2: u8,
 }
 
+#[derive(Subcommand)]
+enum EffectCommand {
+    /// Manage the custom lighting layers, layer 0 is at the bottom
+    Layer {
+        #[command(subcommand)]
+        action: LayerAction,
+    },
+    #[command(flatten)]
+    Set(Effect),
+}
+
+#[derive(Subcommand)]
+enum LayerAction {
+    /// List the layers from the bottom up
+    List,
+    /// Add a layer at the given position, e.g. insert 1 --blend add breathing-single 255 0 0 10
+    Insert(LayerInsertParams),
+    /// Remove a layer
+    Remove {
+        index: usize,
+    },
+    /// Move a layer to another position
+    Move {
+        from: usize,
+        to: usize,
+    },
+    /// Set the keys a layer lights
+    Mask {
+        index: usize,
+        /// "all", "none" or keys like W,A,S,D or index ranges like 0-14, see read keys
+        keys: String,
+    },
+    /// Change the blend mode, opacity or effect parameters of a layer
+    Update(LayerUpdateParams),
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Blend {
+    /// covers the layers below
+    Replace,
+    /// brightens the layers below
+    Add,
+    /// darkens the layers below, white keeps them
+    Multiply,
+    /// brightens the layers below, black keeps them
+    Screen,
+    /// covers the layers below by brightness, black is transparent
+    Alpha,
+}
+
+impl Blend {
+    fn name(&self) -> &'static str {
+        match self {
+            Blend::Replace => "replace",
+            Blend::Add => "add",
+            Blend::Multiply => "multiply",
+            Blend::Screen => "screen",
+            Blend::Alpha => "alpha",
+        }
+    }
+}
+
+#[derive(Parser)]
+struct LayerInsertParams {
+    /// position, the number of layers puts it on top
+    index: usize,
+    /// how the layer is combined with the ones below (default replace)
+    #[arg(long, value_enum)]
+    blend: Option<Blend>,
+    /// 0.0 (invisible) to 1.0 (default)
+    #[arg(long)]
+    opacity: Option<f32>,
+    /// keys to light, like layer mask (default all)
+    #[arg(long)]
+    keys: Option<String>,
+    #[command(subcommand)]
+    effect: Effect,
+}
+
+#[derive(Parser)]
+struct LayerUpdateParams {
+    index: usize,
+    #[arg(long, value_enum)]
+    blend: Option<Blend>,
+    /// 0.0 (invisible) to 1.0
+    #[arg(long)]
+    opacity: Option<f32>,
+    /// new parameters, the effect has to be the one the layer has
+    #[command(subcommand)]
+    effect: Option<Effect>,
+}
+
 #[derive(Subcommand)]
 enum Effect {
     Static(StaticParams),
     StaticGradient(StaticGradientParams),
     WaveGradient(WaveGradientParams),
     BreathingSingle(BreathingSingleParams),
+    /// a colour per key, all off until painted with keys set --layer
+    PerKey,
+    /// pressed keys light up and fade out, needs key_input in daemon.json
+    Afterglow(TypingParams),
+    /// rings spreading out from pressed keys, needs key_input in daemon.json
+    Ripple(TypingParams),
+    /// keys get hotter the more they are pressed, needs key_input in daemon.json
+    Heatmap(HeatmapParams),
+    /// CPU load as a bar on the number row (thresholds in %, default 20 90)
+    CpuLoad(MetricParams),
+    /// load of each core on the F-keys (thresholds in %, default 20 90)
+    CoreLoad(MetricParams),
+    /// CPU to GPU temperature across the keyboard (thresholds in °C, default 45 90)
+    Temperature(MetricParams),
+    /// battery charge on the function row while on battery (thresholds in %, default 20 80)
+    BatteryLevel(MetricParams),
+    /// audio spectrum as bars rising from the bottom row, see audio in daemon.json
+    Spectrum(SpectrumParams),
 }
 
 #[derive(Parser)]
@@ -297,6 +584,80 @@ struct BreathingSingleParams {
     duration: u8,
 }
 
+#[derive(Parser)]
+struct TypingParams {
+    /// red (0-255)
+    red: u8,
+    /// green (0-255)
+    green: u8,
+    /// blue (0-255)
+    blue: u8,
+    /// afterglow: fade time in 100 ms, ripple: speed in keys per second
+    #[arg(default_value_t = 10)]
+    speed: u8,
+}
+
+#[derive(Parser)]
+struct HeatmapParams {
+    /// cold red (0-255)
+    red1: u8,
+    /// cold green (0-255)
+    green1: u8,
+    /// cold blue (0-255)
+    blue1: u8,
+    /// hot red (0-255)
+    red2: u8,
+    /// hot green (0-255)
+    green2: u8,
+    /// hot blue (0-255)
+    blue2: u8,
+    /// minutes until a key press counts half, 0 = never cools down
+    #[arg(default_value_t = 5)]
+    half_life: u8,
+}
+
+#[derive(Parser)]
+struct MetricParams {
+    /// low red (0-255)
+    red1: u8,
+    /// low green (0-255)
+    green1: u8,
+    /// low blue (0-255)
+    blue1: u8,
+    /// high red (0-255)
+    red2: u8,
+    /// high green (0-255)
+    green2: u8,
+    /// high blue (0-255)
+    blue2: u8,
+    /// the low colour up to this value
+    low: Option<u8>,
+    /// the high colour from this value, blended in between
+    high: Option<u8>,
+}
+
+#[derive(Parser)]
+struct SpectrumParams {
+    /// bottom red (0-255)
+    red1: u8,
+    /// bottom green (0-255)
+    green1: u8,
+    /// bottom blue (0-255)
+    blue1: u8,
+    /// top red (0-255)
+    red2: u8,
+    /// top green (0-255)
+    green2: u8,
+    /// top blue (0-255)
+    blue2: u8,
+    /// gain in dB, raise it for quiet audio
+    #[arg(default_value_t = 20)]
+    gain: u8,
+    /// time in 100 ms for a full bar to fall back, 0 = at once
+    #[arg(default_value_t = 5)]
+    decay: u8,
+}
+
 fn main() {
     if std::fs::metadata(comms::SOCKET_PATH).is_err() {
         eprintln!("Error. Socket doesn't exit. Is daemon running?");
@@ -315,6 +676,14 @@ fn main() {
             ReadAttr::Bho => read_bho(),
             ReadAttr::FanRpm => read_actual_fan_rpm(),
             ReadAttr::Gpu => read_gpu_status(),
+            ReadAttr::BatteryRules => read_battery_rules(),
+            ReadAttr::PowerProfiles => read_power_profiles(),
+            ReadAttr::Fade => read_fade(),
+            ReadAttr::ChargeThreshold => read_charge_threshold(),
+            ReadAttr::BatteryHistory => read_battery_history(),
+            ReadAttr::Sensors => read_sensors(),
+            ReadAttr::Keys => read_key_layout(),
+            ReadAttr::Telemetry(params) => read_telemetry(params.minutes.unwrap_or(10), params.resolution.unwrap_or(60)),
         },
         Args::Write { attr } => match attr {
             WriteAttr::Fan(FanParams { ac_state, speed }) => {
@@ -344,38 +713,39 @@ fn main() {
             WriteAttr::GpuMode(GpuModeParams { mode }) => {
                 write_gpu_mode(&mode)
             }
+            WriteAttr::BatteryRules(BatteryRulesParams { no_light, low_power, power_mode }) => {
+                write_battery_rules(no_light, low_power, power_mode.unwrap_or(3))
+            }
+            WriteAttr::PowerProfiles(PowerProfilesParams { follow, sync_back }) => {
+                write_power_profiles(follow.is_on(), sync_back.map_or(false, |s| s.is_on()))
+            }
+            WriteAttr::ProfileMap(params) => write_profile_map(params),
+            WriteAttr::Fade(params) => write_fade(params),
+            WriteAttr::ChargeOverride(ChargeOverrideParams { mode, time }) => write_charge_override(mode, time),
         },
         Args::Effect { effect } => match effect {
-            Effect::Static(params) => send_effect(
-                "static".to_string(),
-                vec![params.red, params.green, params.blue],
-            ),
-            Effect::StaticGradient(params) => send_effect(
-                "static_gradient".to_string(),
-                vec![
-                    params.red1,
-                    params.green1,
-                    params.blue1,
-                    params.red2,
-                    params.green2,
-                    params.blue2,
-                ],
-            ),
-            Effect::WaveGradient(params) => send_effect(
-                "wave_gradient".to_string(),
-                vec![
-                    params.red1,
-                    params.green1,
-                    params.blue1,
-                    params.red2,
-                    params.green2,
-                    params.blue2,
-                ],
-            ),
-            Effect::BreathingSingle(params) => send_effect(
-                "breathing_single".to_string(),
-                vec![params.red, params.green, params.blue, params.duration],
-            ),
+            EffectCommand::Set(effect) => {
+                let (name, params) = effect_params(effect);
+                send_effect(name, params)
+            }
+            EffectCommand::Layer { action } => match action {
+                LayerAction::List => list_layers(),
+                LayerAction::Insert(params) => insert_layer(params),
+                LayerAction::Remove { index } => remove_layer(index),
+                LayerAction::Move { from, to } => move_layer(from, to),
+                LayerAction::Mask { index, keys } => set_layer_mask(index, &keys),
+                LayerAction::Update(params) => update_layer(params),
+            },
+        },
+        Args::Keys { action } => match action {
+            KeysAction::Set(params) => set_keys(params),
+            KeysAction::Clear { layer } => clear_keys(layer),
+        },
+        Args::Config { action } => match action {
+            ConfigAction::Check => check_config(),
+            ConfigAction::Reload => reload_config(),
+            ConfigAction::Export => export_config(),
+            ConfigAction::Import { file } => import_config(&file),
         },
         Args::StandardEffect { effect } => match effect {
             StandardEffect::Off => send_standard_effect("off".to_string(), vec![]),
@@ -569,6 +939,304 @@ fn send_effect(name: String, params: Vec<u8>) {
     }
 }
 
+/// IPC name and parameters of an effect
+fn effect_params(effect: Effect) -> (String, Vec<u8>) {
+    match effect {
+        Effect::Static(params) => (
+            "static".to_string(),
+            vec![params.red, params.green, params.blue],
+        ),
+        Effect::StaticGradient(params) => (
+            "static_gradient".to_string(),
+            vec![
+                params.red1,
+                params.green1,
+                params.blue1,
+                params.red2,
+                params.green2,
+                params.blue2,
+            ],
+        ),
+        Effect::WaveGradient(params) => (
+            "wave_gradient".to_string(),
+            vec![
+                params.red1,
+                params.green1,
+                params.blue1,
+                params.red2,
+                params.green2,
+                params.blue2,
+            ],
+        ),
+        Effect::BreathingSingle(params) => (
+            "breathing_single".to_string(),
+            vec![params.red, params.green, params.blue, params.duration],
+        ),
+        Effect::PerKey => ("per_key".to_string(), vec![]),
+        Effect::Afterglow(params) => (
+            "afterglow".to_string(),
+            vec![params.red, params.green, params.blue, params.speed],
+        ),
+        Effect::Ripple(params) => (
+            "ripple".to_string(),
+            vec![params.red, params.green, params.blue, params.speed],
+        ),
+        Effect::CpuLoad(params) => ("cpu_load".to_string(), metric_args(params)),
+        Effect::CoreLoad(params) => ("core_load".to_string(), metric_args(params)),
+        Effect::Temperature(params) => ("temperature".to_string(), metric_args(params)),
+        Effect::BatteryLevel(params) => ("battery_level".to_string(), metric_args(params)),
+        Effect::Spectrum(params) => (
+            "spectrum".to_string(),
+            vec![params.red1, params.green1, params.blue1, params.red2, params.green2, params.blue2, params.gain, params.decay],
+        ),
+        Effect::Heatmap(params) => (
+            "heatmap".to_string(),
+            vec![params.red1, params.green1, params.blue1, params.red2, params.green2, params.blue2, params.half_life],
+        ),
+    }
+}
+
+/// Colours, then the thresholds given, the daemon has defaults for the rest
+fn metric_args(params: MetricParams) -> Vec<u8> {
+    let mut args = vec![params.red1, params.green1, params.blue1, params.red2, params.green2, params.blue2];
+    args.extend(params.low);
+    args.extend(params.high);
+    return args;
+}
+
+fn get_key_layout() -> Option<service::keyboard::Layout> {
+    match send_data(comms::DaemonCommand::GetKeyLayout) {
+        Some(comms::DaemonResponse::GetKeyLayout { layout }) => Some(layout),
+        Some(_) => {
+            eprintln!("Daemon responded with invalid data!");
+            None
+        },
+        None => {
+            eprintln!("Unknown daemon error!");
+            None
+        },
+    }
+}
+
+/// Parses keys like W,A,S,D with the key layout of the laptop
+fn parse_key_mask(keys: &str) -> Option<Vec<bool>> {
+    let layout = get_key_layout()?;
+    match layout.parse_mask(keys) {
+        Ok(mask) => Some(mask),
+        Err(e) => {
+            eprintln!("{}", e);
+            None
+        }
+    }
+}
+
+/// Prints the key names row by row with their matrix index
+fn read_key_layout() {
+    let layout = match get_key_layout() {
+        Some(l) => l,
+        None => return,
+    };
+    println!("Key layout: {}", layout.name);
+    for row in 0..layout.rows {
+        let keys: Vec<String> = layout.keys.iter()
+            .filter(|k| k.index / layout.columns == row)
+            .map(|k| format!("{}={}", k.name, k.index))
+            .collect();
+        println!("{}", keys.join(" "));
+    }
+}
+
+fn print_layer_result(result: bool, message: String, done: &str) {
+    if result {
+        println!("{}", done);
+    } else {
+        eprintln!("Failed: {}", message);
+    }
+}
+
+fn get_layers() -> Option<Vec<comms::LayerInfo>> {
+    match send_data(comms::DaemonCommand::ListLayers) {
+        Some(comms::DaemonResponse::ListLayers { layers }) => Some(layers),
+        Some(_) => {
+            eprintln!("Daemon responded with invalid data!");
+            None
+        },
+        None => {
+            eprintln!("Unknown daemon error!");
+            None
+        },
+    }
+}
+
+fn list_layers() {
+    let layers = match get_layers() {
+        Some(l) => l,
+        None => return,
+    };
+    if layers.is_empty() {
+        println!("No custom lighting layers");
+        return;
+    }
+    let layout = match get_key_layout() {
+        Some(l) => l,
+        None => return,
+    };
+    for (idx, layer) in layers.iter().enumerate() {
+        let params: Vec<String> = layer.params.iter().map(|p| p.to_string()).collect();
+        println!("{}: {} {} - {} at {:.0}%, keys {}", idx, layer.effect, params.join(" "),
+            layer.blend, layer.opacity * 100.0, layout.format_mask(&layer.key_mask));
+    }
+}
+
+fn insert_layer(params: LayerInsertParams) {
+    let key_mask = match params.keys {
+        Some(keys) => match parse_key_mask(&keys) {
+            Some(m) => m,
+            None => return,
+        },
+        None => match get_key_layout() {
+            Some(layout) => vec![true; layout.key_count()],
+            None => return,
+        },
+    };
+    let (effect, effect_args) = effect_params(params.effect);
+    let layer = comms::LayerInfo {
+        effect,
+        params: effect_args,
+        key_mask,
+        blend: params.blend.unwrap_or(Blend::Replace).name().to_string(),
+        opacity: params.opacity.unwrap_or(1.0),
+    };
+    match send_data(comms::DaemonCommand::InsertLayer { index: params.index, layer }) {
+        Some(comms::DaemonResponse::InsertLayer { result, message }) => {
+            print_layer_result(result, message, "Layer added")
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn remove_layer(index: usize) {
+    match send_data(comms::DaemonCommand::RemoveLayer { index }) {
+        Some(comms::DaemonResponse::RemoveLayer { result, message }) => {
+            print_layer_result(result, message, "Layer removed")
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn move_layer(from: usize, to: usize) {
+    match send_data(comms::DaemonCommand::MoveLayer { from, to }) {
+        Some(comms::DaemonResponse::MoveLayer { result, message }) => {
+            print_layer_result(result, message, "Layer moved")
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn set_layer_mask(index: usize, keys: &str) {
+    let key_mask = match parse_key_mask(keys) {
+        Some(m) => m,
+        None => return,
+    };
+    match send_data(comms::DaemonCommand::SetLayerMask { index, key_mask }) {
+        Some(comms::DaemonResponse::SetLayerMask { result, message }) => {
+            print_layer_result(result, message, "Layer keys set")
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn update_layer(params: LayerUpdateParams) {
+    let effect_args = match params.effect {
+        Some(effect) => {
+            let (name, effect_args) = effect_params(effect);
+            // The daemon only changes parameters, not the effect of a layer
+            let layers = match get_layers() {
+                Some(l) => l,
+                None => return,
+            };
+            if let Some(layer) = layers.get(params.index) {
+                if layer.effect != name {
+                    eprintln!("Layer {} is a {} layer, not {}", params.index, layer.effect, name);
+                    return;
+                }
+            }
+            Some(effect_args)
+        },
+        None => None,
+    };
+    let cmd = comms::DaemonCommand::UpdateLayerParams {
+        index: params.index,
+        params: effect_args,
+        blend: params.blend.map(|b| b.name().to_string()),
+        opacity: params.opacity,
+    };
+    match send_data(cmd) {
+        Some(comms::DaemonResponse::UpdateLayerParams { result, message }) => {
+            print_layer_result(result, message, "Layer updated")
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+/// Parses KEY=RRGGBB, a leading # is allowed
+fn parse_key_colour(arg: &str) -> Result<(String, [u8; 3]), String> {
+    let (key, colour) = match arg.split_once('=') {
+        Some(kc) => kc,
+        None => return Err(format!("Invalid key colour {:?}, expected KEY=RRGGBB", arg)),
+    };
+    let colour = colour.trim_start_matches('#');
+    if colour.len() != 6 || !colour.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid colour {:?}, expected RRGGBB", colour));
+    }
+    let channel = |i: usize| u8::from_str_radix(&colour[i..i + 2], 16).unwrap_or(0);
+    return Ok((key.to_string(), [channel(0), channel(2), channel(4)]));
+}
+
+fn set_keys(params: KeysSetParams) {
+    let mut keys = vec![];
+    for arg in &params.keys {
+        match parse_key_colour(arg) {
+            Ok(k) => keys.push(k),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        }
+    }
+    let cmd = comms::DaemonCommand::SetKeys {
+        layer: params.layer,
+        keys,
+        timeout_ms: params.timeout.unwrap_or(0),
+    };
+    match send_data(cmd) {
+        Some(comms::DaemonResponse::SetKeys { result, message }) => {
+            if !result {
+                eprintln!("Failed: {}", message);
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn clear_keys(layer: Option<usize>) {
+    match send_data(comms::DaemonCommand::ClearKeys { layer }) {
+        Some(comms::DaemonResponse::ClearKeys { result, message }) => {
+            if !result {
+                eprintln!("Failed: {}", message);
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
 fn send_data(opt: comms::DaemonCommand) -> Option<comms::DaemonResponse> {
     match comms::bind() {
         Some(socket) => comms::send_to_daemon(opt, socket),
@@ -807,3 +1475,397 @@ fn write_gpu_mode(mode: &str) {
         None => eprintln!("Unknown daemon error!"),
     }
 }
+
+fn read_battery_rules() {
+    match send_data(comms::DaemonCommand::GetBatteryRules) {
+        Some(comms::DaemonResponse::GetBatteryRules { no_light, low_power, power_mode }) => {
+            match no_light {
+                0 => println!("Lights off: never"),
+                _ => println!("Lights off below: {}%", no_light),
+            }
+            match low_power {
+                0 => println!("Low power mode: never"),
+                _ => println!("Low power mode below: {}% ({})", low_power, power_mode_name(power_mode)),
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn power_mode_name(power_mode: u8) -> &'static str {
+    match power_mode {
+        0 => "Balanced",
+        1 => "Gaming",
+        2 => "Creator",
+        3 => "Silent",
+        4 => "Custom",
+        _ => "Unknown",
+    }
+}
+
+fn read_power_profiles() {
+    match send_data(comms::DaemonCommand::GetPowerProfiles) {
+        Some(comms::DaemonResponse::GetPowerProfiles { follow, sync_back, active, mappings }) => {
+            println!("Follow desktop profile: {}", if follow { "on" } else { "off" });
+            println!("Sync back to desktop: {}", if sync_back { "on" } else { "off" });
+            match active.as_str() {
+                "" => println!("Active desktop profile: unknown"),
+                _ => println!("Active desktop profile: {}", active),
+            }
+            for m in mappings {
+                let fan = match m.fan_rpm {
+                    0 => String::from("auto"),
+                    rpm => format!("{} RPM", rpm),
+                };
+                println!(
+                    "{}: {} (cpu {}, gpu {}), fan {}",
+                    m.profile, power_mode_name(m.power_mode), m.cpu_boost, m.gpu_boost, fan
+                );
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn write_power_profiles(follow: bool, sync_back: bool) {
+    match send_data(comms::DaemonCommand::SetPowerProfiles { follow, sync_back }) {
+        Some(comms::DaemonResponse::SetPowerProfiles { result: true }) => read_power_profiles(),
+        Some(_) => eprintln!("Failed to set power profiles"),
+        None => eprintln!("Unknown error!"),
+    }
+}
+
+fn write_profile_map(params: ProfileMapParams) {
+    if params.pwr > 4 {
+        Cli::command()
+            .error(ErrorKind::InvalidValue, "Power mode must be 0, 1, 2, 3 or 4")
+            .exit()
+    }
+    let cpu_boost = params.cpu_mode.unwrap_or(1);
+    if cpu_boost > 3 {
+        Cli::command()
+            .error(ErrorKind::InvalidValue, "CPU mode must be between 0 and 3")
+            .exit()
+    }
+    let gpu_boost = params.gpu_mode.unwrap_or(0);
+    if gpu_boost > 2 {
+        Cli::command()
+            .error(ErrorKind::InvalidValue, "GPU mode must be between 0 and 2")
+            .exit()
+    }
+    let fan_rpm = params.fan.unwrap_or(0);
+    if fan_rpm < 0 {
+        Cli::command()
+            .error(ErrorKind::InvalidValue, "Fan speed must not be negative")
+            .exit()
+    }
+
+    let mapping = comms::ProfileMappingInfo {
+        profile: params.profile.as_str().to_string(),
+        power_mode: params.pwr,
+        cpu_boost,
+        gpu_boost,
+        fan_rpm,
+    };
+    match send_data(comms::DaemonCommand::SetProfileMapping { mapping }) {
+        Some(comms::DaemonResponse::SetProfileMapping { result: true }) => read_power_profiles(),
+        Some(_) => eprintln!("Failed to set profile mapping"),
+        None => eprintln!("Unknown error!"),
+    }
+}
+
+fn read_charge_threshold() {
+    match send_data(comms::DaemonCommand::GetChargeThreshold) {
+        Some(comms::DaemonResponse::GetChargeThreshold { bho_on, threshold, effective, override_mode, override_at, batteries }) => {
+            match bho_on {
+                true => println!("Battery health optimizer: {}%", threshold),
+                false => println!("Battery health optimizer: off"),
+            }
+            println!("Charging to: {}%", effective);
+            match override_at {
+                0 => println!("Override: {}", override_mode),
+                at => println!("Override: {} {}", override_mode, service::format_time_of_day(at)),
+            }
+            if batteries.is_empty() {
+                println!("Kernel charge threshold: not available");
+            }
+            for (battery, limit) in batteries {
+                println!("Kernel charge threshold {}: {}%", battery, limit);
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn read_battery_history() {
+    let h = match send_data(comms::DaemonCommand::GetBatteryHistory) {
+        Some(comms::DaemonResponse::GetBatteryHistory { history }) => history,
+        Some(_) => {
+            eprintln!("Daemon responded with invalid data!");
+            return;
+        },
+        None => {
+            eprintln!("Unknown daemon error!");
+            return;
+        },
+    };
+    let opt = |v: Option<f64>, unit: &str| match v {
+        Some(v) => format!("{:.1} {}", v, unit),
+        None => String::from("unknown"),
+    };
+    println!("Cycle count: {}", h.cycle_count.map_or(String::from("unknown"), |c| c.to_string()));
+    println!("Design capacity: {}", opt(h.design_wh, "Wh"));
+    println!("Full capacity: {}", opt(h.full_wh, "Wh"));
+    println!("Wear: {}", opt(h.wear_percent, "%"));
+    println!("Average charge rate: {}", opt(h.avg_charge_w, "W"));
+    println!("Average discharge rate: {}", opt(h.avg_discharge_w, "W"));
+    println!("Recorded: {:.1} h over {} days", h.tracked_hours, h.capacity.len());
+    println!("At 95% or more: {:.1} h", h.high_soc_hours);
+    println!("Charge limit in effect: {:.1} h", h.limited_hours);
+    println!("Wear per month with the limit: {}", opt(h.wear_per_month_limited, "%"));
+    println!("Wear per month without the limit: {}", opt(h.wear_per_month_unlimited, "%"));
+}
+
+fn read_sensors() {
+    let readings = match send_data(comms::DaemonCommand::GetSensors) {
+        Some(comms::DaemonResponse::GetSensors { readings }) => readings,
+        Some(_) => {
+            eprintln!("Daemon responded with invalid data!");
+            return;
+        },
+        None => {
+            eprintln!("Unknown daemon error!");
+            return;
+        },
+    };
+    for r in &readings.values {
+        let unit = r.quantity.unit();
+        let precision = if unit == service::sensors::Unit::Watt { 1 } else { 0 };
+        println!("{} {}: {:.*} {} ({})", r.component.name(), r.quantity.name(),
+            precision, r.value, unit.symbol(), r.source);
+    }
+    if let Some(on_ac) = readings.on_ac {
+        println!("AC: {}", if on_ac { "online" } else { "offline" });
+    }
+    if let Some(status) = readings.battery_status {
+        println!("Battery status: {}", status);
+    }
+    for note in &readings.notes {
+        println!("Note: {}", note);
+    }
+}
+
+fn read_telemetry(minutes: u64, resolution: u64) {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let since = now.saturating_sub(minutes * 60);
+    let samples = match send_data(comms::DaemonCommand::GetTelemetry { since, resolution }) {
+        Some(comms::DaemonResponse::GetTelemetry { samples }) => samples,
+        Some(_) => {
+            eprintln!("Daemon responded with invalid data!");
+            return;
+        },
+        None => {
+            eprintln!("Unknown daemon error!");
+            return;
+        },
+    };
+    if samples.is_empty() {
+        println!("No telemetry recorded yet");
+        return;
+    }
+    let opt = |v: Option<f32>, precision: usize| match v {
+        Some(v) => format!("{:.*}", precision, v),
+        None => String::from("-"),
+    };
+    println!("{:<6} {:>6} {:>6} {:>6} {:>5} {:<8} {:>6} {:>6}", "Time", "CPU°C", "iGPU°C", "dGPU°C", "Fan", "Mode", "CPU W", "Bat W");
+    for s in samples {
+        println!("{:<6} {:>6} {:>6} {:>6} {:>5} {:<8} {:>6} {:>6}",
+            service::format_time_of_day(s.time),
+            opt(s.cpu_temp, 0),
+            opt(s.igpu_temp, 0),
+            opt(s.dgpu_temp, 0),
+            s.fan_rpm,
+            power_mode_name(s.power_mode),
+            opt(s.package_w, 1),
+            opt(s.battery_w, 1));
+    }
+}
+
+fn parse_time_of_day(time: &str) -> Option<u64> {
+    let (hour, minute) = time.split_once(':')?;
+    service::next_time_of_day(hour.parse().ok()?, minute.parse().ok()?)
+}
+
+fn write_charge_override(mode: ChargeOverrideMode, time: Option<String>) {
+    let at = match (&mode, time) {
+        (ChargeOverrideMode::FullBy, Some(time)) => match parse_time_of_day(&time) {
+            Some(at) => at,
+            None => Cli::command()
+                .error(ErrorKind::InvalidValue, "Time must be HH:MM")
+                .exit(),
+        },
+        (ChargeOverrideMode::FullBy, None) => Cli::command()
+            .error(ErrorKind::MissingRequiredArgument, "full-by needs a time, HH:MM")
+            .exit(),
+        _ => 0,
+    };
+    match send_data(comms::DaemonCommand::SetChargeOverride { mode: mode.as_str().to_string(), at }) {
+        Some(comms::DaemonResponse::SetChargeOverride { result: true }) => read_charge_threshold(),
+        Some(_) => eprintln!("Failed to set the charge override, is the battery health optimizer supported?"),
+        None => eprintln!("Unknown error!"),
+    }
+}
+
+fn get_fade() -> Option<comms::FadeInfo> {
+    match send_data(comms::DaemonCommand::GetFade) {
+        Some(comms::DaemonResponse::GetFade { fade }) => Some(fade),
+        Some(_) => {
+            eprintln!("Daemon responded with invalid data!");
+            None
+        },
+        None => {
+            eprintln!("Unknown daemon error!");
+            None
+        },
+    }
+}
+
+fn read_fade() {
+    if let Some(fade) = get_fade() {
+        println!("Fade out: {} ms", fade.fade_out_ms);
+        println!("Fade in: {} ms", fade.fade_in_ms);
+        match fade.idle_level {
+            0 => println!("Idle dim: off"),
+            level => println!("Idle dim: {}% for {} s", level, fade.idle_dim_secs),
+        }
+        println!("Brightness changes: {} ms", fade.slider_fade_ms);
+    }
+}
+
+fn write_fade(params: FadeParams) {
+    let current = match get_fade() {
+        Some(fade) => fade,
+        None => return,
+    };
+    let fade = comms::FadeInfo {
+        fade_out_ms: params.fade_out_ms,
+        fade_in_ms: params.fade_in_ms,
+        idle_level: params.idle_level.unwrap_or(current.idle_level),
+        idle_dim_secs: params.idle_dim_secs.unwrap_or(current.idle_dim_secs),
+        slider_fade_ms: params.slider_fade_ms.unwrap_or(current.slider_fade_ms),
+    };
+    if fade.fade_out_ms > 10000 || fade.fade_in_ms > 10000 || fade.slider_fade_ms > 10000 {
+        Cli::command()
+            .error(ErrorKind::InvalidValue, "Fade times must be between 0 and 10000 ms")
+            .exit()
+    }
+    if fade.idle_level > 100 {
+        Cli::command()
+            .error(ErrorKind::InvalidValue, "Idle level must be between 0 and 100")
+            .exit()
+    }
+    if fade.idle_dim_secs > 3600 {
+        Cli::command()
+            .error(ErrorKind::InvalidValue, "Idle dim time must be between 0 and 3600 s")
+            .exit()
+    }
+
+    match send_data(comms::DaemonCommand::SetFade { fade }) {
+        Some(comms::DaemonResponse::SetFade { result: true }) => read_fade(),
+        Some(_) => eprintln!("Failed to set fade timings"),
+        None => eprintln!("Unknown error!"),
+    }
+}
+
+fn write_battery_rules(no_light: u8, low_power: u8, power_mode: u8) {
+    if no_light > 100 || low_power > 100 {
+        Cli::command()
+            .error(ErrorKind::InvalidValue, "Battery percentages must be between 0 and 100")
+            .exit()
+    }
+    if power_mode > 3 {
+        Cli::command()
+            .error(ErrorKind::InvalidValue, "Power mode must be 0, 1, 2 or 3")
+            .exit()
+    }
+
+    match send_data(comms::DaemonCommand::SetBatteryRules { no_light, low_power, power_mode }) {
+        Some(comms::DaemonResponse::SetBatteryRules { result: true }) => read_battery_rules(),
+        Some(_) => eprintln!("Failed to set battery rules"),
+        None => eprintln!("Unknown error!"),
+    }
+}
+
+fn check_config() {
+    match send_data(comms::DaemonCommand::CheckConfig) {
+        Some(comms::DaemonResponse::CheckConfig { problems, missing }) => {
+            if missing {
+                println!("No configuration file, defaults are used");
+            } else if problems.is_empty() {
+                println!("Configuration OK");
+            } else {
+                for problem in &problems {
+                    eprintln!("{}", problem);
+                }
+                std::process::exit(1);
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn reload_config() {
+    match send_data(comms::DaemonCommand::ReloadConfig) {
+        Some(comms::DaemonResponse::ReloadConfig { problems }) => {
+            if problems.is_empty() {
+                println!("Configuration reloaded");
+            } else {
+                for problem in &problems {
+                    eprintln!("{}", problem);
+                }
+                std::process::exit(1);
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn export_config() {
+    match send_data(comms::DaemonCommand::ExportConfig) {
+        Some(comms::DaemonResponse::ExportConfig { bundle }) => println!("{}", bundle),
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
+
+fn import_config(file: &std::path::Path) {
+    let bundle = match std::fs::read_to_string(file) {
+        Ok(b) => b,
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", file.display(), e);
+            std::process::exit(1);
+        }
+    };
+    match send_data(comms::DaemonCommand::ImportConfig { bundle }) {
+        Some(comms::DaemonResponse::ImportConfig { result, messages }) => {
+            for message in &messages {
+                eprintln!("{}", message);
+            }
+            if result {
+                println!("Settings imported");
+            } else {
+                eprintln!("Settings not imported");
+                std::process::exit(1);
+            }
+        },
+        Some(_) => eprintln!("Daemon responded with invalid data!"),
+        None => eprintln!("Unknown daemon error!"),
+    }
+}
@@ -1,3 +1,4 @@
+use bincode::Options;
 use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
 use std::os::unix::net::{UnixListener, UnixStream};
@@ -15,6 +16,67 @@ pub struct GpuInfo {
     pub runtime_status: String,
 }
 
+/// Razer settings used for one power-profiles-daemon profile
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProfileMappingInfo {
+    pub profile: String,
+    pub power_mode: u8,
+    pub cpu_boost: u8,
+    pub gpu_boost: u8,
+    pub fan_rpm: i32,
+}
+
+/// Keyboard brightness fade timings
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FadeInfo {
+    pub fade_out_ms: u32,
+    pub fade_in_ms: u32,
+    pub idle_level: u8, // percent, 0 = no dimmed stage
+    pub idle_dim_secs: u32,
+    pub slider_fade_ms: u32,
+}
+
+/// Battery statistics recorded by the daemon
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BatteryHistoryInfo {
+    pub cycle_count: Option<u32>,
+    pub design_wh: Option<f64>,
+    pub full_wh: Option<f64>,
+    pub wear_percent: Option<f64>, // capacity lost compared to the design
+    pub avg_charge_w: Option<f64>,
+    pub avg_discharge_w: Option<f64>,
+    pub tracked_hours: f64,
+    pub high_soc_hours: f64, // at 95% or more
+    pub limited_hours: f64, // with the charge limit in effect
+    pub wear_per_month_limited: Option<f64>, // % of design capacity, days mostly limited
+    pub wear_per_month_unlimited: Option<f64>,
+    pub capacity: Vec<(u64, f64)>, // full capacity in Wh per day (unix time)
+}
+
+/// One custom lighting layer
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LayerInfo {
+    pub effect: String, // as in SetEffect, e.g. "static"
+    pub params: Vec<u8>,
+    pub key_mask: Vec<bool>, // 90 keys, row by row, see GetKeyLayout
+    pub blend: String, // "replace", "add", "multiply", "screen" or "alpha"
+    pub opacity: f32, // 0.0 to 1.0
+}
+
+/// Sensor history point recorded by the daemon
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TelemetrySample {
+    pub time: u64, // unix seconds
+    pub cpu_temp: Option<f32>, // °C
+    pub igpu_temp: Option<f32>,
+    pub dgpu_temp: Option<f32>,
+    pub fan_rpm: i32, // measured by the EC
+    pub power_mode: u8,
+    pub package_w: Option<f32>, // CPU package power
+    pub battery_w: Option<f32>, // negative while discharging
+    pub on_ac: bool,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 /// Represents data sent TO the daemon
 pub enum DaemonCommand {
@@ -42,6 +104,31 @@ pub enum DaemonCommand {
     GetGpuStatus,
     SetDgpuRuntimePM { enabled: bool },
     SetGpuMode { mode: String },
+    SetBatteryRules { no_light: u8, low_power: u8, power_mode: u8 }, // Battery percentages (0 = off)
+    GetBatteryRules,
+    CheckConfig,
+    ReloadConfig,
+    ExportConfig,
+    ImportConfig { bundle: String },
+    SetPowerProfiles { follow: bool, sync_back: bool },
+    SetProfileMapping { mapping: ProfileMappingInfo },
+    GetPowerProfiles,
+    SetFade { fade: FadeInfo },
+    GetFade,
+    SetChargeOverride { mode: String, at: u64 }, // "off", "full-once", "until-unplug" or "full-by" (at: unix time)
+    GetChargeThreshold,
+    GetBatteryHistory,
+    GetSensors,
+    GetTelemetry { since: u64, resolution: u64 }, // unix seconds, seconds per sample (0 = as recorded)
+    ListLayers,                                    // Layer 0 is at the bottom
+    InsertLayer { index: usize, layer: LayerInfo },
+    RemoveLayer { index: usize },
+    MoveLayer { from: usize, to: usize },
+    SetLayerMask { index: usize, key_mask: Vec<bool> },
+    UpdateLayerParams { index: usize, params: Option<Vec<u8>>, blend: Option<String>, opacity: Option<f32> }, // None = unchanged
+    SetKeys { layer: Option<usize>, keys: Vec<(String, [u8; 3])>, timeout_ms: u32 }, // None = script overlay, keys by index or name
+    ClearKeys { layer: Option<usize> },
+    GetKeyLayout,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -77,6 +164,38 @@ pub enum DaemonResponse {
     },
     SetDgpuRuntimePM { result: bool },
     SetGpuMode { result: bool, message: String },
+    SetBatteryRules { result: bool },
+    GetBatteryRules { no_light: u8, low_power: u8, power_mode: u8 },
+    CheckConfig { problems: Vec<String>, missing: bool }, // missing = no daemon.json, defaults are used
+    ReloadConfig { problems: Vec<String> },
+    ExportConfig { bundle: String },
+    ImportConfig { result: bool, messages: Vec<String> },
+    SetPowerProfiles { result: bool },
+    SetProfileMapping { result: bool },
+    GetPowerProfiles { follow: bool, sync_back: bool, active: String, mappings: Vec<ProfileMappingInfo> },
+    SetFade { result: bool },
+    GetFade { fade: FadeInfo },
+    SetChargeOverride { result: bool },
+    GetChargeThreshold {
+        bho_on: bool,
+        threshold: u8,
+        effective: u8, // limit the battery charges to right now, 100 = none
+        override_mode: String,
+        override_at: u64, // full-by time, unix seconds
+        batteries: Vec<(String, u8)>, // kernel charge_control_end_threshold per battery
+    },
+    GetBatteryHistory { history: BatteryHistoryInfo },
+    GetSensors { readings: service::sensors::Readings },
+    GetTelemetry { samples: Vec<TelemetrySample> },
+    ListLayers { layers: Vec<LayerInfo> },
+    InsertLayer { result: bool, message: String },
+    RemoveLayer { result: bool, message: String },
+    MoveLayer { result: bool, message: String },
+    SetLayerMask { result: bool, message: String },
+    UpdateLayerParams { result: bool, message: String },
+    SetKeys { result: bool, message: String },
+    ClearKeys { result: bool, message: String },
+    GetKeyLayout { layout: service::keyboard::Layout },
 }
 
 #[allow(dead_code)]
@@ -112,22 +231,23 @@ pub fn create() -> Option<UnixListener> {
     return None;
 }
 
+/// Largest message accepted on the socket, config bundles are the biggest
+const MAX_MESSAGE_LEN: u64 = 1 << 20;
+
+/// Same encoding as `bincode::serialize` but with a size limit, messages are
+/// read straight from the stream so they are not cut at a buffer size
+fn message_options() -> impl bincode::Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(MAX_MESSAGE_LEN)
+}
+
 #[allow(dead_code)]
 pub fn send_to_daemon(command: DaemonCommand, mut sock: UnixStream) -> Option<DaemonResponse> {
     if let Ok(encoded) = bincode::serialize(&command) {
         if sock.write_all(&encoded).is_ok() {
-            let mut buf = [0u8; 4096];
-            return match sock.read(&mut buf) {
-                Ok(readed) if readed > 0 => read_from_socked_resp(&buf[0..readed]),
-                Ok(_) => {
-                    eprintln!("No response from daemon");
-                    None
-                }
-                Err(_) => {
-                    eprintln!("Read failed!");
-                    None
-                }
-            };
+            return read_from_socked_resp(&mut sock);
         } else {
             eprintln!("Socket write failed!");
         }
@@ -137,14 +257,13 @@ pub fn send_to_daemon(command: DaemonCommand, mut sock: UnixStream) -> Option<Da
 
 /// Deserializes incomming bytes in order to return
 /// a `DaemonResponse`. None is returned if deserializing failed
-fn read_from_socked_resp(bytes: &[u8]) -> Option<DaemonResponse> {
-    match bincode::deserialize::<DaemonResponse>(bytes) {
+fn read_from_socked_resp(reader: impl Read) -> Option<DaemonResponse> {
+    match message_options().deserialize_from::<_, DaemonResponse>(reader) {
         Ok(res) => {
-            println!("RES: {:?}", res);
             return Some(res);
         }
         Err(e) => {
-            println!("RES ERROR: {}", e);
+            eprintln!("RES ERROR: {}", e);
             return None;
         }
     }
@@ -153,8 +272,8 @@ fn read_from_socked_resp(bytes: &[u8]) -> Option<DaemonResponse> {
 /// Deserializes incomming bytes in order to return
 /// a `DaemonCommand`. None is returned if deserializing failed
 #[allow(dead_code)]
-pub fn read_from_socket_req(bytes: &[u8]) -> Option<DaemonCommand> {
-    match bincode::deserialize::<DaemonCommand>(bytes) {
+pub fn read_from_socket_req(reader: impl Read) -> Option<DaemonCommand> {
+    match message_options().deserialize_from::<_, DaemonCommand>(reader) {
         Ok(res) => {
             println!("REQ: {:?}", res);
             return Some(res);
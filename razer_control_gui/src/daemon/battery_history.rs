@@ -0,0 +1,155 @@
+use serde::{Deserialize, Serialize};
+use std::{fs, io};
+use crate::config;
+use service::sensors::BatteryReading;
+
+pub const HISTORY_FILE: &str = "battery_history.json"; // in the state directory
+pub const SAMPLE_SECS: u64 = 60;
+const DAY_SECS: u64 = 24 * 60 * 60;
+const MAX_DAYS: usize = 730;
+const HIGH_SOC: f64 = 95.0; // percent counted as "high state of charge"
+const MIN_WEAR_DAYS: u64 = 14; // less history than this says nothing about wear
+
+/// Battery capacity and charge limit of one day
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DaySample {
+    pub day: u64, // days since the unix epoch
+    pub full_wh: Option<f64>,
+    pub design_wh: Option<f64>,
+    pub cycle_count: Option<u32>,
+    pub tracked_secs: u64,
+    pub limited_secs: u64, // time the charge limit was in effect
+}
+
+/// Long term battery statistics, kept in the state directory so the
+/// effect of the battery health optimizer can be judged over months
+#[derive(Serialize, Deserialize)]
+#[serde(default = "BatteryHistory::new")]
+pub struct BatteryHistory {
+    pub days: Vec<DaySample>,
+    pub tracked_secs: u64,
+    pub high_soc_secs: u64,
+    pub limited_secs: u64,
+    pub charge_secs: u64,
+    pub charge_wh: f64,
+    pub discharge_secs: u64,
+    pub discharge_wh: f64,
+    #[serde(skip)]
+    last_sample: Option<u64>, // unix time
+}
+
+impl BatteryHistory {
+    pub fn new() -> BatteryHistory {
+        return BatteryHistory {
+            days: vec![],
+            tracked_secs: 0,
+            high_soc_secs: 0,
+            limited_secs: 0,
+            charge_secs: 0,
+            charge_wh: 0.0,
+            discharge_secs: 0,
+            discharge_wh: 0.0,
+            last_sample: None,
+        };
+    }
+
+    pub fn load() -> BatteryHistory {
+        let path = match config::state_dir() {
+            Ok(dir) => dir.join(HISTORY_FILE),
+            Err(_) => return BatteryHistory::new(),
+        };
+        match fs::read_to_string(&path) {
+            Ok(str) => serde_json::from_str(&str).unwrap_or_else(|e| {
+                eprintln!("Error reading {:?}: {}, starting a new history", path, e);
+                BatteryHistory::new()
+            }),
+            Err(_) => BatteryHistory::new(),
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let j = serde_json::to_string(self)?;
+        config::write_atomic(&config::state_dir()?.join(HISTORY_FILE), j.as_bytes())
+    }
+
+    /// Adds a reading taken at `now`, `limited` is true while the charge limit is in effect
+    pub fn record(&mut self, now: u64, reading: &BatteryReading, limited: bool) {
+        // A long gap is a suspend or a stopped daemon, it is not counted
+        let secs = match self.last_sample {
+            Some(last) if now > last && now - last <= 5 * SAMPLE_SECS => now - last,
+            _ => 0,
+        };
+        self.last_sample = Some(now);
+
+        self.tracked_secs += secs;
+        if limited {
+            self.limited_secs += secs;
+        }
+        if reading.percent.map_or(false, |p| p >= HIGH_SOC) {
+            self.high_soc_secs += secs;
+        }
+        if let Some(power) = reading.power_w {
+            let wh = power.abs() * secs as f64 / 3600.0;
+            match reading.status.as_str() {
+                "Charging" => {
+                    self.charge_secs += secs;
+                    self.charge_wh += wh;
+                },
+                "Discharging" => {
+                    self.discharge_secs += secs;
+                    self.discharge_wh += wh;
+                },
+                _ => {},
+            }
+        }
+
+        let day = now / DAY_SECS;
+        if self.days.last().map_or(true, |d| d.day != day) {
+            self.days.push(DaySample {
+                day,
+                full_wh: None,
+                design_wh: None,
+                cycle_count: None,
+                tracked_secs: 0,
+                limited_secs: 0,
+            });
+            if self.days.len() > MAX_DAYS {
+                self.days.remove(0);
+            }
+        }
+        if let Some(today) = self.days.last_mut() {
+            today.full_wh = reading.full_wh.or(today.full_wh);
+            today.design_wh = reading.design_wh.or(today.design_wh);
+            today.cycle_count = reading.cycle_count.or(today.cycle_count);
+            today.tracked_secs += secs;
+            if limited {
+                today.limited_secs += secs;
+            }
+        }
+    }
+
+    pub fn latest(&self) -> Option<&DaySample> {
+        self.days.iter().rev().find(|d| d.full_wh.is_some())
+    }
+
+    /// Capacity lost per 30 days in percent of the design capacity, over the
+    /// days the charge limit was (`limited`) or was not mostly in effect
+    pub fn wear_per_month(&self, limited: bool) -> Option<f64> {
+        let design = self.latest()?.design_wh?;
+        let mut lost = 0.0;
+        let mut days = 0;
+        let samples: Vec<&DaySample> = self.days.iter().filter(|d| d.full_wh.is_some()).collect();
+        for pair in samples.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            if a.tracked_secs == 0 || (a.limited_secs * 2 >= a.tracked_secs) != limited {
+                continue;
+            }
+            lost += a.full_wh? - b.full_wh?;
+            days += b.day - a.day;
+        }
+        if days < MIN_WEAR_DAYS || design <= 0.0 {
+            return None;
+        }
+        return Some(lost / days as f64 * 30.0 / design * 100.0);
+    }
+}
@@ -1,9 +1,41 @@
 use serde::{Deserialize, Serialize};
 use std::{fs, fs::File, io, env};
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::kbd::audio;
+use crate::power_profiles;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-const SETTINGS_FILE: &str = "/.local/share/razercontrol/daemon.json";
-const EFFECTS_FILE: &str = "/.local/share/razercontrol/effects.json";
+pub const SETTINGS_FILE: &str = "daemon.json"; // in the config directory
+pub const EFFECTS_FILE: &str = "effects.json"; // in the state directory
+const DEFAULTS_FILE: &str = "/etc/razercontrol/defaults.json";
+const LEGACY_DIR: &str = ".local/share/razercontrol"; // relative to $HOME
+pub const MAX_FADE_MS: u32 = 10000;
+pub const MAX_IDLE_DIM_SECS: u32 = 3600;
+
+/// Version of the `daemon.json` schema written by this daemon.
+/// Bump it together with a new step in `migrate` whenever a field is added
+/// without a serde default, renamed or changes meaning.
+pub const CONFIG_VERSION: u32 = 1;
+
+pub const BUNDLE_FORMAT: &str = "razercontrol-bundle";
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// Settings of one laptop in a form that can be imported on another
+#[derive(Serialize, Deserialize)]
+pub struct Bundle {
+    pub format: String,
+    pub version: u32,
+    pub device: String, // model the bundle was exported from
+    pub config: serde_json::Value, // `Configuration`, migrated on import
+    pub effects: serde_json::Value, // `EffectManager::save`
+}
+
+/// What the daemon last wrote to or read from each file, so the file watcher
+/// can tell its own writes apart from edits
+static LAST_CONTENTS: Mutex<Vec<(PathBuf, Vec<u8>)>> = Mutex::new(Vec::new());
 
 #[derive(Serialize, Deserialize, Copy, Clone)]
 pub struct PowerConfig {
@@ -32,11 +64,226 @@ impl PowerConfig {
     }
 }
 
+/// Razer settings applied for one power-profiles-daemon profile
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct ProfileMapping {
+    pub power_mode: u8,
+    pub cpu_boost: u8,
+    pub gpu_boost: u8,
+    pub fan_rpm: i32, // 0 = auto
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct PowerProfilesConfig {
+    pub follow: bool, // apply the mapping when the desktop profile changes
+    pub sync_back: bool, // change the desktop profile when the power mode is set here
+    pub power_saver: ProfileMapping,
+    pub balanced: ProfileMapping,
+    pub performance: ProfileMapping,
+}
+
+impl PowerProfilesConfig {
+    pub fn new() -> PowerProfilesConfig {
+        return PowerProfilesConfig {
+            follow: false,
+            sync_back: false,
+            power_saver: ProfileMapping { power_mode: 3, cpu_boost: 1, gpu_boost: 0, fan_rpm: 0 }, // Silent
+            balanced: ProfileMapping { power_mode: 0, cpu_boost: 1, gpu_boost: 0, fan_rpm: 0 }, // Balanced
+            performance: ProfileMapping { power_mode: 1, cpu_boost: 1, gpu_boost: 0, fan_rpm: 0 }, // Gaming
+        };
+    }
+
+    pub fn mappings(&self) -> [(&'static str, ProfileMapping); 3] {
+        return [
+            (power_profiles::POWER_SAVER, self.power_saver),
+            (power_profiles::BALANCED, self.balanced),
+            (power_profiles::PERFORMANCE, self.performance),
+        ];
+    }
+
+    pub fn get_mut(&mut self, profile: &str) -> Option<&mut ProfileMapping> {
+        match profile {
+            power_profiles::POWER_SAVER => Some(&mut self.power_saver),
+            power_profiles::BALANCED => Some(&mut self.balanced),
+            power_profiles::PERFORMANCE => Some(&mut self.performance),
+            _ => None,
+        }
+    }
+
+    /// Desktop profile matching a power mode, used when syncing back
+    pub fn profile_for(&self, power_mode: u8) -> &'static str {
+        for (name, mapping) in self.mappings() {
+            if mapping.power_mode == power_mode {
+                return name;
+            }
+        }
+        match power_mode {
+            0 => power_profiles::BALANCED,
+            3 => power_profiles::POWER_SAVER,
+            _ => power_profiles::PERFORMANCE,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct FadeConfig {
+    pub fade_out_ms: u32, // lights off on idle, screensaver and sleep
+    pub fade_in_ms: u32, // lights back on
+    pub idle_level: u8, // brightness percentage to dim to when idle fires, 0 = straight off
+    pub idle_dim_secs: u32, // time spent dimmed before going off
+    pub slider_fade_ms: u32, // brightness changes from the clients
+}
+
+impl FadeConfig {
+    pub fn new() -> FadeConfig {
+        return FadeConfig {
+            fade_out_ms: 1000,
+            fade_in_ms: 300,
+            idle_level: 20,
+            idle_dim_secs: 10,
+            slider_fade_ms: 150,
+        };
+    }
+}
+
+/// Local metrics endpoints for monitoring, read when the daemon starts
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MetricsConfig {
+    pub socket: String, // Unix socket answering HTTP, empty = off
+    pub port: u16, // HTTP on 127.0.0.1, 0 = off
+    pub textfile: String, // node_exporter textfile collector file (*.prom), empty = off
+    pub textfile_secs: u32, // how often the textfile is rewritten
+}
+
+impl MetricsConfig {
+    pub fn new() -> MetricsConfig {
+        return MetricsConfig {
+            socket: String::new(),
+            port: 0,
+            textfile: String::new(),
+            textfile_secs: 15,
+        };
+    }
+}
+
+/// Key presses for the reactive effects, read when the daemon starts
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KeyInputConfig {
+    pub enabled: bool, // off unless asked for, the daemon then sees every key press
+    pub device: String, // evdev node like /dev/input/event3, empty = the Razer keyboard
+}
+
+impl KeyInputConfig {
+    pub fn new() -> KeyInputConfig {
+        return KeyInputConfig {
+            enabled: false,
+            device: String::new(),
+        };
+    }
+}
+
+/// Audio for the spectrum effect, captured only while a layer shows it
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AudioConfig {
+    pub input: String, // raw s16le mono PCM FIFO or file, empty = what the laptop plays
+    pub rate: u32, // sample rate in Hz
+}
+
+impl AudioConfig {
+    pub fn new() -> AudioConfig {
+        return AudioConfig {
+            input: String::new(),
+            rate: 44100,
+        };
+    }
+}
+
+/// Temporary exception from the battery health optimizer threshold
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug, Default)]
+pub enum ChargeOverride {
+    #[default]
+    Off,
+    FullOnce, // charge to 100% once, then return to the threshold
+    UntilUnplug, // no threshold until AC is unplugged
+    FullBy { at: u64 }, // full at this unix time, back to the threshold at the next unplug
+}
+
+/// How long before a full-by time charging past the threshold starts
+pub const FULL_BY_LEAD_SECS: u64 = 3 * 60 * 60;
+/// The threshold returns this long after a full-by time even without an unplug
+pub const FULL_BY_KEEP_SECS: u64 = 12 * 60 * 60;
+
+impl ChargeOverride {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChargeOverride::Off => "off",
+            ChargeOverride::FullOnce => "full-once",
+            ChargeOverride::UntilUnplug => "until-unplug",
+            ChargeOverride::FullBy { .. } => "full-by",
+        }
+    }
+
+    /// `at` is only used by full-by
+    pub fn from_name(name: &str, at: u64) -> Option<ChargeOverride> {
+        match name {
+            "off" => Some(ChargeOverride::Off),
+            "full-once" => Some(ChargeOverride::FullOnce),
+            "until-unplug" => Some(ChargeOverride::UntilUnplug),
+            "full-by" if at != 0 => Some(ChargeOverride::FullBy { at }),
+            _ => None,
+        }
+    }
+
+    pub fn at(&self) -> u64 {
+        match self {
+            ChargeOverride::FullBy { at } => *at,
+            _ => 0,
+        }
+    }
+
+    /// True while the threshold is lifted, `now` is in unix seconds
+    pub fn lifted(&self, now: u64) -> bool {
+        match self {
+            ChargeOverride::Off => false,
+            ChargeOverride::FullOnce | ChargeOverride::UntilUnplug => true,
+            ChargeOverride::FullBy { at } => now + FULL_BY_LEAD_SECS >= *at,
+        }
+    }
+
+    /// True once unplugging should bring the threshold back
+    pub fn ends_on_unplug(&self, now: u64) -> bool {
+        match self {
+            ChargeOverride::UntilUnplug => true,
+            ChargeOverride::FullBy { at } => now >= *at,
+            _ => false,
+        }
+    }
+
+    pub fn expired(&self, now: u64) -> bool {
+        match self {
+            ChargeOverride::FullBy { at } => now >= at + FULL_BY_KEEP_SECS,
+            _ => false,
+        }
+    }
+}
+
+pub fn unix_now() -> u64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Configuration {
+    #[serde(default)]
+    pub version: u32, // 0 = written before the schema was versioned
+    #[serde(skip)]
+    changed_at: Option<Instant>, // set while there are changes not yet on disk
     pub power: [PowerConfig; 2],
     pub sync: bool, // sync light settings between ac and battery
     pub no_light: f64, // no light bellow this percentage of battery
+    #[serde(default)]
+    pub low_power: f64, // switch to low_power_mode below this percentage of battery
+    #[serde(default = "default_low_power_mode")]
+    pub low_power_mode: u8,
     pub standard_effect: u8,
     pub standard_effect_params: Vec<u8>,
     #[serde(default)]
@@ -44,63 +291,497 @@ pub struct Configuration {
     #[serde(default = "default_bho_threshold")]
     pub bho_threshold: u8,
     #[serde(default)]
+    pub charge_override: ChargeOverride,
+    #[serde(default)]
     pub gui_effect: u8, // GUI custom effect index (0=Static, 1=StaticGradient, 2=WaveGradient, 3=Breathing)
     #[serde(default)]
     pub gui_effect_params: Vec<u8>, // GUI effect color params (RGB bytes)
+    #[serde(default = "PowerProfilesConfig::new")]
+    pub power_profiles: PowerProfilesConfig,
+    #[serde(default = "FadeConfig::new")]
+    pub fade: FadeConfig,
+    #[serde(default)]
+    pub persist_telemetry: bool, // keep the sensor history across daemon restarts
+    #[serde(default = "MetricsConfig::new")]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub key_layout: String, // "ansi", "iso" or "jis", empty = from the device database
+    #[serde(default = "KeyInputConfig::new")]
+    pub key_input: KeyInputConfig,
+    #[serde(default = "AudioConfig::new")]
+    pub audio: AudioConfig,
 }
 
 fn default_bho_threshold() -> u8 { 80 }
+fn default_low_power_mode() -> u8 { 3 } // Silent
 
 impl Configuration {
     pub fn new() -> Configuration {
         return Configuration {
+            version: CONFIG_VERSION,
+            changed_at: None,
             power: [PowerConfig::new(), PowerConfig::new()],
             sync: false,
             no_light: 0.0,
+            low_power: 0.0,
+            low_power_mode: 3,
             standard_effect: 0, // off
             standard_effect_params: vec![],
             bho_on: false,
             bho_threshold: 80,
+            charge_override: ChargeOverride::Off,
             gui_effect: 0,
             gui_effect_params: vec![],
+            power_profiles: PowerProfilesConfig::new(),
+            fade: FadeConfig::new(),
+            persist_telemetry: false,
+            metrics: MetricsConfig::new(),
+            key_layout: String::new(),
+            key_input: KeyInputConfig::new(),
+            audio: AudioConfig::new(),
         };
     }
 
+    /// Configuration for a first run, `/etc/razercontrol/defaults.json`
+    /// overrides the built in defaults when the administrator provides one
+    pub fn from_defaults() -> Configuration {
+        let str = match fs::read_to_string(DEFAULTS_FILE) {
+            Ok(s) => s,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    eprintln!("Error reading {}: {}", DEFAULTS_FILE, e);
+                }
+                return Configuration::new();
+            }
+        };
+        let overrides: serde_json::Value = match serde_json::from_str(str.as_str()) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Error parsing {}: {}", DEFAULTS_FILE, e);
+                return Configuration::new();
+            }
+        };
+        let mut json = match serde_json::to_value(Configuration::new()) {
+            Ok(v) => v,
+            Err(_) => return Configuration::new(),
+        };
+        merge_json(&mut json, overrides);
+        match Configuration::from_json_str(json.to_string().as_str()) {
+            Ok(mut c) => {
+                println!("Using defaults from {}", DEFAULTS_FILE);
+                // Write them so the user file shows what is in effect
+                c.mark_dirty();
+                c
+            },
+            Err(e) => {
+                eprintln!("Invalid defaults in {}: {}", DEFAULTS_FILE, e);
+                Configuration::new()
+            }
+        }
+    }
+
     pub fn write_to_file(&mut self) -> io::Result<()> {
-        ensure_config_dir()?;
         let j: String = serde_json::to_string_pretty(&self)?;
-        File::create(get_home_directory() + SETTINGS_FILE)?.write_all(j.as_bytes())?;
+        write_atomic(&config_dir()?.join(SETTINGS_FILE), j.as_bytes())?;
+        self.changed_at = None;
         Ok(())
     }
 
+    /// Records a change, it is written by the next `flush`
+    pub fn mark_dirty(&mut self) {
+        self.changed_at = Some(Instant::now());
+    }
+
+    /// Writes pending changes once none happened for `settle`,
+    /// so dragging a slider results in a single write
+    pub fn flush(&mut self, settle: Duration) -> io::Result<()> {
+        match self.changed_at {
+            Some(t) if t.elapsed() >= settle => self.write_to_file(),
+            _ => Ok(()),
+        }
+    }
+
     pub fn read_from_config() -> io::Result<Configuration> {
-        let str = fs::read_to_string(get_home_directory() + SETTINGS_FILE)?;
-        let res: Configuration = serde_json::from_str(str.as_str())?;
+        let path = config_dir()?.join(SETTINGS_FILE);
+        let str = fs::read_to_string(&path)?;
+        let res = Configuration::from_json_str(str.as_str())?;
+        remember_contents(&path, str.as_bytes());
         Ok(res)
     }
 
+    /// Parses a configuration, migrating it from older schema versions first
+    pub fn from_json_str(str: &str) -> io::Result<Configuration> {
+        let mut json: serde_json::Value = serde_json::from_str(str)?;
+        migrate(&mut json)?;
+        let res: Configuration = serde_json::from_value(json)?;
+        Ok(res)
+    }
+
+    /// Moves an unreadable `daemon.json` out of the way so it is not
+    /// overwritten by the defaults on the next write.
+    pub fn backup_config_file() -> io::Result<String> {
+        let path = config_dir()?.join(SETTINGS_FILE);
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let backup = format!("{}.{}.bak", path.display(), secs);
+        fs::rename(&path, &backup)?;
+        Ok(backup)
+    }
+
+    /// Reads `daemon.json` from disk and reports everything that is wrong with it,
+    /// None when there is no file and the defaults are used
+    pub fn check_file() -> Option<Vec<String>> {
+        let path = match config_dir() {
+            Ok(dir) => dir.join(SETTINGS_FILE),
+            Err(e) => return Some(vec![format!("Cannot locate configuration file: {}", e)]),
+        };
+        let str = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => return Some(vec![format!("Cannot read configuration file: {}", e)]),
+        };
+        match Configuration::from_json_str(str.as_str()) {
+            Ok(c) => Some(c.validate()),
+            Err(e) => Some(vec![format!("Cannot parse configuration file: {}", e)]),
+        }
+    }
+
+    /// Returns a description of every value that is out of range
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        for (ac, power) in self.power.iter().enumerate() {
+            let state = if ac == 1 { "ac" } else { "bat" };
+            if power.power_mode > 4 {
+                problems.push(format!("{}: power mode {} is not between 0 and 4", state, power.power_mode));
+            }
+            if power.cpu_boost > 3 {
+                problems.push(format!("{}: cpu boost {} is not between 0 and 3", state, power.cpu_boost));
+            }
+            if power.gpu_boost > 2 {
+                problems.push(format!("{}: gpu boost {} is not between 0 and 2", state, power.gpu_boost));
+            }
+            if power.fan_rpm < 0 {
+                problems.push(format!("{}: fan rpm {} is negative", state, power.fan_rpm));
+            }
+            if power.logo_state > 2 {
+                problems.push(format!("{}: logo state {} is not between 0 and 2", state, power.logo_state));
+            }
+        }
+        if !(0.0..=100.0).contains(&self.no_light) {
+            problems.push(format!("no_light {} is not a battery percentage", self.no_light));
+        }
+        if !(0.0..=100.0).contains(&self.low_power) {
+            problems.push(format!("low_power {} is not a battery percentage", self.low_power));
+        }
+        if self.low_power_mode > 3 {
+            problems.push(format!("low power mode {} is not between 0 and 3", self.low_power_mode));
+        }
+        for (name, mapping) in self.power_profiles.mappings() {
+            if mapping.power_mode > 4 || mapping.cpu_boost > 3 || mapping.gpu_boost > 2 || mapping.fan_rpm < 0 {
+                problems.push(format!("{}: profile mapping is out of range", name));
+            }
+        }
+        let fade = &self.fade;
+        if fade.fade_out_ms > MAX_FADE_MS || fade.fade_in_ms > MAX_FADE_MS || fade.slider_fade_ms > MAX_FADE_MS {
+            problems.push(format!("fade durations must not exceed {} ms", MAX_FADE_MS));
+        }
+        if fade.idle_level > 100 {
+            problems.push(format!("fade idle level {} is not a percentage", fade.idle_level));
+        }
+        if fade.idle_dim_secs > MAX_IDLE_DIM_SECS {
+            problems.push(format!("fade idle dim time {} s is longer than {} s", fade.idle_dim_secs, MAX_IDLE_DIM_SECS));
+        }
+        let metrics = &self.metrics;
+        if !metrics.textfile.is_empty() && !metrics.textfile.ends_with(".prom") {
+            problems.push(format!("metrics textfile {} must end in .prom for node_exporter", metrics.textfile));
+        }
+        if !metrics.textfile.is_empty() && metrics.textfile_secs == 0 {
+            problems.push(String::from("metrics textfile interval must be at least 1 s"));
+        }
+        if !self.key_layout.is_empty() && !service::keyboard::LAYOUTS.contains(&self.key_layout.as_str()) {
+            problems.push(format!("key layout {} is not one of {}", self.key_layout, service::keyboard::LAYOUTS.join(", ")));
+        }
+        if !self.key_input.device.is_empty() && !self.key_input.device.starts_with("/dev/input/") {
+            problems.push(format!("key input device {} is not under /dev/input", self.key_input.device));
+        }
+        if !(audio::MIN_RATE..=audio::MAX_RATE).contains(&self.audio.rate) {
+            problems.push(format!("audio rate {} Hz is not between {} and {} Hz", self.audio.rate, audio::MIN_RATE, audio::MAX_RATE));
+        }
+        if self.bho_threshold < 50 || self.bho_threshold > 80 || self.bho_threshold % 5 != 0 {
+            problems.push(format!("bho threshold {} is not a multiple of 5 between 50 and 80", self.bho_threshold));
+        }
+        return problems;
+    }
+
     pub fn write_effects_save(json: serde_json::Value) -> io::Result<()> {
-        ensure_config_dir()?;
         let j: String = serde_json::to_string_pretty(&json)?;
-        File::create(get_home_directory() + EFFECTS_FILE)?.write_all(j.as_bytes())?;
+        write_atomic(&state_dir()?.join(EFFECTS_FILE), j.as_bytes())?;
         Ok(())
     }
 
     pub fn read_effects_file() -> io::Result<serde_json::Value> {
-        let str = fs::read_to_string(get_home_directory() + EFFECTS_FILE)?;
+        let path = state_dir()?.join(EFFECTS_FILE);
+        let str = fs::read_to_string(&path)?;
         let res: serde_json::Value = serde_json::from_str(str.as_str())?;
+        remember_contents(&path, str.as_bytes());
         Ok(res)
     }
 }
 
-fn get_home_directory() -> String {
-    env::var("HOME").unwrap_or_else(|_| {
-        eprintln!("WARNING: HOME environment variable not set, falling back to /tmp");
-        "/tmp".to_string()
-    })
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0); // temporary files of write_atomic
+
+/// Replaces `path` without ever leaving a truncated file behind: the data is
+/// written and synced to a temporary file which is then renamed over it
+pub fn write_atomic(path: &PathBuf, data: &[u8]) -> io::Result<()> {
+    // A name of its own, the persistence task, the shutdown flush and an
+    // import can write the same file at once
+    let name = path.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+    let n = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let tmp = path.with_file_name(format!(".{}.{}.{}.tmp", name, std::process::id(), n));
+    let written = File::create(&tmp).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()
+    });
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    remember_contents(path, data);
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    // Make the rename itself durable
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+pub fn remember_contents(path: &Path, data: &[u8]) {
+    if let Ok(mut contents) = LAST_CONTENTS.lock() {
+        contents.retain(|(p, _)| p != path);
+        contents.push((path.to_path_buf(), data.to_vec()));
+    }
 }
 
-fn ensure_config_dir() -> io::Result<()> {
-    let dir = get_home_directory() + "/.local/share/razercontrol";
-    fs::create_dir_all(dir)
+/// True if `data` is what the daemon itself last wrote to or read from `path`
+pub fn is_known_contents(path: &Path, data: &[u8]) -> bool {
+    match LAST_CONTENTS.lock() {
+        Ok(contents) => contents.iter().any(|(p, d)| p == path && d == data),
+        Err(_) => false,
+    }
+}
+
+/// Upgrades a parsed `daemon.json` to `CONFIG_VERSION`, one version at a time
+fn migrate(json: &mut serde_json::Value) -> io::Result<()> {
+    let obj = json.as_object_mut().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "configuration is not a JSON object")
+    })?;
+    let mut version = obj.get("version").and_then(|v| v.as_u64()).unwrap_or(0) as u32;
+    if version > CONFIG_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("configuration version {} is newer than {}", version, CONFIG_VERSION),
+        ));
+    }
+    while version < CONFIG_VERSION {
+        if version == 0 {
+            migrate_v0(obj);
+        }
+        version += 1;
+        println!("configuration migrated to version {}", version);
+    }
+    obj.insert("version".to_string(), serde_json::json!(version));
+    Ok(())
+}
+
+/// Version 0 files come from releases that did not write every field,
+/// fill the missing ones with their defaults
+fn migrate_v0(obj: &mut serde_json::Map<String, serde_json::Value>) {
+    let defaults = Configuration::new();
+    obj.entry("sync").or_insert(serde_json::json!(defaults.sync));
+    obj.entry("no_light").or_insert(serde_json::json!(defaults.no_light));
+    obj.entry("standard_effect").or_insert(serde_json::json!(defaults.standard_effect));
+    obj.entry("standard_effect_params").or_insert(serde_json::json!(defaults.standard_effect_params));
+    if let Some(power) = obj.get_mut("power").and_then(|p| p.as_array_mut()) {
+        let default_power = serde_json::json!(PowerConfig::new());
+        for entry in power.iter_mut().filter_map(|p| p.as_object_mut()) {
+            if let Some(default_power) = default_power.as_object() {
+                for (key, value) in default_power {
+                    entry.entry(key.clone()).or_insert(value.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Overlays `overrides` on `base`, objects are merged key by key and arrays
+/// element by element so a partial `power` entry keeps the other defaults
+fn merge_json(base: &mut serde_json::Value, overrides: serde_json::Value) {
+    match (base, overrides) {
+        (serde_json::Value::Object(b), serde_json::Value::Object(o)) => {
+            for (key, value) in o {
+                match b.get_mut(&key) {
+                    Some(existing) => merge_json(existing, value),
+                    None => { b.insert(key, value); },
+                }
+            }
+        },
+        (serde_json::Value::Array(b), serde_json::Value::Array(o)) => {
+            for (idx, value) in o.into_iter().enumerate() {
+                match b.get_mut(idx) {
+                    Some(existing) => merge_json(existing, value),
+                    None => b.push(value),
+                }
+            }
+        },
+        (b, o) => *b = o,
+    }
+}
+
+/// Moves `daemon.json` and `effects.json` from `~/.local/share/razercontrol`,
+/// where older releases kept both, to the XDG config and state directories
+pub fn migrate_legacy_files() {
+    let legacy = match env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(LEGACY_DIR),
+        None => return,
+    };
+    if !legacy.is_dir() {
+        return;
+    }
+    for (name, dir) in [(SETTINGS_FILE, config_dir()), (EFFECTS_FILE, state_dir())] {
+        let old = legacy.join(name);
+        let new = match dir {
+            Ok(d) => d.join(name),
+            Err(e) => {
+                eprintln!("Error migrating {}: {}", name, e);
+                continue;
+            }
+        };
+        if !old.exists() || new.exists() {
+            continue;
+        }
+        // rename fails across file systems, copy in that case
+        let res = fs::rename(&old, &new)
+            .or_else(|_| fs::copy(&old, &new).and_then(|_| fs::remove_file(&old)));
+        match res {
+            Ok(_) => println!("Moved {} to {}", old.display(), new.display()),
+            Err(e) => eprintln!("Error moving {} to {}: {}", old.display(), new.display(), e),
+        }
+    }
+    // Only succeeds once the directory is empty
+    let _ = fs::remove_dir(&legacy);
+}
+
+/// `$XDG_CONFIG_HOME/razercontrol`, created if missing
+pub fn config_dir() -> io::Result<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// `$XDG_STATE_HOME/razercontrol`, created if missing
+pub fn state_dir() -> io::Result<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+fn xdg_dir(var: &str, fallback: &str) -> io::Result<PathBuf> {
+    // The spec says relative paths are invalid and must be ignored
+    let base = match env::var_os(var).map(PathBuf::from).filter(|p| p.is_absolute()) {
+        Some(p) => p,
+        None => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(fallback),
+            None => return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("neither {} nor HOME is set", var),
+            )),
+        },
+    };
+    let dir = base.join("razercontrol");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn concurrent_writes_replace_the_file_whole() {
+        let dir = std::env::temp_dir().join(format!("razercontrol-write-atomic-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("daemon.json");
+        let writers: Vec<_> = (0..8u8).map(|n| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                for _ in 0..20 {
+                    write_atomic(&path, &vec![b'0' + n; 4096]).unwrap();
+                }
+            })
+        }).collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len(), 4096);
+        assert!(data.iter().all(|b| *b == data[0]));
+        let names: Vec<_> = fs::read_dir(&dir).unwrap().flatten().map(|e| e.file_name()).collect();
+        assert_eq!(names, vec![std::ffi::OsString::from("daemon.json")], "temporary files left");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn migrate_fills_a_version_0_file() {
+        let mut config = json!({ "power": [{ "power_mode": 3 }, {}] });
+        migrate(&mut config).unwrap();
+        assert_eq!(config["version"], json!(CONFIG_VERSION));
+        assert_eq!(config["sync"], json!(false));
+        assert_eq!(config["standard_effect_params"], json!([]));
+        assert_eq!(config["power"][0]["power_mode"], json!(3));
+        assert_eq!(config["power"][0]["idle"], json!(PowerConfig::new().idle));
+        assert_eq!(config["power"][1]["brightness"], json!(PowerConfig::new().brightness));
+        assert!(serde_json::from_value::<Configuration>(config).is_ok());
+    }
+
+    #[test]
+    fn migrate_keeps_a_current_file() {
+        let mut config = json!({ "version": CONFIG_VERSION, "sync": true });
+        migrate(&mut config).unwrap();
+        assert_eq!(config, json!({ "version": CONFIG_VERSION, "sync": true }));
+    }
+
+    #[test]
+    fn migrate_refuses_newer_and_invalid_files() {
+        assert!(migrate(&mut json!({ "version": CONFIG_VERSION + 1 })).is_err());
+        assert!(migrate(&mut json!([1, 2])).is_err());
+    }
+
+    #[test]
+    fn merge_json_overlays_objects_and_arrays() {
+        let mut base = json!({
+            "sync": false,
+            "power": [{ "power_mode": 0, "brightness": 128 }, { "power_mode": 1, "brightness": 255 }],
+            "fade": { "on_ms": 300, "off_ms": 300 },
+        });
+        merge_json(&mut base, json!({
+            "sync": true,
+            "power": [{ "brightness": 64 }],
+            "fade": { "off_ms": 0 },
+            "key_layout": "iso",
+        }));
+        assert_eq!(base, json!({
+            "sync": true,
+            "power": [{ "power_mode": 0, "brightness": 64 }, { "power_mode": 1, "brightness": 255 }],
+            "fade": { "on_ms": 300, "off_ms": 0 },
+            "key_layout": "iso",
+        }));
+    }
+
+    #[test]
+    fn merge_json_appends_extra_array_entries() {
+        let mut base = json!([1]);
+        merge_json(&mut base, json!([2, 3]));
+        assert_eq!(base, json!([2, 3]));
+    }
 }
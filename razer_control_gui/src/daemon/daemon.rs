@@ -1,6 +1,8 @@
-use std::io::{Read, Write};
+use std::fs;
+use std::io::Write;
 use std::os::unix::net::UnixStream;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
 use std::time;
 
@@ -22,6 +24,14 @@ mod dbus_mutter_displayconfig;
 mod dbus_mutter_idlemonitor;
 mod screensaver;
 mod login1;
+mod file_watch;
+mod power_profiles;
+mod power_supply;
+mod kbd_backlight;
+mod battery_history;
+mod telemetry;
+mod metrics;
+mod key_input;
 
 use crate::kbd::Effect;
 
@@ -39,8 +49,15 @@ lazy_static! {
             Err(_) => Mutex::new(device::DeviceManager::new()),
         }
     };
+    static ref BATTERY_HISTORY: Mutex<battery_history::BatteryHistory> = Mutex::new(battery_history::BatteryHistory::load());
+    static ref SENSORS: Mutex<service::sensors::Sensors> = Mutex::new(service::sensors::Sensors::new());
+    static ref TELEMETRY: Mutex<telemetry::Telemetry> = Mutex::new(telemetry::Telemetry::new());
 }
 
+// Idle backend, set by the screensaver monitor: the Mutter idle monitor when
+// GNOME provides it, the logind idle hint otherwise
+static MUTTER_IDLE: AtomicBool = AtomicBool::new(false);
+
 // Main function for daemon
 fn main() {
     setup_panic_hook();
@@ -68,36 +85,52 @@ fn main() {
                 std::process::exit(1);
             }
         };
-        let proxy_ac = dbus_system.with_proxy("org.freedesktop.UPower", "/org/freedesktop/UPower/devices/line_power_AC0", time::Duration::from_millis(5000));
-        use battery::OrgFreedesktopUPowerDevice;
-        if let Ok(online) = proxy_ac.online() {
-            println!("Online AC0: {:?}", online);
-            d.set_ac_state(online);
-            d.restore_standard_effect();
-            d.restore_bho();
-            if let Ok(json) = config::Configuration::read_effects_file() {
-                if let Ok(mut mgr) = EFFECT_MANAGER.lock() {
-                    mgr.load_from_save(json);
-                }
-            } else {
-                println!("No effects save, creating a new one");
-                // No effects found, start with a green static layer, just like synapse
-                if let Ok(mut mgr) = EFFECT_MANAGER.lock() {
-                    mgr.push_effect(
-                        kbd::effects::Static::new(vec![0, 255, 0]),
-                        [true; 90]
-                    );
-                }
+        let supplies = power_supply::PowerSupplies::discover(&dbus_system);
+        let online = match supplies.ac_online(&dbus_system) {
+            Some(online) => online,
+            None => {
+                println!("error getting current power state, assuming AC");
+                true
+            }
+        };
+        println!("AC online: {:?}", online);
+        d.power_supplies = Some(supplies);
+        d.set_ac_state(online);
+        d.restore_standard_effect();
+        d.restore_bho();
+        apply_key_layout(&mut d);
+        if let Ok(json) = config::Configuration::read_effects_file() {
+            if let Ok(mut mgr) = EFFECT_MANAGER.lock() {
+                mgr.load_from_save(json);
             }
         } else {
-            println!("error getting current power state");
-            std::process::exit(1);
+            println!("No effects save, creating a new one");
+            // No effects found, start with a green static layer, just like synapse
+            if let Ok(mut mgr) = EFFECT_MANAGER.lock() {
+                let mask = vec![true; mgr.get_layout().key_count()];
+                mgr.push_effect(
+                    kbd::effects::Static::new(vec![0, 255, 0]),
+                    mask
+                );
+            }
         }
     }
 
     start_keyboard_animator_task();
+    start_fade_task();
     start_screensaver_monitor_task();
     start_battery_monitor_task();
+    start_logind_monitor_task();
+    start_kbd_backlight_task();
+    start_battery_history_task();
+    start_telemetry_task();
+    start_metrics_task();
+    start_key_input_task();
+    start_system_state_task();
+    start_audio_task();
+    start_power_profiles_task();
+    start_persistence_task();
+    start_config_watch_task();
     let clean_thread = start_shutdown_task();
 
     if let Some(listener) = comms::create() {
@@ -139,17 +172,34 @@ pub fn start_keyboard_animator_task() -> JoinHandle<()> {
     thread::spawn(|| {
         loop {
             if let Ok(mut dev) = DEV_MANAGER.lock() {
+                let mut restore = false;
                 if let Some(laptop) = dev.get_device() {
                     if let Ok(mut mgr) = EFFECT_MANAGER.lock() {
-                        mgr.update(laptop);
+                        restore = mgr.update(laptop);
                     }
                 }
+                // Script keys painted over a standard effect expired
+                if restore {
+                    dev.restore_standard_effect();
+                }
             }
             thread::sleep(std::time::Duration::from_millis(kbd::ANIMATION_SLEEP_MS));
         }
     })
 }
 
+/// Steps keyboard brightness fades, faster than the animator for smooth fades
+fn start_fade_task() -> JoinHandle<()> {
+    thread::spawn(|| {
+        loop {
+            if let Ok(mut d) = DEV_MANAGER.lock() {
+                d.step_fade();
+            }
+            thread::sleep(time::Duration::from_millis(25));
+        }
+    })
+}
+
 fn start_screensaver_monitor_task() -> JoinHandle<()> {
     thread::spawn(move || {
         let dbus_session = match Connection::new_session() {
@@ -182,7 +232,7 @@ fn start_screensaver_monitor_task() -> JoinHandle<()> {
             if let Ok(mut d) = DEV_MANAGER.lock() {
                 if d.idle_id == h.id {
                     println!("idle trigger {:?}", h.id);
-                    d.light_off();
+                    d.idle_light_off();
                 } else if d.active_id == h.id {
                     println!("active trigger {:?}", h.id);
                     d.restore_light();
@@ -203,8 +253,27 @@ fn start_screensaver_monitor_task() -> JoinHandle<()> {
             true
         });
 
+        let mut last_check: Option<time::Instant> = None;
         loop { 
+            // gnome-shell may come up after the daemon, or go away
+            if last_check.map_or(true, |t| t.elapsed() >= time::Duration::from_secs(10)) {
+                let first = last_check.is_none();
+                last_check = Some(time::Instant::now());
+                let mutter = has_mutter_idle_monitor(&dbus_session);
+                if MUTTER_IDLE.swap(mutter, Ordering::Relaxed) != mutter || first {
+                    println!("idle backend: {}", if mutter { "Mutter" } else { "logind" });
+                    if mutter {
+                        // A new Mutter does not know our watches
+                        if let Ok(mut d) = DEV_MANAGER.lock() {
+                            d.change_idle = true;
+                        }
+                    }
+                }
+            }
             if let Ok(res) = dbus_session.process(time::Duration::from_millis(1000)) {
+                if !MUTTER_IDLE.load(Ordering::Relaxed) {
+                    continue;
+                }
                 if res {
                     if let Ok(mut d) = DEV_MANAGER.lock() {
                         d.add_active_watch(&proxy_idle);
@@ -219,39 +288,137 @@ fn start_screensaver_monitor_task() -> JoinHandle<()> {
     })
 }
 
-fn start_battery_monitor_task() -> JoinHandle<()> {
+fn has_mutter_idle_monitor(conn: &Connection) -> bool {
+    let proxy = conn.with_proxy("org.freedesktop.DBus", "/org/freedesktop/DBus", time::Duration::from_millis(1000));
+    let res: Result<(bool,), dbus::Error> = proxy.method_call("org.freedesktop.DBus", "NameHasOwner", ("org.gnome.Mutter.IdleMonitor",));
+    return res.map_or(false, |r| r.0);
+}
+
+fn monotonic_usec() -> u64 {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+    }
+    return ts.tv_sec as u64 * 1_000_000 + ts.tv_nsec as u64 / 1000;
+}
+
+/// Lid state and, on desktops without the Mutter idle monitor (KDE, Sway,
+/// Hyprland, ...), the session idle hint from logind. LidClosed has no change
+/// signal so both are polled.
+fn start_logind_monitor_task() -> JoinHandle<()> {
     thread::spawn(move || {
         let dbus_system = match Connection::new_system() {
             Ok(conn) => conn,
             Err(e) => {
-                eprintln!("Battery monitor: D-Bus system unavailable ({}), skipping", e);
+                eprintln!("Logind monitor: D-Bus system unavailable ({}), skipping", e);
                 return;
             }
         };
-        let proxy_ac = dbus_system.with_proxy("org.freedesktop.UPower", "/org/freedesktop/UPower/devices/line_power_AC0", time::Duration::from_millis(5000));
-        let _id = proxy_ac.match_signal(|h: battery::OrgFreedesktopDBusPropertiesPropertiesChanged, _: &Connection, _: &Message| {
-            let online: Option<&bool> = arg::prop_cast(&h.changed_properties, "Online");
-            if let Some(online) = online {
-                println!("Online AC0: {:?}", online);
+        let proxy = dbus_system.with_proxy("org.freedesktop.login1", "/org/freedesktop/login1", time::Duration::from_millis(1000));
+        use login1::OrgFreedesktopLogin1ManagerState;
+        loop {
+            if let Ok(closed) = proxy.lid_closed() {
                 if let Ok(mut d) = DEV_MANAGER.lock() {
-                    d.set_ac_state(*online);
+                    d.set_lid_closed(closed);
                 }
             }
-            true
-        });
+            if !MUTTER_IDLE.load(Ordering::Relaxed) {
+                if let Ok(idle) = proxy.idle_hint() {
+                    let idle_for = if idle {
+                        proxy.idle_since_hint_monotonic().ok()
+                            .map(|since| time::Duration::from_micros(monotonic_usec().saturating_sub(since)))
+                    } else {
+                        None
+                    };
+                    if let Ok(mut d) = DEV_MANAGER.lock() {
+                        d.logind_idle(idle_for);
+                    }
+                }
+            }
+            thread::sleep(time::Duration::from_secs(1));
+        }
+    })
+}
 
-        let proxy_battery = dbus_system.with_proxy("org.freedesktop.UPower", "/org/freedesktop/UPower/devices/battery_BAT0", time::Duration::from_millis(5000));
-        // use battery::OrgFreedesktopUPowerDevice;
-        // if let Ok(perc) = proxy_battery.percentage() {
-            // println!("battery percentage: {:.1}", perc);
-        // }
-        let _id = proxy_battery.match_signal(|h: battery::OrgFreedesktopDBusPropertiesPropertiesChanged, _: &Connection, _: &Message| {
-            let perc: Option<&f64> = arg::prop_cast(&h.changed_properties, "Percentage");
-            if let Some(perc) = perc {
-                println!("battery percentage: {:.1}", perc);
+fn start_battery_monitor_task() -> JoinHandle<()> {
+    thread::spawn(move || {
+        let dbus_system = match Connection::new_system() {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("Battery monitor: D-Bus system unavailable ({}), skipping", e);
+                return;
+            }
+        };
+        let supplies = match DEV_MANAGER.lock().ok().and_then(|d| d.power_supplies.clone()) {
+            Some(s) => s,
+            None => power_supply::PowerSupplies::discover(&dbus_system),
+        };
+
+        let mut last_online = supplies.ac_online(&dbus_system);
+        let mut last_perc = supplies.battery_percentage(&dbus_system);
+        if let Some(perc) = last_perc {
+            println!("battery percentage: {:.1}", perc);
+            if let Ok(mut d) = DEV_MANAGER.lock() {
+                d.set_battery_percentage(perc);
+            }
+        }
+        let mut last_done = supplies.battery_charge_done(&dbus_system);
+        if let Some(done) = last_done {
+            if let Ok(mut d) = DEV_MANAGER.lock() {
+                d.set_battery_charge_done(done);
+            }
+        }
+
+        if supplies.upower {
+            // Online state of every adapter, AC is on if any of them is
+            let adapters: Arc<Mutex<Vec<(String, bool)>>> = Arc::new(Mutex::new(
+                supplies.adapters.iter().map(|a| (a.clone(), last_online.unwrap_or(false))).collect()
+            ));
+            for adapter in &supplies.adapters {
+                let proxy_ac = dbus_system.with_proxy(power_supply::UPOWER, adapter.as_str(), time::Duration::from_millis(5000));
+                let adapters = adapters.clone();
+                let adapter = adapter.clone();
+                let _id = proxy_ac.match_signal(move |h: battery::OrgFreedesktopDBusPropertiesPropertiesChanged, _: &Connection, _: &Message| {
+                    let online: Option<&bool> = arg::prop_cast(&h.changed_properties, "Online");
+                    if let Some(online) = online {
+                        println!("Online {}: {:?}", adapter, online);
+                        let any_online = match adapters.lock() {
+                            Ok(mut states) => {
+                                for state in states.iter_mut().filter(|s| s.0 == adapter) {
+                                    state.1 = *online;
+                                }
+                                states.iter().any(|s| s.1)
+                            },
+                            Err(_) => *online,
+                        };
+                        if let Ok(mut d) = DEV_MANAGER.lock() {
+                            d.set_ac_state(any_online);
+                        }
+                    }
+                    true
+                });
             }
-            true
-        });
+
+            if let Some(battery) = &supplies.battery {
+                let proxy_battery = dbus_system.with_proxy(power_supply::UPOWER, battery.as_str(), time::Duration::from_millis(5000));
+                let _id = proxy_battery.match_signal(|h: battery::OrgFreedesktopDBusPropertiesPropertiesChanged, _: &Connection, _: &Message| {
+                    let perc: Option<&f64> = arg::prop_cast(&h.changed_properties, "Percentage");
+                    if let Some(perc) = perc {
+                        println!("battery percentage: {:.1}", perc);
+                        if let Ok(mut d) = DEV_MANAGER.lock() {
+                            d.set_battery_percentage(*perc);
+                        }
+                    }
+                    let state: Option<&u32> = arg::prop_cast(&h.changed_properties, "State");
+                    if let Some(state) = state {
+                        if let Ok(mut d) = DEV_MANAGER.lock() {
+                            d.set_battery_charge_done(*state == power_supply::STATE_FULLY_CHARGED || *state == power_supply::STATE_PENDING_CHARGE);
+                        }
+                    }
+                    true
+                });
+            }
+        }
 
         let proxy_login = dbus_system.with_proxy("org.freedesktop.login1", "/org/freedesktop/login1", time::Duration::from_millis(5000));
         let _id = proxy_login.match_signal(|h: login1::OrgFreedesktopLogin1ManagerPrepareForSleep, _: &Connection, _: &Message| {
@@ -259,7 +426,9 @@ fn start_battery_monitor_task() -> JoinHandle<()> {
             if let Ok(mut d) = DEV_MANAGER.lock() {
                 d.set_ac_state_get();
                 if h.start {
+                    // There is no time left to fade before the system suspends
                     d.light_off();
+                    d.finish_fade();
                 } else {
                     d.restore_light();
                 }
@@ -267,14 +436,623 @@ fn start_battery_monitor_task() -> JoinHandle<()> {
             true
         });
         // use login1::OrgFreedesktopLogin1ManagerPrepareForSleep;
+        let mut last_poll = time::Instant::now();
+        let mut last_override_check = time::Instant::now();
         loop {
             if let Err(e) = dbus_system.process(time::Duration::from_millis(1000)) {
                 eprintln!("Battery monitor D-Bus error: {}", e);
             }
+            // Full-by charge overrides start and end at a time of day
+            if last_override_check.elapsed() >= time::Duration::from_secs(60) {
+                last_override_check = time::Instant::now();
+                if let Ok(mut d) = DEV_MANAGER.lock() {
+                    d.check_charge_override();
+                }
+            }
+            // Without UPower there are no signals, poll sysfs instead
+            if !supplies.upower && last_poll.elapsed() >= time::Duration::from_secs(2) {
+                last_poll = time::Instant::now();
+                let online = supplies.ac_online(&dbus_system);
+                if online.is_some() && online != last_online {
+                    println!("AC online: {:?}", online);
+                    if let Ok(mut d) = DEV_MANAGER.lock() {
+                        d.set_ac_state(online.unwrap_or(true));
+                    }
+                    last_online = online;
+                }
+                let perc = supplies.battery_percentage(&dbus_system);
+                if perc.is_some() && perc != last_perc {
+                    if let Ok(mut d) = DEV_MANAGER.lock() {
+                        d.set_battery_percentage(perc.unwrap_or(100.0));
+                    }
+                    last_perc = perc;
+                }
+                let done = supplies.battery_charge_done(&dbus_system);
+                if done.is_some() && done != last_done {
+                    if let Ok(mut d) = DEV_MANAGER.lock() {
+                        d.set_battery_charge_done(done.unwrap_or(false));
+                    }
+                    last_done = done;
+                }
+            }
+        }
+    })
+}
+
+/// Records battery capacity, charge rates and how long the charge limit is in effect
+fn start_battery_history_task() -> JoinHandle<()> {
+    thread::spawn(move || {
+        let battery = match service::sensors::battery_name() {
+            Some(battery) => battery,
+            None => {
+                println!("Battery history: no battery found, not recording");
+                return;
+            }
+        };
+        let mut unsaved = 0;
+        loop {
+            let reading = service::sensors::read_battery(&battery);
+            let limited = DEV_MANAGER.lock().map_or(false, |mut d| d.charge_limit().0);
+            if let Ok(mut history) = BATTERY_HISTORY.lock() {
+                history.record(config::unix_now(), &reading, limited);
+                unsaved += 1;
+                if unsaved >= 10 {
+                    unsaved = 0;
+                    if let Err(e) = history.save() {
+                        eprintln!("Error writing battery history: {}", e);
+                    }
+                }
+            }
+            thread::sleep(time::Duration::from_secs(battery_history::SAMPLE_SECS));
+        }
+    })
+}
+
+/// Samples temperatures, fan speed, power mode and power draw into the telemetry buffer
+fn start_telemetry_task() -> JoinHandle<()> {
+    thread::spawn(move || {
+        if persist_telemetry() {
+            if let Ok(mut telemetry) = TELEMETRY.lock() {
+                *telemetry = telemetry::Telemetry::load();
+            }
+        }
+        let mut unsaved = 0;
+        loop {
+            // Power is measured between two reads of the sensors
+            let readings = match SENSORS.lock() {
+                Ok(mut sensors) => sensors.read(),
+                Err(_) => return,
+            };
+            let (fan_rpm, power_mode, on_ac) = match DEV_MANAGER.lock() {
+                Ok(mut d) => {
+                    let (power_mode, ac) = d.get_device().map_or((0, 0), |laptop| (laptop.current_power_mode(), laptop.get_ac_state()));
+                    (d.get_actual_fan_rpm(), power_mode, ac == 1)
+                }
+                Err(_) => return,
+            };
+            use service::sensors::{Component, Quantity};
+            let temp = |component| readings.get(component, Quantity::Temperature).map(|t| t as f32);
+            let battery_w = readings.get(Component::Battery, Quantity::Power).map(|w| {
+                if readings.battery_status.as_deref() == Some("Discharging") { -w as f32 } else { w as f32 }
+            });
+            let sample = comms::TelemetrySample {
+                time: config::unix_now(),
+                cpu_temp: temp(Component::Cpu),
+                igpu_temp: temp(Component::Igpu),
+                dgpu_temp: temp(Component::Dgpu),
+                fan_rpm,
+                power_mode,
+                package_w: readings.get(Component::Cpu, Quantity::Power).map(|w| w as f32),
+                battery_w,
+                on_ac,
+            };
+            if let Ok(mut telemetry) = TELEMETRY.lock() {
+                telemetry.record(sample);
+                unsaved += 1;
+                if unsaved >= 60 && persist_telemetry() {
+                    unsaved = 0;
+                    if let Err(e) = telemetry.save() {
+                        eprintln!("Error writing telemetry: {}", e);
+                    }
+                }
+            }
+            thread::sleep(time::Duration::from_secs(telemetry::SAMPLE_SECS));
+        }
+    })
+}
+
+/// Serves the metrics endpoints enabled in the configuration
+fn start_metrics_task() {
+    let config = match DEV_MANAGER.lock().ok().and_then(|d| d.config.as_ref().map(|c| c.metrics.clone())) {
+        Some(config) => config,
+        None => return,
+    };
+    if config.socket.is_empty() && config.port == 0 && config.textfile.is_empty() {
+        return;
+    }
+    // GPU names come from nvidia-smi, which wakes the dGPU, so they are only looked up once
+    let gpus = Arc::new(gpu::discover_gpus());
+    if !config.socket.is_empty() {
+        match metrics::bind_unix(&config.socket) {
+            Ok(listener) => {
+                println!("metrics on unix socket {}", config.socket);
+                let gpus = gpus.clone();
+                thread::spawn(move || metrics::serve_unix(listener, |openmetrics| metrics::render(&collect_metrics(&gpus), openmetrics)));
+            }
+            Err(e) => eprintln!("Cannot serve metrics on {}: {}", config.socket, e),
+        }
+    }
+    if config.port != 0 {
+        match std::net::TcpListener::bind(("127.0.0.1", config.port)) {
+            Ok(listener) => {
+                println!("metrics on http://127.0.0.1:{}/metrics", config.port);
+                let gpus = gpus.clone();
+                thread::spawn(move || metrics::serve_http(listener, |openmetrics| metrics::render(&collect_metrics(&gpus), openmetrics)));
+            }
+            Err(e) => eprintln!("Cannot serve metrics on port {}: {}", config.port, e),
+        }
+    }
+    if !config.textfile.is_empty() {
+        println!("metrics written to {}", config.textfile);
+        thread::spawn(move || loop {
+            if let Err(e) = metrics::write_textfile(&config.textfile, &metrics::render(&collect_metrics(&gpus), false)) {
+                eprintln!("Error writing metrics to {}: {}", config.textfile, e);
+            }
+            thread::sleep(time::Duration::from_secs(config.textfile_secs.max(1) as u64));
+        });
+    }
+}
+
+/// Samples CPU load, temperatures and battery charge for the system metric
+/// effects while a layer shows them
+fn start_system_state_task() -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut cpu = service::sensors::CpuTimes::new();
+        loop {
+            thread::sleep(time::Duration::from_secs(1));
+            // Cheap, keeps the load of the first second a layer shows it right
+            let (cpu_load, core_loads) = cpu.sample();
+            let cpu_load = cpu_load.map(|l| l as f32);
+            let core_loads = core_loads.into_iter().map(|l| l as f32).collect();
+            if !EFFECT_MANAGER.lock().map_or(false, |mgr| mgr.uses_system_state()) {
+                continue;
+            }
+            // Temperatures come from the telemetry task, reading the dGPU more often would keep it awake
+            let sample = TELEMETRY.lock().ok().and_then(|t| t.latest().cloned());
+            let temp = |value: fn(&comms::TelemetrySample) -> Option<f32>| sample.as_ref().and_then(value);
+            kbd::system::set(kbd::system::SystemState {
+                cpu_load,
+                core_loads,
+                cpu_temp: temp(|s| s.cpu_temp),
+                gpu_temp: temp(|s| s.dgpu_temp).or(temp(|s| s.igpu_temp)),
+                battery: service::sensors::battery_capacity().map(|c| c as f32),
+                on_ac: service::sensors::ac_online().unwrap_or(true),
+            });
+        }
+    })
+}
+
+/// Captures audio for the spectrum effect while a layer shows it. The
+/// capture stops, and any recorder is killed, once no layer needs it.
+fn start_audio_task() -> JoinHandle<()> {
+    let needed = || EFFECT_MANAGER.lock().map_or(false, |mgr| mgr.uses_audio());
+    thread::spawn(move || {
+        loop {
+            thread::sleep(time::Duration::from_secs(1));
+            if !needed() {
+                continue;
+            }
+            let config = match DEV_MANAGER.lock().ok().and_then(|d| d.config.as_ref().map(|c| c.audio.clone())) {
+                Some(config) => config,
+                None => continue,
+            };
+            let mut capture = match kbd::audio::Capture::open(&config.input, config.rate) {
+                Ok(capture) => capture,
+                Err(e) => {
+                    eprintln!("Cannot capture audio for the spectrum effect: {}", e);
+                    thread::sleep(time::Duration::from_secs(30));
+                    continue;
+                }
+            };
+            let mut analyser = kbd::audio::Analyser::new(config.rate);
+            let mut checked = time::Instant::now();
+            loop {
+                match capture.read() {
+                    Ok(samples) => {
+                        if let Some(levels) = analyser.push(&samples) {
+                            kbd::audio::set_levels(levels);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Audio capture error: {}", e);
+                        break;
+                    }
+                }
+                if checked.elapsed() >= time::Duration::from_secs(1) {
+                    if !needed() {
+                        break;
+                    }
+                    checked = time::Instant::now();
+                }
+            }
+            kbd::audio::set_levels(vec![]);
+        }
+    })
+}
+
+/// Feeds key presses to the reactive effects when enabled in the configuration
+fn start_key_input_task() {
+    let config = match DEV_MANAGER.lock().ok().and_then(|d| d.config.as_ref().map(|c| c.key_input.clone())) {
+        Some(config) => config,
+        None => return,
+    };
+    if !config.enabled {
+        return;
+    }
+    let path = if config.device.is_empty() {
+        match key_input::find_keyboard() {
+            Some(path) => path,
+            None => {
+                eprintln!("No Razer keyboard input found, reactive effects disabled");
+                return;
+            }
+        }
+    } else {
+        std::path::PathBuf::from(&config.device)
+    };
+    thread::spawn(move || {
+        let mut input = match key_input::KeyInput::open(&path) {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("Cannot read key presses from {:?} ({}), reactive effects disabled", path, e);
+                if e.kind() == std::io::ErrorKind::PermissionDenied {
+                    eprintln!("Install 99-razercontrol-key-input.rules to let the daemon read the keyboard");
+                }
+                return;
+            }
+        };
+        println!("reactive effects reading key presses from {:?}", path);
+        loop {
+            match input.wait() {
+                Ok(code) => {
+                    if let Ok(mut mgr) = EFFECT_MANAGER.lock() {
+                        mgr.key_pressed(code);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Key input error: {}", e);
+                    return;
+                }
+            }
+        }
+    });
+}
+
+fn collect_metrics(gpus: &[comms::GpuInfo]) -> metrics::Snapshot {
+    let mut gpus = gpus.to_vec();
+    for gpu in &mut gpus {
+        gpu.runtime_status = gpu::runtime_status(&gpu.pci_slot);
+    }
+    // Temperatures and power come from the telemetry task, so scrapes do not poll the sensors
+    let sensors = TELEMETRY.lock().ok().and_then(|t| t.latest().cloned());
+    let mut snapshot = metrics::Snapshot {
+        fan_rpm: 0,
+        fan_target_rpm: 0,
+        power_mode: 0,
+        configured_power_mode: 0,
+        cpu_boost: 0,
+        gpu_boost: 0,
+        bho_on: false,
+        bho_threshold: 0,
+        ac_online: false,
+        gpus,
+        sensors,
+    };
+    if let Ok(mut d) = DEV_MANAGER.lock() {
+        let (power_mode, ac) = d.get_device().map_or((0, 0), |laptop| (laptop.current_power_mode(), laptop.get_ac_state()));
+        (snapshot.bho_on, snapshot.bho_threshold) = d.charge_limit();
+        snapshot.fan_rpm = d.get_actual_fan_rpm();
+        snapshot.fan_target_rpm = d.get_fan_rpm(ac);
+        snapshot.power_mode = power_mode;
+        snapshot.configured_power_mode = d.get_power_mode(ac);
+        snapshot.cpu_boost = d.get_cpu_boost(ac);
+        snapshot.gpu_boost = d.get_gpu_boost(ac);
+        snapshot.ac_online = ac == 1;
+    }
+    return snapshot;
+}
+
+fn persist_telemetry() -> bool {
+    DEV_MANAGER.lock().map_or(false, |d| d.config.as_ref().map_or(false, |c| c.persist_telemetry))
+}
+
+/// Applies brightness changes from the desktop (UPower KbdBacklight) to the keyboard
+fn start_kbd_backlight_task() -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut led = match kbd_backlight::KbdBacklight::new() {
+            Ok(led) => led,
+            Err(e) => {
+                eprintln!("Keyboard backlight LED unavailable ({}), desktop brightness keys disabled", e);
+                return;
+            }
+        };
+        println!("keyboard backlight LED {} created", kbd_backlight::LED_NAME);
+        if let Ok(mut d) = DEV_MANAGER.lock() {
+            d.publish_brightness();
+        }
+        loop {
+            let brightness = match led.wait() {
+                Ok(brightness) => brightness,
+                Err(e) => {
+                    eprintln!("Keyboard backlight LED error: {}", e);
+                    return;
+                }
+            };
+            if let Ok(mut d) = DEV_MANAGER.lock() {
+                let ac = d.get_device().map_or(0, |laptop| laptop.get_ac_state());
+                // Our own publish_brightness comes back here as well
+                if d.get_brightness(ac) != brightness {
+                    println!("desktop keyboard brightness: {}%", brightness);
+                    d.set_brightness(ac, brightness);
+                }
+            }
         }
     })
 }
 
+/// Follows the power profile chosen in the desktop through power-profiles-daemon
+fn start_power_profiles_task() -> JoinHandle<()> {
+    thread::spawn(move || {
+        let dbus_system = match power_profiles::connect() {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("Power profiles: D-Bus unavailable ({}), skipping", e);
+                return;
+            }
+        };
+        let proxy = dbus_system.with_proxy(power_profiles::BUS_NAME, power_profiles::PATH, time::Duration::from_millis(5000));
+        use power_profiles::NetHadessPowerProfiles;
+        match proxy.active_profile() {
+            Ok(profile) => {
+                println!("power profile: {}", profile);
+                if let Ok(mut d) = DEV_MANAGER.lock() {
+                    d.power_profile_changed(&profile);
+                }
+            },
+            // Keep listening, the signal arrives once it is started
+            Err(e) => println!("power-profiles-daemon not available: {}", e),
+        }
+        let _id = proxy.match_signal(|h: battery::OrgFreedesktopDBusPropertiesPropertiesChanged, _: &Connection, _: &Message| {
+            let profile: Option<&String> = arg::prop_cast(&h.changed_properties, "ActiveProfile");
+            if let Some(profile) = profile {
+                println!("power profile: {}", profile);
+                if let Ok(mut d) = DEV_MANAGER.lock() {
+                    d.power_profile_changed(profile);
+                }
+            }
+            true
+        });
+        loop {
+            if let Err(e) = dbus_system.process(time::Duration::from_millis(200)) {
+                eprintln!("Power profiles D-Bus error: {}", e);
+            }
+            // The lock is released before the call, it would stall the animator and IPC
+            let request = DEV_MANAGER.lock().ok().and_then(|mut d| d.take_power_profile_request());
+            match request {
+                Some(power_profiles::Request::Set(profile)) => {
+                    if let Err(e) = proxy.set_active_profile(profile.clone()) {
+                        eprintln!("Error setting power profile {}: {}", profile, e);
+                    }
+                }
+                Some(power_profiles::Request::Read) => match proxy.active_profile() {
+                    Ok(profile) => {
+                        if let Ok(mut d) = DEV_MANAGER.lock() {
+                            d.power_profile_changed(&profile);
+                        }
+                    }
+                    Err(e) => eprintln!("Error reading power profile: {}", e),
+                },
+                None => {}
+            }
+        }
+    })
+}
+
+/// Writes configuration and effect changes to disk in the background
+pub fn start_persistence_task() -> JoinHandle<()> {
+    thread::spawn(|| {
+        loop {
+            thread::sleep(time::Duration::from_millis(500));
+            if let Ok(mut d) = DEV_MANAGER.lock() {
+                d.flush_config(time::Duration::from_secs(1));
+            }
+            let save = match EFFECT_MANAGER.lock() {
+                Ok(mut mgr) => mgr.take_dirty_save(),
+                Err(_) => None,
+            };
+            if let Some(json) = save {
+                if let Err(error) = config::Configuration::write_effects_save(json) {
+                    error!("Error writing effects {}", error);
+                }
+            }
+        }
+    })
+}
+
+/// Applies edits of `daemon.json` and `effects.json` made while the daemon runs
+pub fn start_config_watch_task() -> JoinHandle<()> {
+    thread::spawn(|| {
+        let dirs: Vec<std::path::PathBuf> = [config::config_dir(), config::state_dir()]
+            .into_iter()
+            .filter_map(|d| d.ok())
+            .collect();
+        let watch = match file_watch::FileWatch::new(&dirs) {
+            Ok(w) => w,
+            Err(e) => {
+                eprintln!("Not watching configuration files: {}", e);
+                return;
+            }
+        };
+        loop {
+            let names = match watch.wait() {
+                Ok(n) => n,
+                Err(e) => {
+                    eprintln!("Configuration watch error: {}", e);
+                    return;
+                }
+            };
+            if names.iter().any(|n| n == config::SETTINGS_FILE) {
+                for problem in reload_config_file(false) {
+                    eprintln!("Rejected configuration change: {}", problem);
+                }
+            }
+            if names.iter().any(|n| n == config::EFFECTS_FILE) {
+                for problem in reload_effects_file(false) {
+                    eprintln!("Rejected effects change: {}", problem);
+                }
+            }
+        }
+    })
+}
+
+/// Reads `daemon.json` and applies it if it is valid. Unless `force` is set
+/// the file is skipped when it holds what the daemon wrote itself.
+/// Returns the reasons for rejecting the file.
+fn reload_config_file(force: bool) -> Vec<String> {
+    let path = match config::config_dir() {
+        Ok(dir) => dir.join(config::SETTINGS_FILE),
+        Err(e) => return vec![format!("Cannot locate configuration file: {}", e)],
+    };
+    let str = match fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(e) => return vec![format!("Cannot read configuration file: {}", e)],
+    };
+    if !force && config::is_known_contents(&path, str.as_bytes()) {
+        return vec![];
+    }
+    let c = match config::Configuration::from_json_str(str.as_str()) {
+        Ok(c) => c,
+        Err(e) => return vec![format!("Cannot parse configuration file: {}", e)],
+    };
+    let problems = c.validate();
+    if !problems.is_empty() {
+        return problems;
+    }
+    config::remember_contents(&path, str.as_bytes());
+    if let Ok(mut d) = DEV_MANAGER.lock() {
+        d.apply_config(c);
+        apply_key_layout(&mut d);
+    }
+    println!("Configuration reloaded");
+    vec![]
+}
+
+/// Same as `reload_config_file` for `effects.json`
+fn reload_effects_file(force: bool) -> Vec<String> {
+    let path = match config::state_dir() {
+        Ok(dir) => dir.join(config::EFFECTS_FILE),
+        Err(e) => return vec![format!("Cannot locate effects file: {}", e)],
+    };
+    let str = match fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(e) => return vec![format!("Cannot read effects file: {}", e)],
+    };
+    if !force && config::is_known_contents(&path, str.as_bytes()) {
+        return vec![];
+    }
+    let json: serde_json::Value = match serde_json::from_str(str.as_str()) {
+        Ok(j) => j,
+        Err(e) => return vec![format!("Cannot parse effects file: {}", e)],
+    };
+    if let Ok(mut mgr) = EFFECT_MANAGER.lock() {
+        if let Err(e) = mgr.replace_from_save(json) {
+            return vec![e];
+        }
+    }
+    config::remember_contents(&path, str.as_bytes());
+    println!("Effects reloaded");
+    vec![]
+}
+
+/// Names keys in effect saves and commands by the layout of this laptop
+fn apply_key_layout(d: &mut device::DeviceManager) {
+    let name = d.key_layout();
+    let (lighting, matrix) = match d.get_device() {
+        Some(laptop) => (laptop.get_lighting(), laptop.get_matrix()),
+        None => (service::keyboard::Lighting::PerKey, service::keyboard::default_matrix()),
+    };
+    let layout = service::keyboard::Layout::for_device(&name, lighting, matrix);
+    if let Ok(mut mgr) = EFFECT_MANAGER.lock() {
+        mgr.set_layout(layout, lighting);
+    }
+}
+
+/// Runs a change of the effect layers, returning the result and error for the reply
+fn edit_layers(edit: impl FnOnce(&mut kbd::EffectManager) -> Result<(), String>) -> (bool, String) {
+    let mut mgr = match EFFECT_MANAGER.lock() {
+        Ok(mgr) => mgr,
+        Err(_) => return (false, "Effect manager is unavailable".to_string()),
+    };
+    match edit(&mut mgr) {
+        Ok(()) => (true, String::new()),
+        Err(e) => {
+            eprintln!("Layer change rejected: {}", e);
+            (false, e)
+        }
+    }
+}
+
+/// Matrix indexes of keys given by index or name
+fn resolve_keys(layout: &service::keyboard::Layout, keys: &[(String, [u8; 3])]) -> Result<Vec<(usize, kbd::board::KeyColour)>, String> {
+    keys.iter()
+        .map(|(key, rgb)| match layout.key_index(key) {
+            Some(idx) => Ok((idx, kbd::board::KeyColour { red: rgb[0], green: rgb[1], blue: rgb[2] })),
+            None => Err(format!("Unknown key {:?}", key)),
+        })
+        .collect()
+}
+
+/// Applies a bundle made by `ExportConfig`. Nothing is changed unless the
+/// whole bundle is valid for this laptop. Returns whether it was applied
+/// together with the problems or warnings found.
+fn import_bundle(d: &mut device::DeviceManager, bundle: &str) -> (bool, Vec<String>) {
+    let bundle: config::Bundle = match serde_json::from_str(bundle) {
+        Ok(b) => b,
+        Err(e) => return (false, vec![format!("Not a settings bundle: {}", e)]),
+    };
+    if bundle.format != config::BUNDLE_FORMAT {
+        return (false, vec![format!("Unknown bundle format {:?}", bundle.format)]);
+    }
+    if bundle.version > config::BUNDLE_VERSION {
+        return (false, vec![format!("Bundle version {} is newer than {}", bundle.version, config::BUNDLE_VERSION)]);
+    }
+    let mut c = match config::Configuration::from_json_str(bundle.config.to_string().as_str()) {
+        Ok(c) => c,
+        Err(e) => return (false, vec![format!("Invalid configuration: {}", e)]),
+    };
+    let mut problems = c.validate();
+    problems.extend(d.check_capabilities(&c));
+    if !problems.is_empty() {
+        return (false, problems);
+    }
+
+    let mut messages = vec![];
+    let name = d.get_device().map_or(String::new(), |laptop| laptop.get_name());
+    if bundle.device != name {
+        messages.push(format!("Bundle was exported from a {}", bundle.device));
+    }
+    if let Ok(mut mgr) = EFFECT_MANAGER.lock() {
+        if let Err(e) = mgr.replace_from_save(bundle.effects) {
+            return (false, vec![format!("Invalid effects: {}", e)]);
+        }
+        mgr.mark_dirty();
+    }
+    c.mark_dirty();
+    d.apply_config(c);
+    apply_key_layout(d);
+    (true, messages)
+}
+
 /// Monitors signals and stops the daemon when receiving one
 pub fn start_shutdown_task() -> JoinHandle<()> {
     thread::spawn(|| {
@@ -283,6 +1061,21 @@ pub fn start_shutdown_task() -> JoinHandle<()> {
         
         // If we reach this point, we have a signal and it is time to exit
         println!("Received signal, cleaning up");
+        if let Ok(mut d) = DEV_MANAGER.lock() {
+            d.flush_config(time::Duration::ZERO);
+        }
+        if let Ok(history) = BATTERY_HISTORY.lock() {
+            if let Err(e) = history.save() {
+                eprintln!("Error writing battery history: {}", e);
+            }
+        }
+        if persist_telemetry() {
+            if let Ok(telemetry) = TELEMETRY.lock() {
+                if let Err(e) = telemetry.save() {
+                    eprintln!("Error writing telemetry: {}", e);
+                }
+            }
+        }
         let json = match EFFECT_MANAGER.lock() {
             Ok(mut mgr) => mgr.save(),
             Err(e) => {
@@ -299,12 +1092,7 @@ pub fn start_shutdown_task() -> JoinHandle<()> {
 }
 
 fn handle_data(mut stream: UnixStream) {
-    let mut buffer = [0u8; 4096];
-    if stream.read(&mut buffer).is_err() {
-        return;
-    }
-
-    if let Some(cmd) = comms::read_from_socket_req(&buffer) {
+    if let Some(cmd) = comms::read_from_socket_req(&mut stream) {
         if let Some(s) = process_client_request(cmd) {
             if let Ok(x) = bincode::serialize(&s) {
                 let result = stream.write_all(&x);
@@ -345,6 +1133,67 @@ pub fn process_client_request(cmd: comms::DaemonCommand) -> Option<comms::Daemon
             let (ok, msg) = gpu::set_envycontrol_mode(mode);
             return Some(comms::DaemonResponse::SetGpuMode { result: ok, message: msg });
         }
+        comms::DaemonCommand::CheckConfig => {
+            let problems = config::Configuration::check_file();
+            return Some(comms::DaemonResponse::CheckConfig { missing: problems.is_none(), problems: problems.unwrap_or_default() });
+        }
+        comms::DaemonCommand::GetBatteryHistory => {
+            let history = BATTERY_HISTORY.lock().ok()?;
+            let latest = history.latest();
+            let full_wh = latest.and_then(|d| d.full_wh);
+            let design_wh = latest.and_then(|d| d.design_wh);
+            let rate = |wh: f64, secs: u64| if secs > 0 { Some(wh * 3600.0 / secs as f64) } else { None };
+            return Some(comms::DaemonResponse::GetBatteryHistory { history: comms::BatteryHistoryInfo {
+                cycle_count: history.days.iter().rev().find_map(|d| d.cycle_count),
+                design_wh,
+                full_wh,
+                wear_percent: match (full_wh, design_wh) {
+                    (Some(full), Some(design)) if design > 0.0 => Some((1.0 - full / design) * 100.0),
+                    _ => None,
+                },
+                avg_charge_w: rate(history.charge_wh, history.charge_secs),
+                avg_discharge_w: rate(history.discharge_wh, history.discharge_secs),
+                tracked_hours: history.tracked_secs as f64 / 3600.0,
+                high_soc_hours: history.high_soc_secs as f64 / 3600.0,
+                limited_hours: history.limited_secs as f64 / 3600.0,
+                wear_per_month_limited: history.wear_per_month(true),
+                wear_per_month_unlimited: history.wear_per_month(false),
+                capacity: history.days.iter()
+                    .filter_map(|d| d.full_wh.map(|wh| (d.day * 24 * 60 * 60, wh)))
+                    .collect(),
+            } });
+        }
+        comms::DaemonCommand::GetTelemetry { since, resolution } => {
+            let samples = TELEMETRY.lock().ok()?.query(*since, *resolution);
+            return Some(comms::DaemonResponse::GetTelemetry { samples });
+        }
+        comms::DaemonCommand::SetKeys { layer, keys, timeout_ms } => {
+            let (result, message) = edit_layers(|mgr| {
+                let keys = resolve_keys(mgr.get_layout(), keys)?;
+                mgr.set_keys(*layer, &keys, *timeout_ms)
+            });
+            return Some(comms::DaemonResponse::SetKeys { result, message });
+        }
+        comms::DaemonCommand::GetKeyLayout => {
+            let layout = EFFECT_MANAGER.lock().ok()?.get_layout().clone();
+            return Some(comms::DaemonResponse::GetKeyLayout { layout });
+        }
+        comms::DaemonCommand::ClearKeys { layer } => {
+            let (result, message) = edit_layers(|mgr| mgr.clear_keys(*layer));
+            return Some(comms::DaemonResponse::ClearKeys { result, message });
+        }
+        comms::DaemonCommand::GetSensors => {
+            let readings = SENSORS.lock().ok()?.read();
+            return Some(comms::DaemonResponse::GetSensors { readings });
+        }
+        comms::DaemonCommand::ReloadConfig => {
+            let mut problems = reload_config_file(true);
+            // A missing effects file just means none were saved yet
+            if config::state_dir().map_or(false, |d| d.join(config::EFFECTS_FILE).exists()) {
+                problems.extend(reload_effects_file(true));
+            }
+            return Some(comms::DaemonResponse::ReloadConfig { problems });
+        }
         _ => {}
     }
 
@@ -402,20 +1251,18 @@ pub fn process_client_request(cmd: comms::DaemonCommand) -> Option<comms::Daemon
                 }
                 if let Ok(mut k) = EFFECT_MANAGER.lock() {
                     res = true;
-                    let effect = match name.as_str() {
-                        "static" => Some(kbd::effects::Static::new(params)),
-                        "static_gradient" => Some(kbd::effects::StaticGradient::new(params)),
-                        "wave_gradient" => Some(kbd::effects::WaveGradient::new(params)),
-                        "breathing_single" => Some(kbd::effects::BreathSingle::new(params)),
-                        _ => None
-                    };
+                    let effect = kbd::new_effect(name.as_str(), params);
 
                     if let Some(laptop) = d.get_device() {
-                        if let Some(e) = effect {
+                        if !laptop.get_lighting().has_effects() {
+                            eprintln!("Custom effects need a RGB keyboard");
+                            res = false;
+                        } else if let Some(e) = effect {
+                            let mask = vec![true; k.get_layout().key_count()];
                             k.pop_effect(laptop); // Remove old layer
                             k.push_effect(
                                 e,
-                                [true; 90]
+                                mask
                                 );
                         } else {
                             res = false
@@ -431,7 +1278,10 @@ pub fn process_client_request(cmd: comms::DaemonCommand) -> Option<comms::Daemon
                 // TODO save standart effect may be struct ?
                 let mut res = false;
                 if let Some(laptop) = d.get_device() {
-                    if let Ok(mut k) = EFFECT_MANAGER.lock() {
+                    let lighting = laptop.get_lighting();
+                    if !lighting.standard_effects().contains(&name.as_str()) {
+                        eprintln!("The {} keyboard has no {} effect", lighting.name(), name);
+                    } else if let Ok(mut k) = EFFECT_MANAGER.lock() {
                         k.pop_effect(laptop); // Remove old layer
                         let _res = match name.as_str() {
                             "off" => d.set_standard_effect(device::RazerLaptop::OFF, params),
@@ -450,6 +1300,45 @@ pub fn process_client_request(cmd: comms::DaemonCommand) -> Option<comms::Daemon
                 }
                 Some(comms::DaemonResponse::SetStandardEffect{result: res})
             }
+            comms::DaemonCommand::ListLayers => {
+                let layers = EFFECT_MANAGER.lock().ok()?.list_layers();
+                Some(comms::DaemonResponse::ListLayers { layers })
+            }
+            comms::DaemonCommand::InsertLayer { index, layer } => {
+                let (result, message) = edit_layers(|mgr| {
+                    let effect = kbd::new_effect(&layer.effect, layer.params)
+                        .ok_or(format!("Unknown effect {}", layer.effect))?;
+                    let blend = kbd::BlendMode::from_name(&layer.blend)
+                        .ok_or(format!("Unknown blend mode {}", layer.blend))?;
+                    mgr.insert_layer(index, effect, layer.key_mask, blend, layer.opacity)
+                });
+                Some(comms::DaemonResponse::InsertLayer { result, message })
+            }
+            comms::DaemonCommand::RemoveLayer { index } => {
+                let (result, message) = match d.get_device() {
+                    Some(laptop) => edit_layers(|mgr| mgr.remove_layer(index, laptop)),
+                    None => (false, "No device".to_string()),
+                };
+                Some(comms::DaemonResponse::RemoveLayer { result, message })
+            }
+            comms::DaemonCommand::MoveLayer { from, to } => {
+                let (result, message) = edit_layers(|mgr| mgr.move_layer(from, to));
+                Some(comms::DaemonResponse::MoveLayer { result, message })
+            }
+            comms::DaemonCommand::SetLayerMask { index, key_mask } => {
+                let (result, message) = edit_layers(|mgr| mgr.set_layer_mask(index, key_mask));
+                Some(comms::DaemonResponse::SetLayerMask { result, message })
+            }
+            comms::DaemonCommand::UpdateLayerParams { index, params, blend, opacity } => {
+                let (result, message) = edit_layers(|mgr| {
+                    let blend = match blend {
+                        Some(b) => Some(kbd::BlendMode::from_name(&b).ok_or(format!("Unknown blend mode {}", b))?),
+                        None => None,
+                    };
+                    mgr.update_layer(index, params, blend, opacity)
+                });
+                Some(comms::DaemonResponse::UpdateLayerParams { result, message })
+            }
             comms::DaemonCommand::SetBatteryHealthOptimizer { is_on, threshold } => { 
                 return Some(comms::DaemonResponse::SetBatteryHealthOptimizer { result: d.set_bho_handler(is_on, threshold)});
             }
@@ -461,6 +1350,80 @@ pub fn process_client_request(cmd: comms::DaemonCommand) -> Option<comms::Daemon
                     }
                 );
             }
+            comms::DaemonCommand::SetBatteryRules { no_light, low_power, power_mode } => {
+                Some(comms::DaemonResponse::SetBatteryRules { result: d.set_battery_rules(no_light, low_power, power_mode) })
+            }
+            comms::DaemonCommand::SetPowerProfiles { follow, sync_back } => {
+                Some(comms::DaemonResponse::SetPowerProfiles { result: d.set_power_profiles(follow, sync_back) })
+            }
+            comms::DaemonCommand::SetProfileMapping { mapping } => {
+                let result = mapping.power_mode <= 4 && mapping.cpu_boost <= 3 && mapping.gpu_boost <= 2
+                    && mapping.fan_rpm >= 0
+                    && d.set_profile_mapping(mapping.profile.as_str(), config::ProfileMapping {
+                        power_mode: mapping.power_mode,
+                        cpu_boost: mapping.cpu_boost,
+                        gpu_boost: mapping.gpu_boost,
+                        fan_rpm: mapping.fan_rpm,
+                    });
+                Some(comms::DaemonResponse::SetProfileMapping { result })
+            }
+            comms::DaemonCommand::GetPowerProfiles => {
+                d.get_power_profiles().map(|(profiles, active)| comms::DaemonResponse::GetPowerProfiles {
+                    follow: profiles.follow,
+                    sync_back: profiles.sync_back,
+                    active,
+                    mappings: profiles.mappings().iter().map(|(profile, m)| comms::ProfileMappingInfo {
+                        profile: profile.to_string(),
+                        power_mode: m.power_mode,
+                        cpu_boost: m.cpu_boost,
+                        gpu_boost: m.gpu_boost,
+                        fan_rpm: m.fan_rpm,
+                    }).collect(),
+                })
+            }
+            comms::DaemonCommand::SetFade { fade } => {
+                let result = d.set_fade(config::FadeConfig {
+                    fade_out_ms: fade.fade_out_ms,
+                    fade_in_ms: fade.fade_in_ms,
+                    idle_level: fade.idle_level,
+                    idle_dim_secs: fade.idle_dim_secs,
+                    slider_fade_ms: fade.slider_fade_ms,
+                });
+                Some(comms::DaemonResponse::SetFade { result })
+            }
+            comms::DaemonCommand::GetFade => {
+                let fade = d.get_fade();
+                Some(comms::DaemonResponse::GetFade { fade: comms::FadeInfo {
+                    fade_out_ms: fade.fade_out_ms,
+                    fade_in_ms: fade.fade_in_ms,
+                    idle_level: fade.idle_level,
+                    idle_dim_secs: fade.idle_dim_secs,
+                    slider_fade_ms: fade.slider_fade_ms,
+                } })
+            }
+            comms::DaemonCommand::SetChargeOverride { mode, at } => {
+                let result = match config::ChargeOverride::from_name(mode.as_str(), at) {
+                    Some(mode) => d.set_charge_override(mode),
+                    None => false,
+                };
+                Some(comms::DaemonResponse::SetChargeOverride { result })
+            }
+            comms::DaemonCommand::GetChargeThreshold => {
+                d.get_charge_threshold().map(|(bho_on, threshold, effective, mode, batteries)| {
+                    comms::DaemonResponse::GetChargeThreshold {
+                        bho_on,
+                        threshold,
+                        effective,
+                        override_mode: mode.name().to_string(),
+                        override_at: mode.at(),
+                        batteries,
+                    }
+                })
+            }
+            comms::DaemonCommand::GetBatteryRules => {
+                let (no_light, low_power, power_mode) = d.get_battery_rules();
+                Some(comms::DaemonResponse::GetBatteryRules { no_light, low_power, power_mode })
+            }
             comms::DaemonCommand::GetActualFanRpm => {
                 Some(comms::DaemonResponse::GetActualFanRpm { rpm: d.get_actual_fan_rpm() })
             },
@@ -471,6 +1434,18 @@ pub fn process_client_request(cmd: comms::DaemonCommand) -> Option<comms::Daemon
                 };
                 return Some(comms::DaemonResponse::GetDeviceName { name });
             }
+            comms::DaemonCommand::ExportConfig => {
+                let effects = match EFFECT_MANAGER.lock() {
+                    Ok(mut mgr) => mgr.save(),
+                    Err(_) => serde_json::json!({"effects": []}),
+                };
+                return d.export_bundle(effects)
+                    .map(|bundle| comms::DaemonResponse::ExportConfig { bundle });
+            }
+            comms::DaemonCommand::ImportConfig { bundle } => {
+                let (result, messages) = import_bundle(&mut d, bundle.as_str());
+                return Some(comms::DaemonResponse::ImportConfig { result, messages });
+            }
             comms::DaemonCommand::GetStandardEffect => {
                 let (effect, params) = d.get_standard_effect();
                 Some(comms::DaemonResponse::GetStandardEffect { effect, params })
@@ -2,11 +2,15 @@
 use serde::{Deserialize, Serialize};
 use serde_big_array::BigArray;
 use std::{thread, time, io, fs};
+use std::collections::VecDeque;
 use hidapi::HidApi;
 use crate::dbus_mutter_idlemonitor;
 use crate::config;
-use crate::battery;
+use crate::kbd_backlight;
+use crate::power_profiles;
+use crate::power_supply;
 use dbus::blocking::Connection;
+use service::keyboard::Lighting;
 
 const RAZER_VENDOR_ID: u16 = 0x1532;
 
@@ -17,6 +21,12 @@ pub struct SupportedDevice {
     pub pid: String,
     pub features: Vec<String>,
     pub fan: Vec<u16>,
+    #[serde(default)]
+    pub layout: String, // key layout, see service::keyboard::LAYOUTS (default ansi)
+    #[serde(default)]
+    pub lighting: Lighting,
+    #[serde(default = "service::keyboard::default_matrix")]
+    pub matrix: [usize; 2], // rows and columns of a per key keyboard
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -73,6 +83,16 @@ impl RazerPacket {
 }
 
 const DEVICE_FILE: &str = "/usr/share/razercontrol/laptops.json";
+const FULL_ONCE_MIN_PERCENT: f64 = 95.0; // above any charge threshold
+
+/// One leg of a keyboard brightness fade
+#[derive(Copy, Clone)]
+struct FadeStep {
+    to: u8, // raw brightness, 0 - 255
+    delay: time::Duration, // hold the previous level this long first
+    duration: time::Duration,
+}
+
 pub struct DeviceManager {
     pub device: Option <RazerLaptop>,
     supported_devices: Vec<SupportedDevice>,
@@ -81,6 +101,20 @@ pub struct DeviceManager {
     pub active_id: u32,
     add_active: bool,
     pub change_idle: bool,
+    battery_percentage: f64,
+    battery_charge_done: bool, // the charger stopped, full or holding the charge
+    battery_light_off: bool, // lights are off because of the no_light rule
+    battery_low_power: bool, // low_power_mode is active because of the low_power rule
+    ppd_profile: String, // last active profile of power-profiles-daemon we know of
+    ppd_request: Option<power_profiles::Request>, // sent by the power profiles task, not under the lock
+    lid_closed: bool, // lights stay off while the lid is closed
+    logind_idle_off: bool, // lights are off because logind reports the session idle
+    fade_steps: VecDeque<FadeStep>, // brightness fade in progress, stepped by the fade task
+    fade_from: u8,
+    fade_start: time::Instant,
+    fade_logo: Option<u8>, // logo state to set once the fade is done
+    charge_lifted: bool, // the charge override lifted the threshold when it was last applied
+    pub power_supplies: Option<power_supply::PowerSupplies>,
 }
 
 impl DeviceManager {
@@ -93,6 +127,20 @@ impl DeviceManager {
             active_id: 0,
             add_active: false,
             change_idle: false,
+            battery_percentage: 100.0,
+            battery_charge_done: false,
+            battery_light_off: false,
+            battery_low_power: false,
+            ppd_profile: String::new(),
+            ppd_request: None,
+            lid_closed: false,
+            logind_idle_off: false,
+            fade_steps: VecDeque::new(),
+            fade_from: 0,
+            fade_start: time::Instant::now(),
+            fade_logo: None,
+            charge_lifted: false,
+            power_supplies: None,
         };
     }
 
@@ -135,9 +183,7 @@ impl DeviceManager {
             config.power[other].logo_state = config.power[ac].logo_state;
             config.power[other].screensaver = config.power[ac].screensaver;
             config.power[other].idle = config.power[ac].idle;
-            if let Err(e) = config.write_to_file() {
-                eprintln!("Error write config {:?}", e);
-            }
+            config.mark_dirty();
         }
 
         return true;
@@ -171,9 +217,25 @@ impl DeviceManager {
         let mut res: DeviceManager = DeviceManager::new();
         res.supported_devices = serde_json::from_slice(str.as_slice())?;
         println!("suported devices found: {:?}", res.supported_devices.len());
+        config::migrate_legacy_files();
         match config::Configuration::read_from_config() {
-            Ok(c) => res.config = Some(c),
-            Err(_) => res.config = Some(config::Configuration::new()),
+            Ok(c) => {
+                for problem in c.validate() {
+                    eprintln!("Configuration problem: {}", problem);
+                }
+                res.config = Some(c);
+            },
+            Err(e) => {
+                // Keep the unreadable file around instead of overwriting it with the defaults
+                if e.kind() != io::ErrorKind::NotFound {
+                    eprintln!("Error reading configuration: {}", e);
+                    match config::Configuration::backup_config_file() {
+                        Ok(backup) => eprintln!("Unreadable configuration moved to {}", backup),
+                        Err(e) => eprintln!("Error backing up configuration: {}", e),
+                    }
+                }
+                res.config = Some(config::Configuration::from_defaults());
+            },
         }
 
         Ok(res)
@@ -188,14 +250,38 @@ impl DeviceManager {
     }
 
     pub fn light_off(&mut self) {
+        if self.idle_id != 0 {
+            self.add_active = true;
+        }
+        let fade = self.fade_config();
+        if let Some(laptop) = self.get_device() {
+            laptop.set_screensaver(true);
+        }
+        self.start_fade(vec![
+            FadeStep { to: 0, delay: time::Duration::ZERO, duration: time::Duration::from_millis(fade.fade_out_ms as u64) },
+        ], Some(0));
+    }
+
+    /// Like `light_off`, but dims to the idle level for a while first
+    pub fn idle_light_off(&mut self) {
+        let fade = self.fade_config();
+        let dim = (fade.idle_level as u32 * 255 / 100) as u8;
+        let current = self.get_device().map_or(0, |laptop| laptop.current_brightness());
+        if fade.idle_dim_secs == 0 || dim == 0 || dim >= current {
+            self.light_off();
+            return;
+        }
         if self.idle_id != 0 {
             self.add_active = true;
         }
         if let Some(laptop) = self.get_device() {
             laptop.set_screensaver(true);
-            laptop.set_brightness(0);
-            laptop.set_logo_led_state(0);
         }
+        let fade_out = time::Duration::from_millis(fade.fade_out_ms as u64);
+        self.start_fade(vec![
+            FadeStep { to: dim, delay: time::Duration::ZERO, duration: fade_out },
+            FadeStep { to: 0, delay: time::Duration::from_secs(fade.idle_dim_secs as u64), duration: fade_out },
+        ], Some(0));
     }
 
     pub fn restore_light(&mut self) {
@@ -210,11 +296,148 @@ impl DeviceManager {
             brightness = config.brightness;
             logo_state = config.logo_state;
         }
+        if self.battery_light_off || self.lid_closed {
+            brightness = 0;
+            logo_state = 0;
+        }
+        let fade = self.fade_config();
         if let Some(laptop) = self.get_device() {
             laptop.set_screensaver(false);
-            laptop.set_brightness(brightness);
             laptop.set_logo_led_state(logo_state);
         }
+        self.start_fade(vec![
+            FadeStep { to: brightness, delay: time::Duration::ZERO, duration: time::Duration::from_millis(fade.fade_in_ms as u64) },
+        ], None);
+    }
+
+    fn fade_config(&mut self) -> config::FadeConfig {
+        return self.get_config().map_or(config::FadeConfig::new(), |c| c.fade);
+    }
+
+    /// Replaces any fade in progress, `logo` is applied when the last step is done
+    fn start_fade(&mut self, steps: Vec<FadeStep>, logo: Option<u8>) {
+        self.fade_from = self.get_device().map_or(0, |laptop| laptop.current_brightness());
+        self.fade_start = time::Instant::now();
+        self.fade_steps = steps.into();
+        self.fade_logo = logo;
+        self.step_fade();
+    }
+
+    /// Stops the fade in progress where it is, for changes that have to apply at once
+    fn cancel_fade(&mut self) {
+        self.fade_steps.clear();
+        self.fade_logo = None;
+    }
+
+    /// Sets the brightness the fade in progress has reached by now
+    pub fn step_fade(&mut self) {
+        while let Some(step) = self.fade_steps.front().copied() {
+            let elapsed = self.fade_start.elapsed();
+            if elapsed < step.delay {
+                return;
+            }
+            let t = elapsed - step.delay;
+            let done = t >= step.duration;
+            let value = if done {
+                step.to
+            } else {
+                let from = self.fade_from as i64;
+                let to = step.to as i64;
+                (from + (to - from) * t.as_millis() as i64 / step.duration.as_millis() as i64) as u8
+            };
+            if let Some(laptop) = self.get_device() {
+                if laptop.current_brightness() != value {
+                    laptop.set_brightness(value);
+                }
+            }
+            if !done {
+                return;
+            }
+            self.fade_steps.pop_front();
+            self.fade_from = step.to;
+            self.fade_start += step.delay + step.duration;
+        }
+        if let Some(logo) = self.fade_logo.take() {
+            if let Some(laptop) = self.get_device() {
+                laptop.set_logo_led_state(logo);
+            }
+        }
+    }
+
+    /// Jumps to the end of the fade in progress, used before suspending
+    pub fn finish_fade(&mut self) {
+        if let Some(last) = self.fade_steps.back().copied() {
+            self.fade_steps.clear();
+            if let Some(laptop) = self.get_device() {
+                laptop.set_brightness(last.to);
+            }
+        }
+        self.step_fade();
+    }
+
+    pub fn get_fade(&mut self) -> config::FadeConfig {
+        return self.fade_config();
+    }
+
+    pub fn set_fade(&mut self, fade: config::FadeConfig) -> bool {
+        if fade.fade_out_ms > config::MAX_FADE_MS || fade.fade_in_ms > config::MAX_FADE_MS
+            || fade.slider_fade_ms > config::MAX_FADE_MS || fade.idle_level > 100
+            || fade.idle_dim_secs > config::MAX_IDLE_DIM_SECS {
+            return false;
+        }
+        if let Some(config) = self.get_config() {
+            config.fade = fade;
+            config.mark_dirty();
+            return true;
+        }
+
+        return false;
+    }
+
+    pub fn set_lid_closed(&mut self, closed: bool) {
+        if closed == self.lid_closed {
+            return;
+        }
+        println!("lid closed: {:?}", closed);
+        self.lid_closed = closed;
+        if closed {
+            self.cancel_fade();
+            if let Some(laptop) = self.get_device() {
+                laptop.set_brightness(0);
+                laptop.set_logo_led_state(0);
+            }
+        } else if !self.get_device().map_or(false, |laptop| laptop.is_screensaver()) {
+            self.restore_light();
+        }
+    }
+
+    /// Idle handling for desktops without the Mutter idle monitor. `idle_for` is
+    /// how long logind has reported the session idle, None while it is active.
+    /// The configured idle time counts from the moment the desktop sets the hint.
+    pub fn logind_idle(&mut self, idle_for: Option<time::Duration>) {
+        let mut ac: usize = 0;
+        if let Some(laptop) = self.get_device() {
+            ac = laptop.get_ac_state();
+        }
+        let timeout = match self.get_ac_config(ac) {
+            Some(config) => config.idle as u64 * 60,
+            None => 0,
+        };
+        match idle_for {
+            Some(idle) if timeout != 0 && idle.as_secs() >= timeout => {
+                if !self.logind_idle_off {
+                    println!("session idle for {}s", idle.as_secs());
+                    self.logind_idle_off = true;
+                    self.idle_light_off();
+                }
+            },
+            None if self.logind_idle_off => {
+                println!("session active");
+                self.logind_idle_off = false;
+                self.restore_light();
+            },
+            _ => {},
+        }
     }
 
     pub fn restore_standard_effect(&mut self) {
@@ -238,9 +461,7 @@ impl DeviceManager {
                     let other = (ac + 1) & 0x01;
                     config.power[other].idle = timeout;
                 }
-                if let Err(e) = config.write_to_file() {
-                    eprintln!("Error write config {:?}", e);
-                }
+                config.mark_dirty();
                 // arm = true;
                 self.change_idle = true;
             }
@@ -250,18 +471,23 @@ impl DeviceManager {
     }
 
     pub fn set_power_mode(&mut self, ac: usize, pwr: u8, cpu: u8, gpu: u8) -> bool {
+        let res = self.apply_power_mode(ac, pwr, cpu, gpu);
+        self.sync_power_profile();
+        return res;
+    }
+
+    fn apply_power_mode(&mut self, ac: usize, pwr: u8, cpu: u8, gpu: u8) -> bool {
         let mut res: bool = false;
         if let Some(config) = self.get_config() {
             config.power[ac].power_mode = pwr;
             config.power[ac].cpu_boost = cpu;
             config.power[ac].gpu_boost = gpu;
-            if let Err(e) = config.write_to_file() {
-                eprintln!("Error write config {:?}", e);
-            }
+            config.mark_dirty();
         }
+        let low_power = self.battery_low_power;
         if let Some(laptop) = self.get_device() {
             let state = laptop.get_ac_state();
-            if state != ac {
+            if state != ac || low_power {
                 res = true;
             } else {
                 res = laptop.set_power_mode(pwr, cpu, gpu);
@@ -282,9 +508,7 @@ impl DeviceManager {
         if let Some(config) = self.get_config() {
             config.gui_effect = effect_idx;
             config.gui_effect_params = params;
-            if let Err(e) = config.write_to_file() {
-                eprintln!("Error write config {:?}", e);
-            }
+            config.mark_dirty();
         }
     }
 
@@ -292,9 +516,7 @@ impl DeviceManager {
         if let Some(config) = self.get_config() {
             config.standard_effect = effect_id;
             config.standard_effect_params = params.clone();
-            if let Err(e) = config.write_to_file() {
-                eprintln!("Error write config {:?}", e);
-            }
+            config.mark_dirty();
         }
         if let Some(laptop) = self.get_device() {
             laptop.set_standard_effect(effect_id, params);
@@ -307,9 +529,7 @@ impl DeviceManager {
         let mut res: bool = false;
         if let Some(config) = self.get_config() {
             config.power[ac].fan_rpm = rpm;
-            if let Err(e) = config.write_to_file() {
-                eprintln!("Error write config {:?}", e);
-            }
+            config.mark_dirty();
         }
              
         if let Some(laptop) = self.get_device() {
@@ -332,15 +552,14 @@ impl DeviceManager {
                 let other = (ac + 1) & 0x01;
                 config.power[other].logo_state = logo_state;
             }
-            if let Err(e) = config.write_to_file() {
-                eprintln!("Error write config {:?}", e);
-            }
+            config.mark_dirty();
         }
              
+        let light_off = self.battery_light_off || self.lid_closed;
         if let Some(laptop) = self.get_device() {
             let state = laptop.get_ac_state();
            
-            if state != ac {
+            if state != ac || light_off {
                 res = true;
             } else {
                 res = laptop.set_logo_led_state(logo_state);
@@ -374,19 +593,27 @@ impl DeviceManager {
                 let other = (ac + 1) & 0x01;
                 config.power[other].brightness = _val as u8;
             }
-            if let Err(e) = config.write_to_file() {
-                eprintln!("Error write config {:?}", e);
-            }
+            config.mark_dirty();
         }
  
+        let light_off = self.battery_light_off || self.lid_closed;
+        let mut apply = false;
         if let Some(laptop) = self.get_device() {
             let state = laptop.get_ac_state();
-            if state != ac {
+            if state != ac || light_off {
                 res = true;
             } else {
-                res = laptop.set_brightness(_val as u8);
+                apply = true;
             }
         }
+        if apply {
+            let fade = self.fade_config();
+            self.start_fade(vec![
+                FadeStep { to: _val as u8, delay: time::Duration::ZERO, duration: time::Duration::from_millis(fade.slider_fade_ms as u64) },
+            ], None);
+            self.publish_brightness();
+            res = true;
+        }
 
         return res;
     }
@@ -443,16 +670,28 @@ impl DeviceManager {
     }
 
     pub fn set_ac_state(&mut self, ac: bool) {
+        let mut unplugged = false;
         if let Some(laptop) = self.get_device() {
+            unplugged = laptop.get_ac_state() == 1 && !ac;
             laptop.set_ac_state(ac);
         }
+        if unplugged {
+            self.charge_unplugged();
+        }
         self.change_idle = true;
+        self.cancel_fade();
         let config: Option<config::PowerConfig> = self.get_ac_config(ac as usize);
         if let Some(config) = config {
             if let Some(laptop) = self.get_device() {
                 laptop.set_config(config);
             }
         }
+        self.battery_light_off = false;
+        self.battery_low_power = false;
+        self.apply_battery_rules();
+        self.keep_lid_dark();
+        self.publish_brightness();
+        self.sync_power_profile();
     }
 
     pub fn set_ac_state_get(&mut self) {
@@ -463,21 +702,228 @@ impl DeviceManager {
                 return;
             }
         };
-        let proxy_ac = dbus_system.with_proxy("org.freedesktop.UPower", "/org/freedesktop/UPower/devices/line_power_AC0", time::Duration::from_millis(5000));
-        use battery::OrgFreedesktopUPowerDevice;
-        if let Ok(online) = proxy_ac.online() {
+        let online = match &self.power_supplies {
+            Some(supplies) => supplies.ac_online(&dbus_system),
+            None => power_supply::PowerSupplies::discover(&dbus_system).ac_online(&dbus_system),
+        };
+        if let Some(online) = online {
+            let mut unplugged = false;
             if let Some(laptop) = self.get_device() {
+                unplugged = laptop.get_ac_state() == 1 && !online;
                 laptop.set_ac_state(online);
             }
+            if unplugged {
+                self.charge_unplugged();
+            }
             self.change_idle = true;
+            self.cancel_fade();
             let config: Option<config::PowerConfig> = self.get_ac_config(online as usize);
             if let Some(config) = config {
                 if let Some(laptop) = self.get_device() {
                     laptop.set_config(config);
                 }
             }
+            self.battery_light_off = false;
+            self.battery_low_power = false;
+            self.apply_battery_rules();
+            self.keep_lid_dark();
+            self.publish_brightness();
+            self.sync_power_profile();
+        }
+
+    }
+
+    pub fn set_battery_percentage(&mut self, perc: f64) {
+        self.battery_percentage = perc;
+        self.apply_battery_rules();
+        self.check_charge_override();
+    }
+
+    pub fn set_battery_charge_done(&mut self, done: bool) {
+        self.battery_charge_done = done;
+        self.check_charge_override();
+    }
+
+    pub fn set_battery_rules(&mut self, no_light: u8, low_power: u8, power_mode: u8) -> bool {
+        if no_light > 100 || low_power > 100 || power_mode > 3 {
+            return false;
+        }
+        if let Some(config) = self.get_config() {
+            config.no_light = no_light as f64;
+            config.low_power = low_power as f64;
+            config.low_power_mode = power_mode;
+            config.mark_dirty();
+        }
+        self.apply_battery_rules();
+
+        return true;
+    }
+
+    pub fn get_battery_rules(&mut self) -> (u8, u8, u8) {
+        if let Some(config) = self.get_config() {
+            return (config.no_light as u8, config.low_power as u8, config.low_power_mode);
+        }
+
+        return (0, 0, 3);
+    }
+
+    /// Brightness of the active profile for the desktop's keyboard backlight
+    pub fn publish_brightness(&mut self) {
+        let ac = self.get_device().map_or(0, |laptop| laptop.get_ac_state());
+        let brightness = self.get_brightness(ac);
+        kbd_backlight::publish(brightness);
+    }
+
+    // Loading a power profile restores its lighting, undo that while the lid is closed
+    fn keep_lid_dark(&mut self) {
+        if self.lid_closed {
+            self.cancel_fade();
+            if let Some(laptop) = self.get_device() {
+                laptop.set_brightness(0);
+                laptop.set_logo_led_state(0);
+            }
+        }
+    }
+
+    /// Turns the lights off and/or drops to the low power mode when the battery
+    /// falls below the configured percentages. Rules only apply on battery,
+    /// plugging in restores the AC profile through `set_ac_state`.
+    fn apply_battery_rules(&mut self) {
+        let (no_light, low_power, low_power_mode) = match self.get_config() {
+            Some(config) => (config.no_light, config.low_power, config.low_power_mode),
+            None => return,
+        };
+        let on_battery = self.get_device().map_or(false, |laptop| laptop.get_ac_state() == 0);
+        let perc = self.battery_percentage;
+
+        let light_off = on_battery && perc < no_light;
+        if light_off != self.battery_light_off {
+            self.battery_light_off = light_off;
+            if light_off {
+                println!("battery at {:.1}%, turning lights off", perc);
+                self.cancel_fade();
+                if let Some(laptop) = self.get_device() {
+                    laptop.set_brightness(0);
+                    laptop.set_logo_led_state(0);
+                }
+            } else if on_battery && !self.get_device().map_or(false, |laptop| laptop.is_screensaver()) {
+                self.restore_light();
+            }
+        }
+
+        let low = on_battery && perc < low_power;
+        if low != self.battery_low_power {
+            self.battery_low_power = low;
+            if low {
+                println!("battery at {:.1}%, switching to power mode {}", perc, low_power_mode);
+                if let Some(laptop) = self.get_device() {
+                    laptop.set_power_mode(low_power_mode, 0, 0);
+                }
+            } else if on_battery {
+                if let Some(config) = self.get_ac_config(0) {
+                    if let Some(laptop) = self.get_device() {
+                        laptop.set_power_mode(config.power_mode, config.cpu_boost, config.gpu_boost);
+                        laptop.set_fan_rpm(config.fan_rpm as u16);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Called with the ActiveProfile of power-profiles-daemon whenever it changes
+    pub fn power_profile_changed(&mut self, profile: &str) {
+        // Nothing new, or the change we made ourselves in sync_power_profile
+        if self.ppd_profile == profile {
+            return;
+        }
+        self.ppd_profile = profile.to_string();
+        let mapping = match self.get_config() {
+            Some(config) if config.power_profiles.follow => {
+                config.power_profiles.get_mut(profile).map(|m| *m)
+            },
+            _ => None,
+        };
+        if let Some(m) = mapping {
+            println!("power profile {}, power mode {}", profile, m.power_mode);
+            let ac = self.get_device().map_or(0, |laptop| laptop.get_ac_state());
+            self.apply_power_mode(ac, m.power_mode, m.cpu_boost, m.gpu_boost);
+            self.set_fan_rpm(ac, m.fan_rpm);
+        }
+    }
+
+    /// Sets the desktop profile to match the power mode in use
+    fn sync_power_profile(&mut self) {
+        if let Some(ac) = self.get_device().map(|laptop| laptop.get_ac_state()) {
+            self.sync_power_profile_for(ac);
+        }
+    }
+
+    fn sync_power_profile_for(&mut self, ac: usize) {
+        let profile = match self.get_config() {
+            Some(config) if config.power_profiles.sync_back => {
+                config.power_profiles.profile_for(config.power[ac].power_mode)
+            },
+            _ => return,
+        };
+        if self.ppd_profile == profile {
+            return;
+        }
+        // Remember it first so the PropertiesChanged signal it causes is ignored
+        self.ppd_profile = profile.to_string();
+        self.ppd_request = Some(power_profiles::Request::Set(profile.to_string()));
+    }
+
+    /// Takes the call power-profiles-daemon is waiting for, the power profiles
+    /// task makes it without holding the device manager
+    pub fn take_power_profile_request(&mut self) -> Option<power_profiles::Request> {
+        return self.ppd_request.take();
+    }
+
+    pub fn set_power_profiles(&mut self, follow: bool, sync_back: bool) -> bool {
+        let was_following = match self.get_config() {
+            Some(config) => {
+                let was_following = config.power_profiles.follow;
+                config.power_profiles.follow = follow;
+                config.power_profiles.sync_back = sync_back;
+                config.mark_dirty();
+                was_following
+            },
+            None => return false,
+        };
+        if follow && !was_following {
+            // Catch up with the profile the desktop has right now
+            self.ppd_profile.clear();
+            self.ppd_request = Some(power_profiles::Request::Read);
+        } else {
+            self.sync_power_profile();
         }
+        return true;
+    }
 
+    pub fn set_profile_mapping(&mut self, profile: &str, mapping: config::ProfileMapping) -> bool {
+        let follow = match self.get_config() {
+            Some(config) => match config.power_profiles.get_mut(profile) {
+                Some(m) => {
+                    *m = mapping;
+                    config.mark_dirty();
+                    config.power_profiles.follow
+                },
+                None => return false,
+            },
+            None => return false,
+        };
+        // Apply right away if the profile is the active one
+        if follow && self.ppd_profile == profile {
+            self.ppd_profile.clear();
+            self.power_profile_changed(profile);
+        }
+        return true;
+    }
+
+    /// Returns the power profiles settings and the active desktop profile
+    pub fn get_power_profiles(&mut self) -> Option<(config::PowerProfilesConfig, String)> {
+        let profile = self.ppd_profile.clone();
+        return self.get_config().map(|config| (config.power_profiles, profile));
     }
 
     pub fn get_device(&mut self) -> Option<&mut RazerLaptop> {
@@ -485,20 +931,124 @@ impl DeviceManager {
     }
 
     pub fn set_bho_handler(&mut self, is_on: bool, threshold: u8) -> bool {
-        let result = self.get_device()
-            .map_or(false, |laptop| laptop.set_bho(is_on, threshold));
-        if result {
-            if let Some(config) = self.get_config() {
-                config.bho_on = is_on;
-                config.bho_threshold = threshold;
-                if let Err(e) = config.write_to_file() {
-                    eprintln!("Error write config {:?}", e);
-                }
+        let old = match self.get_config() {
+            Some(config) => (config.bho_on, config.bho_threshold),
+            None => return false,
+        };
+        if let Some(config) = self.get_config() {
+            config.bho_on = is_on;
+            config.bho_threshold = threshold;
+        }
+        let result = self.apply_charge_limit();
+        if let Some(config) = self.get_config() {
+            if result {
+                config.mark_dirty();
+            } else {
+                (config.bho_on, config.bho_threshold) = old;
+            }
+        }
+        return result;
+    }
+
+    /// The threshold to charge to right now, BHO unless an override is active
+    pub fn charge_limit(&mut self) -> (bool, u8) {
+        let now = config::unix_now();
+        match self.get_config() {
+            Some(config) if config.charge_override.lifted(now) => (false, config.bho_threshold),
+            Some(config) => (config.bho_on, config.bho_threshold),
+            None => (false, 80),
+        }
+    }
+
+    /// Sets the charge limit in the EC and mirrors it to the kernel's
+    /// charge_control_end_threshold, where other tools look for it
+    fn apply_charge_limit(&mut self) -> bool {
+        let now = config::unix_now();
+        self.charge_lifted = self.get_config().map_or(false, |c| c.charge_override.lifted(now));
+        let (is_on, threshold) = self.charge_limit();
+        if !self.get_device().map_or(false, |laptop| laptop.set_bho(is_on, threshold)) {
+            return false;
+        }
+        let percent = if is_on { threshold } else { 100 };
+        for battery in service::sensors::threshold_batteries() {
+            if service::sensors::read_charge_threshold(&battery) == Some(percent) {
+                continue;
+            }
+            match service::sensors::write_charge_threshold(&battery, percent) {
+                Ok(()) => println!("{} charge threshold: {}%", battery, percent),
+                Err(e) => eprintln!("Cannot set the charge threshold of {}: {}", battery, e),
+            }
+        }
+        return true;
+    }
+
+    pub fn set_charge_override(&mut self, mode: config::ChargeOverride) -> bool {
+        if !self.get_device().map_or(false, |laptop| laptop.have_feature("bho".to_string())) {
+            return false;
+        }
+        if let Some(config) = self.get_config() {
+            if config.charge_override != mode {
+                println!("charge override: {}", mode.name());
+                config.charge_override = mode;
+                config.mark_dirty();
             }
         }
+        let result = self.apply_charge_limit();
+        self.check_charge_override();
         return result;
     }
 
+    /// Ends or starts lifting the threshold as the override says, called
+    /// periodically and whenever the battery percentage changes
+    pub fn check_charge_override(&mut self) {
+        let mode = self.get_config().map_or(config::ChargeOverride::Off, |c| c.charge_override);
+        let now = config::unix_now();
+        // Many packs stop short of 100 % or only report that they are full.
+        // The charger also stops at the threshold, so a stop only counts near full.
+        let full = self.battery_percentage >= 99.5
+            || (self.battery_charge_done && self.battery_percentage >= FULL_ONCE_MIN_PERCENT);
+        if mode == config::ChargeOverride::FullOnce && full {
+            println!("battery full, returning to the charge threshold");
+            self.set_charge_override(config::ChargeOverride::Off);
+        } else if mode.expired(now) {
+            println!("charge override expired, returning to the charge threshold");
+            self.set_charge_override(config::ChargeOverride::Off);
+        } else if mode.lifted(now) != self.charge_lifted {
+            self.apply_charge_limit();
+        }
+    }
+
+    fn charge_unplugged(&mut self) {
+        let mode = self.get_config().map_or(config::ChargeOverride::Off, |c| c.charge_override);
+        if mode.ends_on_unplug(config::unix_now()) {
+            println!("unplugged, returning to the charge threshold");
+            self.set_charge_override(config::ChargeOverride::Off);
+        }
+    }
+
+    /// Configured BHO, the limit the EC reports, the override and the
+    /// kernel thresholds of the batteries
+    pub fn get_charge_threshold(&mut self) -> Option<(bool, u8, u8, config::ChargeOverride, Vec<(String, u8)>)> {
+        let (bho_on, threshold, mode) = match self.get_config() {
+            Some(config) => (config.bho_on, config.bho_threshold, config.charge_override),
+            None => return None,
+        };
+        let effective = match self.get_device().and_then(|laptop| laptop.get_bho()) {
+            Some(byte) => match byte_to_bho(byte) {
+                (true, limit) => limit,
+                (false, _) => 100,
+            },
+            None => match self.charge_limit() {
+                (true, limit) => limit,
+                (false, _) => 100,
+            },
+        };
+        let batteries = service::sensors::threshold_batteries().into_iter()
+            .filter_map(|battery| service::sensors::read_charge_threshold(&battery).map(|t| (battery, t)))
+            .collect();
+        return Some((bho_on, threshold, effective, mode, batteries));
+    }
+
     pub fn get_bho_handler(&mut self) -> Option<(bool, u8)> {
         // Check if device supports BHO
         let has_bho = self.get_device()
@@ -513,16 +1063,14 @@ impl DeviceManager {
     }
 
     pub fn restore_bho(&mut self) {
-        let (bho_on, bho_threshold) = {
+        let (bho_on, mode) = {
             match self.get_config() {
-                Some(config) => (config.bho_on, config.bho_threshold),
+                Some(config) => (config.bho_on, config.charge_override),
                 None => return,
             }
         };
-        if bho_on {
-            if let Some(laptop) = self.get_device() {
-                laptop.set_bho(bho_on, bho_threshold);
-            }
+        if bho_on || mode != config::ChargeOverride::Off {
+            self.apply_charge_limit();
         }
     }
 
@@ -530,6 +1078,67 @@ impl DeviceManager {
         return self.config.as_mut();
     }
 
+    /// Key layout set in the configuration, or the one of the device
+    pub fn key_layout(&mut self) -> String {
+        if let Some(config) = self.get_config() {
+            if !config.key_layout.is_empty() {
+                return config.key_layout.clone();
+            }
+        }
+        return self.get_device().map_or(String::new(), |laptop| laptop.get_layout());
+    }
+
+    pub fn export_bundle(&mut self, effects: serde_json::Value) -> Option<String> {
+        let device = self.get_device().map_or(String::new(), |laptop| laptop.get_name());
+        let config = serde_json::to_value(self.config.as_ref()?).ok()?;
+        let bundle = config::Bundle {
+            format: config::BUNDLE_FORMAT.to_string(),
+            version: config::BUNDLE_VERSION,
+            device,
+            config,
+            effects,
+        };
+        serde_json::to_string_pretty(&bundle).ok()
+    }
+
+    /// Lists the settings in `config` this laptop does not support
+    pub fn check_capabilities(&mut self, config: &config::Configuration) -> Vec<String> {
+        match self.get_device() {
+            Some(laptop) => laptop.check_capabilities(config),
+            None => vec!["No supported device found".to_string()],
+        }
+    }
+
+    /// Replaces the configuration with one edited outside the daemon and
+    /// applies whatever changed to the hardware
+    pub fn apply_config(&mut self, config: config::Configuration) {
+        let (old_effect, old_bho) = match self.get_config() {
+            Some(c) => ((c.standard_effect, c.standard_effect_params.clone()), (c.bho_on, c.bho_threshold, c.charge_override)),
+            None => ((0, vec![]), (false, 0, config::ChargeOverride::Off)),
+        };
+        let new_effect = (config.standard_effect, config.standard_effect_params.clone());
+        let new_bho = (config.bho_on, config.bho_threshold, config.charge_override);
+        self.config = Some(config);
+
+        let ac = self.get_device().map_or(false, |laptop| laptop.get_ac_state() == 1);
+        self.set_ac_state(ac);
+        if old_effect != new_effect {
+            self.restore_standard_effect();
+        }
+        if old_bho != new_bho {
+            self.apply_charge_limit();
+        }
+    }
+
+    /// Writes the configuration if it changed and then stayed untouched for `settle`
+    pub fn flush_config(&mut self, settle: time::Duration) {
+        if let Some(config) = self.get_config() {
+            if let Err(e) = config.flush(settle) {
+                eprintln!("Error write config {:?}", e);
+            }
+        }
+    }
+
     // pub fn set_device(&mut self, device: RazerLaptop) {
         // self.device = Some(device);
     // }
@@ -567,6 +1176,9 @@ impl DeviceManager {
                                     supported_device.name.clone(),
                                     supported_device.features.clone(),
                                     supported_device.fan.clone(),
+                                    supported_device.layout.clone(),
+                                    supported_device.lighting,
+                                    supported_device.matrix,
                                     dev
                                 ));
                                 break;
@@ -589,11 +1201,16 @@ pub struct RazerLaptop {
     name: String,
     features: Vec<String>,
     fan: Vec<u16>,
+    layout: String,
+    lighting: Lighting,
+    matrix: [usize; 2],
     device: hidapi::HidDevice,
     power: u8, // need for fan
     fan_rpm: u8, // need for power
     ac_state: u8, // index config array
     screensaver: bool,
+    brightness: u8, // last brightness sent to the keyboard
+    zone_colour: Option<[u8; 3]>, // last frame sent to a single zone keyboard
 }
 //
 impl RazerLaptop {
@@ -612,19 +1229,34 @@ impl RazerLaptop {
     pub const SPECTRUM:u8 = 0x04;
     pub const CUSTOMFRAME:u8 = 0x05;
     pub const STATIC:u8 = 0x06;
+    // Where the colours of a custom frame row start in the args
+    const FRAME_DATA: usize = 7;
+    const FRAME_DATA_PACKED: usize = 4;
+    /// Most columns a custom frame row can carry
+    pub const MAX_FRAME_COLUMNS: usize = (80 - RazerLaptop::FRAME_DATA_PACKED) / 3;
     #[allow(dead_code)]
     pub const STARLIGHT:u8 = 0x19;
 
-    pub fn new(name: String, features: Vec<String>, fan: Vec<u16>, device: hidapi::HidDevice) -> RazerLaptop {
+    pub fn new(name: String, features: Vec<String>, fan: Vec<u16>, layout: String, lighting: Lighting, matrix: [usize; 2], device: hidapi::HidDevice) -> RazerLaptop {
+        let mut matrix = matrix;
+        if matrix[1] > RazerLaptop::MAX_FRAME_COLUMNS {
+            eprintln!("{} columns do not fit into a custom frame, only the first {} are lit", matrix[1], RazerLaptop::MAX_FRAME_COLUMNS);
+            matrix[1] = RazerLaptop::MAX_FRAME_COLUMNS;
+        }
         return RazerLaptop{
             name,
             features,
             fan,
+            layout,
+            lighting,
+            matrix,
             device,
             power: 0,
             fan_rpm: 0,
             ac_state: 0,
-            screensaver: false
+            screensaver: false,
+            brightness: 0,
+            zone_colour: None,
         };
     }
 
@@ -632,6 +1264,10 @@ impl RazerLaptop {
         self.screensaver = active;
     }
 
+    pub fn is_screensaver(&self) -> bool {
+        return self.screensaver;
+    }
+
     pub fn set_config(&mut self, config: config::PowerConfig) -> bool {
         let mut ret: bool = false;
 
@@ -666,10 +1302,57 @@ impl RazerLaptop {
         return self.name.clone();
     }
 
+    /// Key layout from the device database
+    pub fn get_layout(&self) -> String {
+        return self.layout.clone();
+    }
+
+    pub fn get_lighting(&self) -> Lighting {
+        return self.lighting;
+    }
+
+    /// Rows and columns of the keyboard matrix
+    pub fn get_matrix(&self) -> [usize; 2] {
+        return self.matrix;
+    }
+
     pub fn have_feature(&mut self, fch: String) -> bool {
         return self.features.contains(&fch);
     }
 
+    pub fn check_capabilities(&mut self, config: &config::Configuration) -> Vec<String> {
+        let mut problems = vec![];
+        if config.bho_on && !self.have_feature("bho".to_string()) {
+            problems.push("Battery health optimizer is not supported".to_string());
+        }
+        let fan_min = *self.fan.get(0).unwrap_or(&0) as i32;
+        let fan_max = *self.fan.get(1).unwrap_or(&0) as i32;
+        for (ac, power) in config.power.iter().enumerate() {
+            let state = if ac == 1 { "ac" } else { "bat" };
+            if power.power_mode == 2 && !self.have_feature("creator_mode".to_string()) {
+                problems.push(format!("{}: creator mode is not supported", state));
+            }
+            if power.cpu_boost == 3 && !self.have_feature("boost".to_string()) {
+                problems.push(format!("{}: cpu boost 3 is not supported", state));
+            }
+            if power.fan_rpm != 0 && (power.fan_rpm < fan_min || power.fan_rpm > fan_max) {
+                problems.push(format!("{}: fan rpm {} is outside {} - {}", state, power.fan_rpm, fan_min, fan_max));
+            }
+            if power.logo_state != 0 && !self.have_feature("logo".to_string()) {
+                problems.push(format!("{}: logo LED is not supported", state));
+            }
+        }
+        for (name, mapping) in config.power_profiles.mappings() {
+            if mapping.power_mode == 2 && !self.have_feature("creator_mode".to_string()) {
+                problems.push(format!("{}: creator mode is not supported", name));
+            }
+            if mapping.fan_rpm != 0 && (mapping.fan_rpm < fan_min || mapping.fan_rpm > fan_max) {
+                problems.push(format!("{}: fan rpm {} is outside {} - {}", name, mapping.fan_rpm, fan_min, fan_max));
+            }
+        }
+        return problems;
+    }
+
     fn clamp_fan(&mut self, rpm: u16) -> u8 {
         if rpm > self.fan[1] {
             return (self.fan[1] / 100) as u8;
@@ -693,6 +1376,11 @@ impl RazerLaptop {
     }
 
     pub fn set_standard_effect(&mut self, effect_id: u8, params: Vec<u8>) -> bool {
+        self.zone_colour = None; // the next zone frame has to be sent again
+        return self.send_standard_effect(effect_id, params);
+    }
+
+    fn send_standard_effect(&mut self, effect_id: u8, params: Vec<u8>) -> bool {
         let mut report: RazerPacket = RazerPacket::new(0x03, 0x0a, 80);
         report.args[0] = effect_id; // effect id
         if !params.is_empty() {
@@ -709,20 +1397,42 @@ impl RazerLaptop {
     }
 
     pub fn set_custom_frame_data(&mut self, row: u8, data: Vec<u8>) {
-        // if data.len() == kbd::board::KEYS_PER_ROW {
-        if data.len() == 45 {
-            let mut report: RazerPacket = RazerPacket::new(0x03, 0x0b, 0x34);
-            report.args[0] = 0xff;
-            report.args[1] = row;
-            report.args[2] = 0x00; // start col
-            report.args[3] = 0x0f; // end col
-            for idx in 0..data.len() {
-                report.args[idx + 7] = data[idx];
-            }
+        if let Some(report) = RazerLaptop::custom_frame_report(row, &data) {
             self.send_report(report);
         }
     }
 
+    /// Packet with the RGB of every column of a row. Rows too long to start
+    /// after the padding follow the end column directly, as OpenRazer sends
+    /// the 25 columns of the Blade Pro 2017.
+    fn custom_frame_report(row: u8, data: &[u8]) -> Option<RazerPacket> {
+        if data.is_empty() || data.len() % 3 != 0 || data.len() > 80 - RazerLaptop::FRAME_DATA_PACKED {
+            return None;
+        }
+        let offset = if data.len() <= 80 - RazerLaptop::FRAME_DATA {
+            RazerLaptop::FRAME_DATA
+        } else {
+            RazerLaptop::FRAME_DATA_PACKED
+        };
+        let mut report: RazerPacket = RazerPacket::new(0x03, 0x0b, (offset + data.len()) as u8);
+        report.args[0] = 0xff;
+        report.args[1] = row;
+        report.args[2] = 0x00; // start col
+        report.args[3] = (data.len() / 3) as u8; // end col, 0x0f for 15 columns
+        report.args[offset..offset + data.len()].copy_from_slice(data);
+        return Some(report);
+    }
+
+    /// Shows a frame on a single zone keyboard as a static colour,
+    /// only sending it when the colour changes
+    pub fn set_zone_colour(&mut self, rgb: [u8; 3]) -> bool {
+        if self.zone_colour == Some(rgb) {
+            return true;
+        }
+        self.zone_colour = Some(rgb);
+        return self.send_standard_effect(RazerLaptop::STATIC, rgb.to_vec());
+    }
+
     pub fn set_custom_frame(&mut self) -> bool {
         let mut report: RazerPacket = RazerPacket::new(0x03, 0x0a, 0x02);
         report.args[0] = RazerLaptop::CUSTOMFRAME; // effect id
@@ -920,7 +1630,16 @@ impl RazerLaptop {
         return 0;
     }
 
+    pub fn current_brightness(&self) -> u8 {
+        return self.brightness;
+    }
+
+    pub fn current_power_mode(&self) -> u8 {
+        return self.power;
+    }
+
     pub fn set_brightness(&mut self, brightness: u8) -> bool {
+        self.brightness = brightness;
         let mut report: RazerPacket = RazerPacket::new(0x03, 0x03, 0x03);
         report.args[0] = RazerLaptop::VARSTORE;
         report.args[1] = RazerLaptop::BACKLIGHT_LED;
@@ -944,7 +1663,6 @@ impl RazerLaptop {
         return 0;
     }
 
-    #[allow(dead_code)]
     pub fn get_bho(&mut self) -> Option<u8> {
         if !self.have_feature("bho".to_string()) {
             return None;
@@ -1029,7 +1747,6 @@ impl RazerLaptop {
 
 // top bit flags whether battery health optimization is on or off
 // bottom bits are the actual threshold that it is set to
-#[allow(dead_code)]
 fn byte_to_bho(u: u8) -> (bool, u8) {
     return (u & (1 << 7) != 0, (u & 0b0111_1111));
 }
@@ -1040,3 +1757,96 @@ fn bho_to_byte(is_on: bool, threshold: u8) -> u8 {
     }
     return threshold;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kbd::board;
+
+    fn manager() -> DeviceManager {
+        let mut manager = DeviceManager::new();
+        manager.config = Some(config::Configuration::new());
+        manager
+    }
+
+    #[test]
+    fn desktop_profiles_map_to_power_modes() {
+        let mut manager = manager();
+        // Not following, the profile is only remembered
+        manager.power_profile_changed(power_profiles::PERFORMANCE);
+        assert_eq!(manager.config.as_ref().unwrap().power[0].power_mode, 0);
+
+        // Following asks for the active profile first
+        assert!(manager.set_power_profiles(true, false));
+        assert_eq!(manager.take_power_profile_request(), Some(power_profiles::Request::Read));
+        assert_eq!(manager.take_power_profile_request(), None);
+
+        manager.power_profile_changed(power_profiles::PERFORMANCE);
+        let power = manager.config.as_ref().unwrap().power[0];
+        assert_eq!((power.power_mode, power.cpu_boost, power.gpu_boost), (1, 1, 0));
+        manager.power_profile_changed(power_profiles::POWER_SAVER);
+        assert_eq!(manager.config.as_ref().unwrap().power[0].power_mode, 3);
+        manager.power_profile_changed("unknown");
+        assert_eq!(manager.config.as_ref().unwrap().power[0].power_mode, 3);
+
+        // A new mapping of the active profile applies right away
+        let mapping = config::ProfileMapping { power_mode: 2, cpu_boost: 0, gpu_boost: 0, fan_rpm: 3500 };
+        assert!(!manager.set_profile_mapping("unknown", mapping));
+        assert!(manager.set_profile_mapping(power_profiles::BALANCED, mapping));
+        assert_eq!(manager.config.as_ref().unwrap().power[0].power_mode, 3);
+        manager.power_profile_changed(power_profiles::BALANCED);
+        let power = manager.config.as_ref().unwrap().power[0];
+        assert_eq!((power.power_mode, power.fan_rpm), (2, 3500));
+    }
+
+    #[test]
+    fn power_modes_sync_back_once() {
+        let mut manager = manager();
+        manager.config.as_mut().unwrap().power[1].power_mode = 3;
+        manager.sync_power_profile_for(1);
+        assert_eq!(manager.take_power_profile_request(), None, "sync back is off");
+
+        assert!(manager.set_power_profiles(false, true));
+        manager.take_power_profile_request();
+        manager.sync_power_profile_for(1);
+        assert_eq!(manager.take_power_profile_request(), Some(power_profiles::Request::Set(power_profiles::POWER_SAVER.to_string())));
+        manager.sync_power_profile_for(1);
+        assert_eq!(manager.take_power_profile_request(), None, "already set");
+
+        // The signal of our own change is not applied again
+        manager.config.as_mut().unwrap().power_profiles.follow = true;
+        manager.config.as_mut().unwrap().power[0].power_mode = 1;
+        manager.power_profile_changed(power_profiles::POWER_SAVER);
+        assert_eq!(manager.config.as_ref().unwrap().power[0].power_mode, 1);
+    }
+
+    #[test]
+    fn every_matrix_fits_a_custom_frame() {
+        let devices: Vec<SupportedDevice> = serde_json::from_str(include_str!("../../data/devices/laptops.json")).unwrap();
+        for device in devices.iter().filter(|d| d.lighting == Lighting::PerKey) {
+            let [rows, columns] = device.matrix;
+            for row in 0..rows {
+                let data = board::RowData::new(columns).get_row_data();
+                let report = RazerLaptop::custom_frame_report(row as u8, &data);
+                assert!(report.is_some(), "{} with {} columns", device.name, columns);
+            }
+        }
+    }
+
+    #[test]
+    fn long_rows_are_packed() {
+        let data: Vec<u8> = (0..75).collect();
+        let report = RazerLaptop::custom_frame_report(2, &data).unwrap();
+        assert_eq!(report.args[..4], [0xff, 2, 0, 25]);
+        assert_eq!(report.args[4..79], data[..]);
+        assert_eq!(report.data_size, 79);
+
+        let report = RazerLaptop::custom_frame_report(0, &data[..45]).unwrap();
+        assert_eq!(report.args[7..52], data[..45]);
+        assert_eq!(report.data_size, 52);
+
+        assert!(RazerLaptop::custom_frame_report(0, &[0; 78]).is_none());
+        assert!(RazerLaptop::custom_frame_report(0, &[0; 4]).is_none());
+        assert!(RazerLaptop::custom_frame_report(0, &[]).is_none());
+    }
+}
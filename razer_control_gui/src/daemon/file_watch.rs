@@ -0,0 +1,75 @@
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+const HEADER_LEN: usize = std::mem::size_of::<libc::inotify_event>();
+
+/// Watches directories with inotify and reports files written in them.
+/// Directories are watched instead of the files themselves so replacing a
+/// file by renaming over it, like editors and `config::write_atomic` do, is seen.
+pub struct FileWatch {
+    fd: i32,
+}
+
+impl FileWatch {
+    pub fn new(dirs: &[PathBuf]) -> io::Result<FileWatch> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let watch = FileWatch { fd };
+        for dir in dirs {
+            let path = CString::new(dir.as_os_str().as_bytes())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+            if unsafe { libc::inotify_add_watch(fd, path.as_ptr(), mask) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(watch)
+    }
+
+    /// Blocks until at least one file is written and returns the file names
+    pub fn wait(&self) -> io::Result<Vec<String>> {
+        let mut buf = [0u8; 4096];
+        let len = loop {
+            let res = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if res >= 0 {
+                break res as usize;
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        };
+
+        let mut names: Vec<String> = vec![];
+        let mut pos = 0;
+        while pos + HEADER_LEN <= len {
+            // SAFETY: the kernel only returns complete events, the header is copied out unaligned
+            let event: libc::inotify_event = unsafe {
+                std::ptr::read_unaligned(buf[pos..].as_ptr() as *const libc::inotify_event)
+            };
+            let name_start = pos + HEADER_LEN;
+            let name_end = (name_start + event.len as usize).min(len);
+            // The name is padded with NUL bytes
+            let raw = &buf[name_start..name_end];
+            let raw = &raw[..raw.iter().position(|b| *b == 0).unwrap_or(raw.len())];
+            let name = OsStr::from_bytes(raw).to_string_lossy().to_string();
+            if !name.is_empty() && !names.contains(&name) {
+                names.push(name);
+            }
+            pos = name_end;
+        }
+        Ok(names)
+    }
+}
+
+impl Drop for FileWatch {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
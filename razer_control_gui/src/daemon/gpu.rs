@@ -60,9 +60,7 @@ pub fn discover_gpus() -> Vec<GpuInfo> {
             _ => "unknown".to_string(),
         };
 
-        // Get runtime PM status
-        let runtime_status = read_sysfs_trimmed(&dev_path.join("power/runtime_status"))
-            .unwrap_or_else(|| "unsupported".to_string());
+        let runtime_status = runtime_status(&pci_slot);
 
         // Build a human-readable name
         let name = resolve_gpu_name(vendor.as_deref(), device_id.as_deref(), &driver);
@@ -81,6 +79,12 @@ pub fn discover_gpus() -> Vec<GpuInfo> {
     gpus
 }
 
+/// Runtime PM status of a GPU, read from sysfs without waking it up
+pub fn runtime_status(pci_slot: &str) -> String {
+    read_sysfs_trimmed(&Path::new("/sys/bus/pci/devices").join(pci_slot).join("power/runtime_status"))
+        .unwrap_or_else(|| "unsupported".to_string())
+}
+
 /// Find the first dGPU PCI slot
 fn find_dgpu_path() -> Option<std::path::PathBuf> {
     let gpus = discover_gpus();
@@ -0,0 +1,243 @@
+// Audio spectrum for the spectrum effect. A daemon task captures what the
+// laptop plays, or reads raw PCM from a FIFO or file, and stores the level
+// of each band here; the effects only read the last levels when drawing.
+// Captured audio is analysed in memory and never stored.
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{self, Read};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+pub const BANDS: usize = 24;
+pub const FLOOR_DB: f32 = -60.0; // silence, levels are dB below full scale
+pub const MIN_RATE: u32 = 8000; // sample rates in Hz
+pub const MAX_RATE: u32 = 192000;
+const FFT_SIZE: usize = 1024;
+const HOP: usize = FFT_SIZE / 2; // windows overlap by half
+const MIN_HZ: f32 = 40.0;
+const MAX_HZ: f32 = 12000.0;
+
+static LEVELS: Mutex<Vec<f32>> = Mutex::new(Vec::new());
+
+/// Level of each band in dB, from low to high frequencies. Empty while
+/// nothing is captured.
+pub fn levels() -> Vec<f32> {
+    match LEVELS.lock() {
+        Ok(levels) => levels.clone(),
+        Err(e) => e.into_inner().clone(),
+    }
+}
+
+pub fn set_levels(levels: Vec<f32>) {
+    if let Ok(mut l) = LEVELS.lock() {
+        *l = levels;
+    }
+}
+
+/// Signed 16 bit little endian mono PCM from a recorder or a file
+pub struct Capture {
+    child: Option<Child>,
+    reader: Box<dyn Read + Send>,
+    pace: Option<Duration>, // files are read in real time, FIFOs and recorders set their own pace
+    path: Option<String>, // files start over at their end
+}
+
+impl Capture {
+    /// `input` is a FIFO or file, or empty for the monitor of the default
+    /// output through PulseAudio or PipeWire
+    pub fn open(input: &str, rate: u32) -> io::Result<Capture> {
+        if !(MIN_RATE..=MAX_RATE).contains(&rate) {
+            let error = format!("audio rate {} Hz is not between {} and {} Hz", rate, MIN_RATE, MAX_RATE);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, error));
+        }
+        if !input.is_empty() {
+            let file = File::open(input)?;
+            let pace = if file.metadata()?.is_file() {
+                Some(Duration::from_secs_f64(HOP as f64 / rate as f64))
+            } else {
+                None
+            };
+            return Ok(Capture { child: None, reader: Box::new(file), pace, path: Some(input.to_string()) });
+        }
+        let rate = rate.to_string();
+        let recorders: [(&str, Vec<String>); 2] = [
+            ("parec", vec!["--device=@DEFAULT_MONITOR@".into(), "--format=s16le".into(), format!("--rate={}", rate), "--channels=1".into(), "--raw".into()]),
+            ("pw-record", vec!["-P".into(), "{ stream.capture.sink=true }".into(), "--format=s16".into(), format!("--rate={}", rate), "--channels=1".into(), "-".into()]),
+        ];
+        let mut error = io::Error::new(io::ErrorKind::NotFound, "no recorder");
+        for (program, args) in recorders {
+            match Command::new(program).args(&args).stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::null()).spawn() {
+                Ok(mut child) => {
+                    let stdout: ChildStdout = match child.stdout.take() {
+                        Some(s) => s,
+                        None => continue,
+                    };
+                    println!("audio spectrum captured with {}", program);
+                    return Ok(Capture { child: Some(child), reader: Box::new(stdout), pace: None, path: None });
+                }
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    /// Reads the next `HOP` samples
+    pub fn read(&mut self) -> io::Result<Vec<i16>> {
+        let mut buf = [0u8; HOP * 2];
+        match self.reader.read_exact(&mut buf) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && self.pace.is_some() => {
+                if let Some(path) = &self.path {
+                    self.reader = Box::new(File::open(path)?);
+                }
+                self.reader.read_exact(&mut buf)?;
+            }
+            result => result?,
+        }
+        if let Some(pace) = self.pace {
+            thread::sleep(pace);
+        }
+        Ok(buf.chunks_exact(2).map(|s| i16::from_le_bytes([s[0], s[1]])).collect())
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Band levels of the last `FFT_SIZE` samples
+pub struct Analyser {
+    rate: f32,
+    samples: Vec<f32>,
+    window: Vec<f32>, // Hann
+}
+
+impl Analyser {
+    pub fn new(rate: u32) -> Analyser {
+        Analyser {
+            rate: rate.clamp(MIN_RATE, MAX_RATE) as f32,
+            samples: Vec::with_capacity(FFT_SIZE + HOP),
+            window: (0..FFT_SIZE).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FFT_SIZE as f32).cos()).collect(),
+        }
+    }
+
+    /// Adds samples, returns the band levels once a window is full
+    pub fn push(&mut self, samples: &[i16]) -> Option<Vec<f32>> {
+        self.samples.extend(samples.iter().map(|s| *s as f32 / 32768.0));
+        if self.samples.len() < FFT_SIZE {
+            return None;
+        }
+        let start = self.samples.len() - FFT_SIZE;
+        let mut re: Vec<f32> = self.samples[start..].iter().zip(&self.window).map(|(s, w)| s * w).collect();
+        let mut im = vec![0.0; FFT_SIZE];
+        self.samples.drain(..self.samples.len() - (FFT_SIZE - HOP));
+        fft(&mut re, &mut im);
+
+        // A full scale sine peaks at FFT_SIZE / 4 through the window, that is 0 dB
+        let magnitude = |bin: usize| (re[bin] * re[bin] + im[bin] * im[bin]).sqrt() / (FFT_SIZE as f32 / 4.0);
+        let bin_hz = self.rate / FFT_SIZE as f32;
+        let max_hz = MAX_HZ.min(self.rate / 2.0);
+        let levels = (0..BANDS).map(|band| {
+            // Bands are spread logarithmically, like octaves
+            let low = MIN_HZ * (max_hz / MIN_HZ).powf(band as f32 / BANDS as f32);
+            let high = MIN_HZ * (max_hz / MIN_HZ).powf((band + 1) as f32 / BANDS as f32);
+            let first = ((low / bin_hz) as usize).clamp(1, FFT_SIZE / 2 - 1);
+            let last = ((high / bin_hz) as usize).clamp(first, FFT_SIZE / 2 - 1);
+            let peak = (first..=last).map(magnitude).fold(0.0, f32::max);
+            (20.0 * peak.max(1e-6).log10()).max(FLOOR_DB)
+        }).collect();
+        Some(levels)
+    }
+}
+
+/// In place radix 2 FFT, the length has to be a power of two
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_re, w_im) = ((angle * k as f32).cos(), (angle * k as f32).sin());
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(hz: f32, amplitude: f32, rate: u32, len: usize) -> Vec<i16> {
+        (0..len).map(|i| (amplitude * 32767.0 * (2.0 * PI * hz * i as f32 / rate as f32).sin()) as i16).collect()
+    }
+
+    #[test]
+    fn fft_of_an_impulse_is_flat() {
+        let mut re = vec![0.0; 16];
+        let mut im = vec![0.0; 16];
+        re[0] = 1.0;
+        fft(&mut re, &mut im);
+        assert!(re.iter().all(|r| (r - 1.0).abs() < 1e-6));
+        assert!(im.iter().all(|i| i.abs() < 1e-6));
+    }
+
+    #[test]
+    fn sines_land_in_their_band() {
+        for (hz, band) in [(100.0, 3), (1000.0, 13), (8000.0, 22)] {
+            let mut analyser = Analyser::new(44100);
+            let levels = analyser.push(&sine(hz, 0.5, 44100, FFT_SIZE)).unwrap();
+            // Low bands are narrower than a bin and share it with their neighbour
+            let loudest = levels.iter().cloned().fold(FLOOR_DB, f32::max);
+            assert_eq!(levels[band], loudest, "{} Hz", hz);
+            // Bands more than one and a half octaves away stay quiet
+            assert!(levels.iter().enumerate().all(|(b, l)| b.abs_diff(band) < 5 || *l < loudest - 20.0), "{} Hz", hz);
+            // Half of full scale
+            assert!((levels[band] + 6.0).abs() < 1.5, "{} Hz at {} dB", hz, levels[band]);
+        }
+    }
+
+    #[test]
+    fn silence_is_the_floor() {
+        let mut analyser = Analyser::new(44100);
+        assert_eq!(analyser.push(&[0; HOP]), None);
+        assert_eq!(analyser.push(&[0; HOP]), Some(vec![FLOOR_DB; BANDS]));
+    }
+
+    #[test]
+    fn bad_rates_do_not_panic() {
+        assert_eq!(Capture::open("", 0).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidInput));
+        assert!(Capture::open("", u32::MAX).is_err());
+        for rate in [0, 1, MIN_RATE, MAX_RATE, u32::MAX] {
+            let mut analyser = Analyser::new(rate);
+            assert_eq!(analyser.push(&sine(440.0, 1.0, 44100, FFT_SIZE)).map(|l| l.len()), Some(BANDS));
+        }
+    }
+}
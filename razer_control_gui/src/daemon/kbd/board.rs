@@ -2,14 +2,38 @@
 use crate::driver_sysfs;
 */
 use crate::device;
+use service::keyboard::Lighting;
 use std::cmp::Ordering;
 use std::ops;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 
 // -- RGB Key channel --
 
 pub const KEYS_PER_ROW: usize = 15;
 pub const ROWS: usize = 6;
 
+// Matrix size of the laptop, boards are created in this size
+static MATRIX_ROWS: AtomicUsize = AtomicUsize::new(ROWS);
+static MATRIX_COLUMNS: AtomicUsize = AtomicUsize::new(KEYS_PER_ROW);
+
+/// Sets the size of boards created from now on, 1x1 for single zone keyboards
+pub fn set_matrix(rows: usize, columns: usize) {
+    MATRIX_ROWS.store(rows.max(1), AtomicOrdering::Relaxed);
+    MATRIX_COLUMNS.store(columns.max(1), AtomicOrdering::Relaxed);
+}
+
+pub fn rows() -> usize {
+    MATRIX_ROWS.load(AtomicOrdering::Relaxed)
+}
+
+pub fn columns() -> usize {
+    MATRIX_COLUMNS.load(AtomicOrdering::Relaxed)
+}
+
+pub fn key_count() -> usize {
+    rows() * columns()
+}
+
 #[derive(Copy, Clone, Debug)]
 /// Represents the colour channels for a key
 pub struct KeyColour {
@@ -94,6 +118,17 @@ impl ops::Sub for AnimatorKeyColour {
     }
 }
 
+impl ops::Mul<f32> for AnimatorKeyColour {
+    type Output = Self;
+    fn mul(self, rhs: f32) -> Self {
+        Self {
+            red: self.red * rhs,
+            green: self.green * rhs,
+            blue: self.blue * rhs,
+        }
+    }
+}
+
 impl ops::AddAssign for AnimatorKeyColour {
     fn add_assign(&mut self, rhs: AnimatorKeyColour) {
         self.red += rhs.red;
@@ -129,21 +164,21 @@ impl PartialOrd for AnimatorKeyColour {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-/// Represents a horizontal row of 15 keys on the keyboard
+#[derive(Clone, Debug)]
+/// Represents a horizontal row of keys on the keyboard
 pub struct RowData {
-    keys: [KeyColour; KEYS_PER_ROW],
+    keys: Vec<KeyColour>,
 }
 
 impl RowData {
     /// Generates an empty keyboard row, with each key being white (FF,FF,FF)
-    pub fn new() -> RowData {
+    pub fn new(columns: usize) -> RowData {
         return RowData {
-            keys: [KeyColour {
+            keys: vec![KeyColour {
                 red: 255,
                 green: 255,
                 blue: 255,
-            }; KEYS_PER_ROW],
+            }; columns],
         };
     }
 
@@ -169,12 +204,12 @@ impl RowData {
     /// * g - Green channel value
     /// * b - Blue channel value
     pub fn set_row_color(&mut self, r: u8, g: u8, b: u8) {
-        (0..KEYS_PER_ROW).for_each(|x| self.set_key_color(x, r, g, b)) // Sets the entire row
+        (0..self.keys.len()).for_each(|x| self.set_key_color(x, r, g, b)) // Sets the entire row
     }
 
     pub fn get_row_data(&mut self) -> Vec<u8> {
         // *3 as itll be the RGB values
-        let mut v = Vec::<u8>::with_capacity(3 * KEYS_PER_ROW);
+        let mut v = Vec::<u8>::with_capacity(3 * self.keys.len());
         self.keys.iter().for_each(|k| {
             v.push(k.red);
             v.push(k.green);
@@ -184,16 +219,20 @@ impl RowData {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct KeyboardData {
-    rows: [RowData; ROWS],
+    rows: Vec<RowData>,
+    columns: usize,
     // brightness: u8,
 }
 
 impl KeyboardData {
+    /// A board in the size of the laptop's matrix, see `set_matrix`
     pub fn new() -> KeyboardData {
+        let (rows, columns) = (rows(), columns());
         return KeyboardData {
-            rows: [RowData::new(); ROWS],
+            rows: vec![RowData::new(columns); rows],
+            columns,
             // brightness: 0,
         };
     }
@@ -214,25 +253,51 @@ impl KeyboardData {
 
     pub fn update_kbd(&mut self, laptop: &mut device::RazerLaptop) -> bool {
         // driver_sysfs::write_rgb_map(self.get_curr_state())
-        for idx in 0..ROWS {
-            laptop.set_custom_frame_data(idx as u8, self.rows[idx].get_row_data());
+        match laptop.get_lighting() {
+            Lighting::PerKey => {
+                for idx in 0..self.rows.len() {
+                    laptop.set_custom_frame_data(idx as u8, self.rows[idx].get_row_data());
+                }
+                return true;
+            }
+            // The board is a single key on these
+            Lighting::Zone => {
+                let key = self.get_key_at(0);
+                return laptop.set_zone_colour([key.red, key.green, key.blue]);
+            }
+            Lighting::SingleColour | Lighting::None => return false,
         }
-        return true;
     }
 
     pub fn update_custom_mode(&mut self, laptop: &mut device::RazerLaptop) -> bool {
         // driver_sysfs::write_custom_mode_frame(1)
         // driver_sysfs::write_custom_mode_frame(1)
+        if laptop.get_lighting() != Lighting::PerKey {
+            return false; // no custom frames, zones are drawn with a static effect
+        }
         return laptop.set_custom_frame();
     }
 
+    pub fn get_rows(&self) -> usize {
+        return self.rows.len();
+    }
+
+    pub fn get_columns(&self) -> usize {
+        return self.columns;
+    }
+
+    /// Number of keys in the matrix
+    pub fn key_count(&self) -> usize {
+        return self.rows.len() * self.columns;
+    }
+
     /// Sets a specific key in the keyboard matrix to a colour
     #[allow(dead_code)]
     pub fn set_key_colour(&mut self, row: usize, col: usize, r: u8, g: u8, b: u8) {
-        if row >= ROWS {
+        if row >= self.rows.len() {
             return;
         }
-        if col >= KEYS_PER_ROW {
+        if col >= self.columns {
             return;
         }
         self.rows[row].set_key_color(col, r, g, b)
@@ -241,7 +306,7 @@ impl KeyboardData {
     /// Sets a horizontal row on the keyboard to a colour
     #[allow(dead_code)]
     pub fn set_row_colour(&mut self, row: usize, r: u8, g: u8, b: u8) {
-        if row >= ROWS {
+        if row >= self.rows.len() {
             return;
         }
         self.rows[row].set_row_color(r, g, b)
@@ -249,33 +314,33 @@ impl KeyboardData {
 
     /// Sets a vertical column on the keyboard to a colour
     pub fn set_col_colour(&mut self, col: usize, r: u8, g: u8, b: u8) {
-        if col >= KEYS_PER_ROW {
+        if col >= self.columns {
             return;
         }
-        for row_id in 0..ROWS {
-            self.rows[row_id].set_key_color(col, r, g, b)
+        for row in self.rows.iter_mut() {
+            row.set_key_color(col, r, g, b)
         }
     }
 
     /// Sets the entire keyboard to a colour
     pub fn set_kbd_colour(&mut self, r: u8, g: u8, b: u8) {
-        for row_id in 0..ROWS {
-            self.rows[row_id].set_row_color(r, g, b)
+        for row in self.rows.iter_mut() {
+            row.set_row_color(r, g, b)
         }
     }
 
     /// Returns a specific key
-    pub fn get_key_at(self, index: usize) -> KeyColour {
-        self.rows[index / KEYS_PER_ROW].keys[index % KEYS_PER_ROW]
+    pub fn get_key_at(&self, index: usize) -> KeyColour {
+        self.rows[index / self.columns].keys[index % self.columns]
     }
 
     /// Internal function used only for the combining of effect layers
     pub fn set_key_at(&mut self, index: usize, col: KeyColour) {
-        self.rows[index / KEYS_PER_ROW].keys[index % KEYS_PER_ROW] = col
+        self.rows[index / self.columns].keys[index % self.columns] = col
     }
 
     pub fn get_curr_state(&mut self) -> Vec<u8> {
-        let mut all_vals = Vec::<u8>::with_capacity(3 * KEYS_PER_ROW * ROWS);
+        let mut all_vals = Vec::<u8>::with_capacity(3 * self.key_count());
         for row in self.rows.iter_mut() {
             all_vals.extend(&row.get_row_data());
         }
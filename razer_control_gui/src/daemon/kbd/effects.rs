@@ -5,7 +5,7 @@ use super::*;
 /// 1 colour, simple
 ///
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Static {
     kbd: board::KeyboardData,
     args: [u8; 3],
@@ -29,7 +29,7 @@ impl Effect for Static {
     }
 
     fn update(&mut self) -> board::KeyboardData {
-        return self.kbd;
+        return self.kbd.clone();
     }
 
     fn get_name() -> &'static str
@@ -44,7 +44,7 @@ impl Effect for Static {
     }
 
     fn clone_box(&self) -> Box<dyn Effect> {
-        return Box::new(*self);
+        return Box::new(self.clone());
     }
 
     fn save(&mut self) -> EffectSave {
@@ -64,7 +64,7 @@ impl Effect for Static {
 /// 2 colours forming a gradient
 ///
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct StaticGradient {
     kbd: board::KeyboardData,
     args: [u8; 6],
@@ -82,8 +82,9 @@ impl Effect for StaticGradient {
         ];
         let mut c1 = board::AnimatorKeyColour::new_u(args[0], args[1], args[2]);
         let c2 = board::AnimatorKeyColour::new_u(args[3], args[4], args[5]);
-        let delta = (c2 - c1).divide(14.0);
-        for i in 0..15 {
+        let columns = kbd.get_columns();
+        let delta = (c2 - c1).divide((columns - 1).max(1) as f32);
+        for i in 0..columns {
             let clamped = c1.get_clamped_colour();
             kbd.set_col_colour(i, clamped.red, clamped.green, clamped.blue);
             c1 += delta;
@@ -93,7 +94,7 @@ impl Effect for StaticGradient {
     }
 
     fn update(&mut self) -> board::KeyboardData {
-        self.kbd // Nothing to update
+        self.kbd.clone() // Nothing to update
     }
 
     fn get_name() -> &'static str
@@ -108,7 +109,7 @@ impl Effect for StaticGradient {
     }
 
     fn clone_box(&self) -> Box<dyn Effect> {
-        return Box::new(*self);
+        return Box::new(self.clone());
     }
 
     fn save(&mut self) -> EffectSave {
@@ -150,12 +151,13 @@ impl Effect for WaveGradient {
         };
         let mut c1 = board::AnimatorKeyColour::new_u(args[0], args[1], args[2]);
         let mut c2 = board::AnimatorKeyColour::new_u(args[3], args[4], args[5]);
-        let c_delta = (c2 - c1).divide(15.0);
-        for _ in 0..15 {
+        let columns = wave.kbd.get_columns();
+        let c_delta = (c2 - c1).divide(columns as f32);
+        for _ in 0..columns {
             wave.colour_band.push(c1);
             c1 += c_delta;
         }
-        for _ in 0..15 {
+        for _ in 0..columns {
             wave.colour_band.push(c2);
             c2 -= c_delta;
         }
@@ -163,12 +165,12 @@ impl Effect for WaveGradient {
     }
 
     fn update(&mut self) -> board::KeyboardData {
-        for i in 0..15 {
+        for i in 0..self.kbd.get_columns() {
             let c = self.colour_band[i].get_clamped_colour();
             self.kbd.set_col_colour(i, c.red, c.green, c.blue);
         }
         self.colour_band.rotate_right(1);
-        self.kbd
+        self.kbd.clone()
     }
 
     fn get_name() -> &'static str
@@ -201,7 +203,7 @@ impl Effect for WaveGradient {
 impl Clone for WaveGradient {
     fn clone(&self) -> Self {
         WaveGradient {
-            kbd: self.kbd,
+            kbd: self.kbd.clone(),
             args: self.args,
             colour_band: self.colour_band.to_vec(),
         }
@@ -212,7 +214,7 @@ impl Clone for WaveGradient {
 /// BREATHING (1 Colour) KEYBOARD EFFECT
 /// 1 colour, fading in and out
 ///
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct BreathSingle {
     args: [u8; 4],
     kbd: board::KeyboardData,
@@ -284,7 +286,7 @@ impl Effect for BreathSingle {
         }
         let col = self.current_colour.get_clamped_colour();
         self.kbd.set_kbd_colour(col.red, col.green, col.blue); // Cast back to u8
-        return self.kbd;
+        return self.kbd.clone();
     }
 
     fn get_name() -> &'static str
@@ -299,7 +301,7 @@ impl Effect for BreathSingle {
     }
 
     fn clone_box(&self) -> Box<dyn Effect> {
-        return Box::new(*self);
+        return Box::new(self.clone());
     }
 
     fn save(&mut self) -> EffectSave {
@@ -313,3 +315,734 @@ impl Effect for BreathSingle {
         self.kbd.get_curr_state()
     }
 }
+
+///
+/// PER KEY KEYBOARD EFFECT
+/// 1 colour per key, painted with SetKeys
+///
+#[derive(Clone)]
+pub struct PerKey {
+    kbd: board::KeyboardData,
+    args: Vec<u8>, // RGB of every key, row by row
+}
+
+impl Effect for PerKey {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        let mut kbd = board::KeyboardData::new();
+        let mut args = args;
+        args.resize(3 * kbd.key_count(), 0);
+        for (idx, rgb) in args.chunks(3).enumerate() {
+            kbd.set_key_at(idx, board::KeyColour { red: rgb[0], green: rgb[1], blue: rgb[2] });
+        }
+        Box::new(PerKey { kbd, args })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        self.kbd.clone() // Only changes with set_key
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Per Key"
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        return &self.args;
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        return Box::new(self.clone());
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.clone(),
+            name: String::from("Per Key"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+
+    fn set_key(&mut self, index: usize, colour: board::KeyColour) -> bool {
+        if index >= self.kbd.key_count() {
+            return false;
+        }
+        self.kbd.set_key_at(index, colour);
+        self.args[3 * index..3 * index + 3].copy_from_slice(&[colour.red, colour.green, colour.blue]);
+        return true;
+    }
+}
+
+///
+/// AFTERGLOW KEYBOARD EFFECT
+/// Pressed keys light up and fade out
+///
+#[derive(Clone)]
+pub struct Afterglow {
+    kbd: board::KeyboardData,
+    args: [u8; 4],
+    colour: board::AnimatorKeyColour,
+    fade_ms: u128,
+    pressed_ms: Vec<Option<u128>>, // last press per key
+}
+
+impl Effect for Afterglow {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        let args: [u8; 4] = [
+            *args.get(0).unwrap_or(&0), *args.get(1).unwrap_or(&0), *args.get(2).unwrap_or(&0),
+            *args.get(3).unwrap_or(&10),
+        ];
+        let mut kbd = board::KeyboardData::new();
+        kbd.set_kbd_colour(0, 0, 0);
+        let keys = kbd.key_count();
+        Box::new(Afterglow {
+            kbd,
+            args,
+            colour: board::AnimatorKeyColour::new_u(args[0], args[1], args[2]),
+            fade_ms: (args[3].max(1) as u128) * 100,
+            pressed_ms: vec![None; keys],
+        })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        let now = get_millis();
+        for (idx, pressed) in self.pressed_ms.iter_mut().enumerate() {
+            let elapsed = match pressed {
+                Some(ms) if now.saturating_sub(*ms) < self.fade_ms => now.saturating_sub(*ms),
+                _ => {
+                    *pressed = None;
+                    self.kbd.set_key_at(idx, board::KeyColour { red: 0, green: 0, blue: 0 });
+                    continue;
+                }
+            };
+            let level = 1.0 - elapsed as f32 / self.fade_ms as f32;
+            self.kbd.set_key_at(idx, (self.colour * level).get_clamped_colour());
+        }
+        self.kbd.clone()
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Afterglow"
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        return &self.args;
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        return Box::new(self.clone());
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.to_vec(),
+            name: String::from("Afterglow"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+
+    fn key_pressed(&mut self, index: usize) {
+        if let Some(pressed) = self.pressed_ms.get_mut(index) {
+            *pressed = Some(get_millis());
+        }
+    }
+}
+
+///
+/// RIPPLE KEYBOARD EFFECT
+/// A ring spreading out from each pressed key
+///
+const RIPPLE_WIDTH: f32 = 1.5; // in keys
+
+#[derive(Clone)]
+pub struct Ripple {
+    kbd: board::KeyboardData,
+    args: [u8; 4],
+    colour: board::AnimatorKeyColour,
+    keys_per_ms: f32,
+    ripples: Vec<(f32, f32, u128)>, // row, column and start of each ring
+}
+
+impl Effect for Ripple {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        let args: [u8; 4] = [
+            *args.get(0).unwrap_or(&0), *args.get(1).unwrap_or(&0), *args.get(2).unwrap_or(&0),
+            *args.get(3).unwrap_or(&10),
+        ];
+        let mut kbd = board::KeyboardData::new();
+        kbd.set_kbd_colour(0, 0, 0);
+        Box::new(Ripple {
+            kbd,
+            args,
+            colour: board::AnimatorKeyColour::new_u(args[0], args[1], args[2]),
+            keys_per_ms: args[3].max(1) as f32 / 1000.0,
+            ripples: vec![],
+        })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        let now = get_millis();
+        let columns = self.kbd.get_columns();
+        let max_radius = columns as f32; // the ring fades out on the way
+        let keys_per_ms = self.keys_per_ms;
+        self.ripples.retain(|(_, _, start)| now.saturating_sub(*start) as f32 * keys_per_ms < max_radius + RIPPLE_WIDTH);
+        for idx in 0..self.kbd.key_count() {
+            let (row, col) = ((idx / columns) as f32, (idx % columns) as f32);
+            let mut level: f32 = 0.0;
+            for (r_row, r_col, start) in &self.ripples {
+                let radius = now.saturating_sub(*start) as f32 * keys_per_ms;
+                let distance = ((row - r_row).powi(2) + (col - r_col).powi(2)).sqrt();
+                let ring = 1.0 - (distance - radius).abs() / RIPPLE_WIDTH;
+                let fade = 1.0 - radius / (max_radius + RIPPLE_WIDTH);
+                level = level.max(ring * fade);
+            }
+            self.kbd.set_key_at(idx, (self.colour * level.max(0.0)).get_clamped_colour());
+        }
+        self.kbd.clone()
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Ripple"
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        return &self.args;
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        return Box::new(self.clone());
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.to_vec(),
+            name: String::from("Ripple"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+
+    fn key_pressed(&mut self, index: usize) {
+        let columns = self.kbd.get_columns();
+        if index < self.kbd.key_count() {
+            self.ripples.push(((index / columns) as f32, (index % columns) as f32, get_millis()));
+        }
+    }
+}
+
+///
+/// HEATMAP KEYBOARD EFFECT
+/// Keys go from the cold to the hot colour the more they are pressed,
+/// cooling down again over time
+///
+#[derive(Clone)]
+pub struct Heatmap {
+    kbd: board::KeyboardData,
+    args: [u8; 7],
+    cold: board::AnimatorKeyColour,
+    hot: board::AnimatorKeyColour,
+    half_life_ms: f32, // 0 = presses are never forgotten
+    presses: Vec<f32>, // decayed press count per key
+    last_update_ms: u128,
+}
+
+impl Effect for Heatmap {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        let args: [u8; 7] = [
+            *args.get(0).unwrap_or(&0), *args.get(1).unwrap_or(&0), *args.get(2).unwrap_or(&0),
+            *args.get(3).unwrap_or(&0), *args.get(4).unwrap_or(&0), *args.get(5).unwrap_or(&0),
+            *args.get(6).unwrap_or(&5),
+        ];
+        let kbd = board::KeyboardData::new();
+        let keys = kbd.key_count();
+        Box::new(Heatmap {
+            kbd,
+            args,
+            cold: board::AnimatorKeyColour::new_u(args[0], args[1], args[2]),
+            hot: board::AnimatorKeyColour::new_u(args[3], args[4], args[5]),
+            half_life_ms: args[6] as f32 * 60_000.0,
+            presses: vec![0.0; keys],
+            last_update_ms: get_millis(),
+        })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        let now = get_millis();
+        if self.half_life_ms > 0.0 {
+            let decay = 0.5f32.powf(now.saturating_sub(self.last_update_ms) as f32 / self.half_life_ms);
+            self.presses.iter_mut().for_each(|p| *p *= decay);
+        }
+        self.last_update_ms = now;
+        // The most pressed key is the hottest
+        let most = self.presses.iter().cloned().fold(1.0, f32::max);
+        for (idx, presses) in self.presses.iter().enumerate() {
+            let colour = self.cold + (self.hot - self.cold) * (presses / most);
+            self.kbd.set_key_at(idx, colour.get_clamped_colour());
+        }
+        self.kbd.clone()
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Heatmap"
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        return &self.args;
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        return Box::new(self.clone());
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.to_vec(),
+            name: String::from("Heatmap"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+
+    fn key_pressed(&mut self, index: usize) {
+        if let Some(presses) = self.presses.get_mut(index) {
+            *presses += 1.0;
+        }
+    }
+}
+
+///
+/// SYSTEM METRIC KEYBOARD EFFECTS
+/// Live CPU load, core load, temperatures and battery charge, sampled by
+/// the daemon into `system::current`
+///
+
+/// Colours and thresholds of the metric effects, their args are
+/// [low RGB, high RGB, low threshold, high threshold]
+#[derive(Clone)]
+struct MetricColours {
+    args: [u8; 8],
+    low: board::AnimatorKeyColour,
+    high: board::AnimatorKeyColour,
+}
+
+impl MetricColours {
+    /// Missing args are taken from `defaults`
+    fn new(args: Vec<u8>, defaults: [u8; 8]) -> MetricColours {
+        let args: [u8; 8] = std::array::from_fn(|i| *args.get(i).unwrap_or(&defaults[i]));
+        MetricColours {
+            args,
+            low: board::AnimatorKeyColour::new_u(args[0], args[1], args[2]),
+            high: board::AnimatorKeyColour::new_u(args[3], args[4], args[5]),
+        }
+    }
+
+    /// The low colour up to the low threshold, the high one from the
+    /// high threshold, blended in between
+    fn colour(&self, value: f32) -> board::KeyColour {
+        let (low, high) = (self.args[6] as f32, self.args[7] as f32);
+        let level = if high <= low {
+            if value >= high { 1.0 } else { 0.0 }
+        } else {
+            ((value - low) / (high - low)).clamp(0.0, 1.0)
+        };
+        (self.low + (self.high - self.low) * level).get_clamped_colour()
+    }
+}
+
+/// Lights `fraction` of the columns from `first` to before `last` on a row,
+/// the rest of the row is black
+fn draw_bar(kbd: &mut board::KeyboardData, row: usize, first: usize, last: usize, fraction: f32, colour: board::KeyColour) {
+    let last = last.min(kbd.get_columns());
+    let width = last.saturating_sub(first) as f32;
+    for (pos, col) in (first..last).enumerate() {
+        if (pos as f32) < (fraction * width).round() {
+            kbd.set_key_colour(row, col, colour.red, colour.green, colour.blue);
+        } else {
+            kbd.set_key_colour(row, col, 0, 0, 0);
+        }
+    }
+}
+
+/// Row of the keyboard matrix, the only one on single zone keyboards
+fn metric_row(kbd: &board::KeyboardData, row: usize) -> usize {
+    row.min(kbd.get_rows() - 1)
+}
+
+/// CPU load as a bar across the number row, coloured by the load
+#[derive(Clone)]
+pub struct CpuLoad {
+    kbd: board::KeyboardData,
+    colours: MetricColours,
+}
+
+impl Effect for CpuLoad {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        let mut kbd = board::KeyboardData::new();
+        kbd.set_kbd_colour(0, 0, 0);
+        // Green at low load to red at high load unless given
+        Box::new(CpuLoad { kbd, colours: MetricColours::new(args, [0, 255, 0, 255, 0, 0, 20, 90]) })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        let load = system::current().cpu_load.unwrap_or(0.0);
+        let row = metric_row(&self.kbd, 1);
+        let columns = self.kbd.get_columns();
+        draw_bar(&mut self.kbd, row, 0, columns, load / 100.0, self.colours.colour(load));
+        self.kbd.clone()
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "CPU Load"
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        return &self.colours.args;
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        return Box::new(self.clone());
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.colours.args.to_vec(),
+            name: String::from("CPU Load"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+
+    fn uses_system_state(&self) -> bool {
+        true
+    }
+}
+
+/// Load of each core on the F-keys. With more cores than F-keys a key
+/// shows the busiest core of its group.
+#[derive(Clone)]
+pub struct CoreLoad {
+    kbd: board::KeyboardData,
+    colours: MetricColours,
+}
+
+const F_KEYS: usize = 12; // F1 to F12, right of Esc
+
+impl Effect for CoreLoad {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        let mut kbd = board::KeyboardData::new();
+        kbd.set_kbd_colour(0, 0, 0);
+        Box::new(CoreLoad { kbd, colours: MetricColours::new(args, [0, 255, 0, 255, 0, 0, 20, 90]) })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        let loads = system::current().core_loads;
+        let row = metric_row(&self.kbd, 0);
+        let (first, keys) = if self.kbd.get_columns() > F_KEYS { (1, F_KEYS) } else { (0, self.kbd.get_columns()) };
+        let per_key = loads.len().div_ceil(keys).max(1);
+        for key in 0..keys {
+            let load = loads.iter().skip(key * per_key).take(per_key).cloned().reduce(f32::max);
+            let c = match load {
+                Some(load) => self.colours.colour(load),
+                None => board::KeyColour { red: 0, green: 0, blue: 0 }, // fewer cores than keys
+            };
+            self.kbd.set_key_colour(row, first + key, c.red, c.green, c.blue);
+        }
+        self.kbd.clone()
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Core Load"
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        return &self.colours.args;
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        return Box::new(self.clone());
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.colours.args.to_vec(),
+            name: String::from("Core Load"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+
+    fn uses_system_state(&self) -> bool {
+        true
+    }
+}
+
+/// CPU temperature on the left blending into the GPU temperature on the right
+#[derive(Clone)]
+pub struct Temperature {
+    kbd: board::KeyboardData,
+    colours: MetricColours,
+}
+
+impl Effect for Temperature {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        // Blue at 45 °C to red at 90 °C unless given
+        Box::new(Temperature { kbd: board::KeyboardData::new(), colours: MetricColours::new(args, [0, 0, 255, 255, 0, 0, 45, 90]) })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        let state = system::current();
+        let cpu = state.cpu_temp.unwrap_or(0.0);
+        let gpu = state.gpu_temp.unwrap_or(cpu);
+        let columns = self.kbd.get_columns();
+        for col in 0..columns {
+            let share = if columns > 1 { col as f32 / (columns - 1) as f32 } else { 0.5 };
+            let c = self.colours.colour(cpu + (gpu - cpu) * share);
+            self.kbd.set_col_colour(col, c.red, c.green, c.blue);
+        }
+        self.kbd.clone()
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Temperature"
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        return &self.colours.args;
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        return Box::new(self.clone());
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.colours.args.to_vec(),
+            name: String::from("Temperature"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+
+    fn uses_system_state(&self) -> bool {
+        true
+    }
+}
+
+/// Battery charge as a bar on the function row while on battery,
+/// the row stays black on AC
+#[derive(Clone)]
+pub struct BatteryLevel {
+    kbd: board::KeyboardData,
+    colours: MetricColours,
+}
+
+impl Effect for BatteryLevel {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        let mut kbd = board::KeyboardData::new();
+        kbd.set_kbd_colour(0, 0, 0);
+        // Red at 20 % to green at 80 % unless given
+        Box::new(BatteryLevel { kbd, colours: MetricColours::new(args, [255, 0, 0, 0, 255, 0, 20, 80]) })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        let state = system::current();
+        let row = metric_row(&self.kbd, 0);
+        let columns = self.kbd.get_columns();
+        match state.battery {
+            Some(charge) if !state.on_ac => {
+                draw_bar(&mut self.kbd, row, 0, columns, charge / 100.0, self.colours.colour(charge));
+            }
+            _ => draw_bar(&mut self.kbd, row, 0, columns, 0.0, board::KeyColour { red: 0, green: 0, blue: 0 }),
+        }
+        self.kbd.clone()
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Battery Level"
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        return &self.colours.args;
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        return Box::new(self.clone());
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.colours.args.to_vec(),
+            name: String::from("Battery Level"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+
+    fn uses_system_state(&self) -> bool {
+        true
+    }
+}
+
+///
+/// AUDIO KEYBOARD EFFECTS
+/// Spectrum of the audio playing, analysed by the daemon into `audio::levels`
+///
+
+/// Frequency bands as bars rising from the bottom row, low frequencies on the
+/// left. Args are [bottom RGB, top RGB, gain dB, decay * 100ms], the decay is
+/// how long a full bar takes to fall back.
+#[derive(Clone)]
+pub struct Spectrum {
+    kbd: board::KeyboardData,
+    args: [u8; 8],
+    low: board::AnimatorKeyColour,
+    high: board::AnimatorKeyColour,
+    heights: Vec<f32>, // per column, 0 to 1
+    last_update_ms: u128,
+}
+
+impl Effect for Spectrum {
+    fn new(args: Vec<u8>) -> Box<dyn Effect>
+    where
+        Self: Sized,
+    {
+        // Green to red, -20 dB fills a bar
+        let defaults: [u8; 8] = [0, 255, 0, 255, 0, 0, 20, 5];
+        let args: [u8; 8] = std::array::from_fn(|i| *args.get(i).unwrap_or(&defaults[i]));
+        let kbd = board::KeyboardData::new();
+        let columns = kbd.get_columns();
+        Box::new(Spectrum {
+            kbd,
+            args,
+            low: board::AnimatorKeyColour::new_u(args[0], args[1], args[2]),
+            high: board::AnimatorKeyColour::new_u(args[3], args[4], args[5]),
+            heights: vec![0.0; columns],
+            last_update_ms: get_millis(),
+        })
+    }
+
+    fn update(&mut self) -> board::KeyboardData {
+        let now = get_millis();
+        let fall = match self.args[7] {
+            0 => 1.0,
+            decay => now.saturating_sub(self.last_update_ms) as f32 / (decay as f32 * 100.0),
+        };
+        self.last_update_ms = now;
+
+        let levels = audio::levels();
+        let gain = self.args[6] as f32;
+        let (rows, columns) = (self.kbd.get_rows(), self.heights.len());
+        for (col, height) in self.heights.iter_mut().enumerate() {
+            // The loudest band of the column, silence while nothing is captured
+            let first = col * levels.len() / columns;
+            let last = ((col + 1) * levels.len() / columns).max(first + 1);
+            let db = levels.get(first..last).and_then(|b| b.iter().cloned().reduce(f32::max)).unwrap_or(audio::FLOOR_DB);
+            // The floor is an empty bar, -gain dB a full one
+            let target = ((db - audio::FLOOR_DB) / (-audio::FLOOR_DB - gain).max(1.0)).clamp(0.0, 1.0);
+            *height = target.max(*height - fall);
+
+            for pos in 0..rows {
+                // How much of this key the bar covers, counted from the bottom
+                let fill = (*height * rows as f32 - pos as f32).clamp(0.0, 1.0);
+                let colour = self.low + (self.high - self.low) * ((pos as f32 + fill) / rows as f32);
+                let c = (colour * fill).get_clamped_colour();
+                self.kbd.set_key_colour(rows - 1 - pos, col, c.red, c.green, c.blue);
+            }
+        }
+        self.kbd.clone()
+    }
+
+    fn get_name() -> &'static str
+    where
+        Self: Sized,
+    {
+        "Spectrum"
+    }
+
+    fn get_varargs(&mut self) -> &[u8] {
+        return &self.args;
+    }
+
+    fn clone_box(&self) -> Box<dyn Effect> {
+        return Box::new(self.clone());
+    }
+
+    fn save(&mut self) -> EffectSave {
+        EffectSave {
+            args: self.args.to_vec(),
+            name: String::from("Spectrum"),
+        }
+    }
+
+    fn get_state(&mut self) -> Vec<u8> {
+        self.kbd.get_curr_state()
+    }
+
+    fn uses_audio(&self) -> bool {
+        true
+    }
+}
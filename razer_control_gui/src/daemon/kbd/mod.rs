@@ -1,7 +1,11 @@
+pub mod audio;
 pub mod board;
 pub mod effects;
+pub mod system;
+use crate::comms;
 use crate::device;
 use serde::{Deserialize, Serialize};
+use service::keyboard::{Layout, Lighting};
 use serde_json::json;
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -9,6 +13,23 @@ const ANIMATION_FPS: u64 = 10; // 33 ms ~= 30fps
 
 pub const ANIMATION_SLEEP_MS: u64 = (1000.0 / ANIMATION_FPS as f32) as u64;
 
+/// Effect names used over IPC and the names they are saved with
+const EFFECT_NAMES: [(&str, &str); 13] = [
+    ("static", "Static"),
+    ("static_gradient", "Static Gradient"),
+    ("wave_gradient", "Wave Gradient"),
+    ("breathing_single", "Breathing Single"),
+    ("per_key", "Per Key"),
+    ("afterglow", "Afterglow"),
+    ("ripple", "Ripple"),
+    ("heatmap", "Heatmap"),
+    ("cpu_load", "CPU Load"),
+    ("core_load", "Core Load"),
+    ("temperature", "Temperature"),
+    ("battery_level", "Battery Level"),
+    ("spectrum", "Spectrum"),
+];
+
 pub fn get_millis() -> u128 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -16,6 +37,26 @@ pub fn get_millis() -> u128 {
         .as_millis()
 }
 
+/// Creates an effect from its IPC name, e.g. "static"
+pub fn new_effect(name: &str, args: Vec<u8>) -> Option<Box<dyn Effect>> {
+    match name {
+        "static" => Some(effects::Static::new(args)),
+        "static_gradient" => Some(effects::StaticGradient::new(args)),
+        "wave_gradient" => Some(effects::WaveGradient::new(args)),
+        "breathing_single" => Some(effects::BreathSingle::new(args)),
+        "per_key" => Some(effects::PerKey::new(args)),
+        "afterglow" => Some(effects::Afterglow::new(args)),
+        "ripple" => Some(effects::Ripple::new(args)),
+        "heatmap" => Some(effects::Heatmap::new(args)),
+        "cpu_load" => Some(effects::CpuLoad::new(args)),
+        "core_load" => Some(effects::CoreLoad::new(args)),
+        "temperature" => Some(effects::Temperature::new(args)),
+        "battery_level" => Some(effects::BatteryLevel::new(args)),
+        "spectrum" => Some(effects::Spectrum::new(args)),
+        _ => None,
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct EffectSave {
     args: Vec<u8>,
@@ -44,6 +85,82 @@ pub trait Effect: Send + Sync {
     fn clone_box(&self) -> Box<dyn Effect>;
     fn save(&mut self) -> EffectSave;
     fn get_state(&mut self) -> Vec<u8>;
+    /// Paints a single key, returns false for effects that are not painted by key
+    fn set_key(&mut self, _index: usize, _colour: board::KeyColour) -> bool {
+        false
+    }
+    /// Called for every key press when the daemon reads the keyboard,
+    /// for effects reacting to typing
+    fn key_pressed(&mut self, _index: usize) {}
+    /// Whether the effect draws `system::current`, the daemon only samples it then
+    fn uses_system_state(&self) -> bool {
+        false
+    }
+    /// Whether the effect draws `audio::levels`, the daemon only captures audio then
+    fn uses_audio(&self) -> bool {
+        false
+    }
+}
+
+/// How a layer is combined with the layers below it
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum BlendMode {
+    /// Covers the layers below
+    Replace,
+    /// Adds the colours, brightening the layers below
+    Add,
+    /// Multiplies the colours, a white layer leaves the ones below untouched
+    Multiply,
+    /// Inverse of multiply, a black layer leaves the ones below untouched
+    Screen,
+    /// Covers the layers below by the brightness of the layer, black keys are transparent
+    Alpha,
+}
+
+impl BlendMode {
+    pub fn from_name(name: &str) -> Option<BlendMode> {
+        match name {
+            "replace" => Some(BlendMode::Replace),
+            "add" => Some(BlendMode::Add),
+            "multiply" => Some(BlendMode::Multiply),
+            "screen" => Some(BlendMode::Screen),
+            "alpha" => Some(BlendMode::Alpha),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BlendMode::Replace => "replace",
+            BlendMode::Add => "add",
+            BlendMode::Multiply => "multiply",
+            BlendMode::Screen => "screen",
+            BlendMode::Alpha => "alpha",
+        }
+    }
+
+    /// Blends `top` over `base`, both with channels from 0 to 255
+    fn blend(self, base: board::AnimatorKeyColour, top: board::AnimatorKeyColour, opacity: f32) -> board::AnimatorKeyColour {
+        let channel = |b: f32, t: f32| -> f32 {
+            match self {
+                BlendMode::Replace | BlendMode::Alpha => t,
+                BlendMode::Add => b + t,
+                BlendMode::Multiply => b * t / 255.0,
+                BlendMode::Screen => 255.0 - (255.0 - b) * (255.0 - t) / 255.0,
+            }
+        };
+        let blended = board::AnimatorKeyColour::new_f(
+            channel(base.red, top.red),
+            channel(base.green, top.green),
+            channel(base.blue, top.blue),
+        );
+        let mut coverage = opacity;
+        if self == BlendMode::Alpha {
+            coverage *= top.red.max(top.green).max(top.blue) / 255.0;
+        }
+        return base + (blended - base) * coverage;
+    }
 }
 
 /// An effect combined with a mask layer.
@@ -53,6 +170,9 @@ struct EffectLayer {
     /// Mask for keys
     key_mask: Vec<bool>,
     effect: Box<dyn Effect>,
+    blend: BlendMode,
+    /// 0.0 (invisible) to 1.0
+    opacity: f32,
 }
 
 // SAFETY: EffectLayer is only accessed behind a Mutex<EffectManager>, which
@@ -62,10 +182,12 @@ unsafe impl Send for EffectLayer {}
 unsafe impl Sync for EffectLayer {}
 
 impl EffectLayer {
-    fn new(effect: Box<dyn Effect>, mask: [bool; 90]) -> EffectLayer {
+    fn new(effect: Box<dyn Effect>, mask: Vec<bool>) -> EffectLayer {
         return EffectLayer {
-            key_mask: mask.to_vec(),
+            key_mask: mask,
             effect,
+            blend: BlendMode::Replace,
+            opacity: 1.0,
         };
     }
 
@@ -73,15 +195,17 @@ impl EffectLayer {
         return self.effect.update();
     }
 
-    fn get_save(&mut self) -> Option<serde_json::Value> {
+    fn get_save(&mut self, layout: &Layout) -> Option<serde_json::Value> {
         match serde_json::to_value(self.effect.save()) {
             Ok(mut x) => {
-                let keys = match serde_json::to_value(&self.key_mask) {
-                    Ok(k) => k,
-                    Err(e) => { eprintln!("Failed to serialize key_mask: {}", e); return None; }
-                };
+                // Keys by name, so the file can be edited by hand
+                let keys = layout.format_mask(&self.key_mask);
                 match x.as_object_mut() {
-                    Some(obj) => { obj.insert(String::from("key_mask"), keys); }
+                    Some(obj) => {
+                        obj.insert(String::from("keys"), json!(keys));
+                        obj.insert(String::from("blend"), json!(self.blend));
+                        obj.insert(String::from("opacity"), json!(self.opacity));
+                    }
                     None => { eprintln!("Effect save is not a JSON object"); return None; }
                 }
                 Some(x)
@@ -90,22 +214,33 @@ impl EffectLayer {
         }
     }
 
-    fn from_save(json: serde_json::Value) -> Option<EffectLayer> {
-        if json["key_mask"].is_null() || json["name"].is_null() || json["args"].is_null() {
+    fn from_save(json: serde_json::Value, layout: &Layout) -> Option<EffectLayer> {
+        if (json["keys"].is_null() && json["key_mask"].is_null()) || json["name"].is_null() || json["args"].is_null() {
             eprintln!("Missing data for effect!");
             return None;
         }
-        let key_mask: Vec<bool> = match serde_json::from_value(json["key_mask"].clone()) {
-            Ok(v) => v,
-            Err(e) => { eprintln!("Failed to deserialize key_mask: {}", e); return None; }
+        // Saves from before key names have a mask of booleans. A mask that
+        // doesn't fit this keyboard lights every key instead of losing the layer
+        let key_mask: Vec<bool> = match json["keys"].as_str() {
+            Some(keys) => match layout.parse_mask(keys) {
+                Ok(v) => v,
+                Err(e) => { eprintln!("Failed to parse keys, using all keys: {}", e); vec![true; layout.key_count()] }
+            },
+            None => match serde_json::from_value(json["key_mask"].clone()) {
+                Ok(v) => v,
+                Err(e) => { eprintln!("Failed to deserialize key_mask: {}", e); return None; }
+            },
         };
-        if key_mask.len() != 90 {
+        let key_mask = if key_mask.len() != layout.key_count() {
             eprintln!(
-                "Invalid key count effect. Expected 90, found {}",
+                "Invalid key count effect, using all keys. Expected {}, found {}",
+                layout.key_count(),
                 key_mask.len()
             );
-            return None;
-        }
+            vec![true; layout.key_count()]
+        } else {
+            key_mask
+        };
         let name: String = match serde_json::from_value(json["name"].clone()) {
             Ok(v) => v,
             Err(e) => { eprintln!("Failed to deserialize effect name: {}", e); return None; }
@@ -114,16 +249,28 @@ impl EffectLayer {
             Ok(v) => v,
             Err(e) => { eprintln!("Failed to deserialize effect args: {}", e); return None; }
         };
+        // Saves from before blend modes replace the layers below
+        let blend: BlendMode = match json.get("blend") {
+            Some(b) => match serde_json::from_value(b.clone()) {
+                Ok(v) => v,
+                Err(e) => { eprintln!("Failed to deserialize blend mode: {}", e); return None; }
+            },
+            None => BlendMode::Replace,
+        };
+        let opacity = match json.get("opacity") {
+            Some(o) => match o.as_f64() {
+                Some(v) if (0.0..=1.0).contains(&v) => v as f32,
+                _ => { eprintln!("Invalid opacity {}, expected 0.0 to 1.0", o); return None; }
+            },
+            None => 1.0,
+        };
 
-        let effect: Option<Box<dyn Effect>> = match name.as_str() {
-            "Static" => Some(effects::Static::new(args)),
-            "Wave Gradient" => Some(effects::WaveGradient::new(args)),
-            "Breathing Single" => Some(effects::BreathSingle::new(args)),
-            "Static Gradient" => Some(effects::StaticGradient::new(args)),
-            _ => None,
+        let effect = match EFFECT_NAMES.iter().find(|(_, saved)| *saved == name) {
+            Some((ipc_name, _)) => new_effect(ipc_name, args),
+            None => None,
         };
         match effect {
-            Some(e) => Some(EffectLayer { key_mask, effect: e }),
+            Some(e) => Some(EffectLayer { key_mask, effect: e, blend, opacity }),
             None => {
                 eprintln!("Effect failed to load. Invalid name: {}", name);
                 None
@@ -134,15 +281,62 @@ impl EffectLayer {
     pub fn get_state(&mut self) -> Vec<u8> {
         self.effect.get_state()
     }
-    #[allow(dead_code)]
     pub fn get_mask(&mut self) -> Vec<bool> {
         self.key_mask.to_vec()
     }
+
+    fn get_info(&mut self) -> comms::LayerInfo {
+        let save = self.effect.save();
+        let effect = match EFFECT_NAMES.iter().find(|(_, saved)| *saved == save.name) {
+            Some((ipc_name, _)) => ipc_name.to_string(),
+            None => save.name,
+        };
+        comms::LayerInfo {
+            effect,
+            params: save.args,
+            key_mask: self.get_mask(),
+            blend: self.blend.name().to_string(),
+            opacity: self.opacity,
+        }
+    }
+}
+/// Keys painted by scripts on top of all layers. It is not saved and its
+/// keys can expire, uncovering the layers again.
+struct ScriptOverlay {
+    /// Colour and expiry time in ms (0 = never) per key, None is transparent
+    keys: Vec<Option<(board::KeyColour, u128)>>,
+    drawn: bool, // the last frame had overlay keys
+}
+
+impl ScriptOverlay {
+    fn new(key_count: usize) -> ScriptOverlay {
+        ScriptOverlay {
+            keys: vec![None; key_count],
+            drawn: false,
+        }
+    }
+
+    /// Drops expired keys, returns whether any are left
+    fn expire(&mut self, now: u128) -> bool {
+        for key in self.keys.iter_mut() {
+            if let Some((_, until)) = key {
+                if *until != 0 && *until <= now {
+                    *key = None;
+                }
+            }
+        }
+        self.keys.iter().any(|k| k.is_some())
+    }
 }
+
 pub struct EffectManager {
     layers: Vec<EffectLayer>,
+    overlay: ScriptOverlay,
+    layout: Layout, // names the keys in saves and commands
+    lighting: Lighting,
     last_update_ms: u128,
     render_board: board::KeyboardData,
+    dirty: bool, // layers changed since the last save
 }
 
 // SAFETY: EffectManager is only accessed behind a global Mutex (EFFECT_MANAGER),
@@ -151,21 +345,86 @@ pub struct EffectManager {
 unsafe impl Send for EffectManager {}
 unsafe impl Sync for EffectManager {}
 
+/// Moves a key mask to another layout by key name. A layer whose keys are
+/// all missing there, like "W,A,S,D" on a zone keyboard, lights every key.
+fn remap_mask(from: &Layout, to: &Layout, mask: &[bool]) -> Vec<bool> {
+    if !mask.contains(&false) || to.key_count() == 1 {
+        return vec![true; to.key_count()];
+    }
+    let mut remapped = vec![false; to.key_count()];
+    for (index, _) in mask.iter().enumerate().filter(|(_, on)| **on) {
+        if let Some(new) = from.key_name(index).and_then(|name| to.key_index(name)) {
+            remapped[new] = true;
+        }
+    }
+    if mask.contains(&true) && !remapped.contains(&true) {
+        eprintln!("No keys of a layer exist on this keyboard, it lights all keys");
+        return vec![true; to.key_count()];
+    }
+    return remapped;
+}
+
 impl EffectManager {
     pub fn new() -> EffectManager {
         EffectManager {
             layers: vec![],
+            overlay: ScriptOverlay::new(board::key_count()),
+            layout: Layout::for_name("ansi"),
+            lighting: Lighting::PerKey,
             last_update_ms: get_millis(),
             render_board: board::KeyboardData::new(),
+            dirty: false,
+        }
+    }
+
+    /// Sets the keyboard the effects are drawn for. Layers are reloaded
+    /// by key name when the matrix changes size.
+    pub fn set_layout(&mut self, layout: Layout, lighting: Lighting) {
+        let resized = layout.key_count() != self.layout.key_count();
+        if resized {
+            for layer in self.layers.iter_mut() {
+                layer.key_mask = remap_mask(&self.layout, &layout, &layer.key_mask);
+            }
+        }
+        board::set_matrix(layout.rows, layout.columns);
+        self.layout = layout;
+        self.lighting = lighting;
+        if resized {
+            let save = self.save();
+            self.render_board = board::KeyboardData::new();
+            self.overlay = ScriptOverlay::new(board::key_count());
+            self.layers.clear();
+            self.load_from_save(save);
+        }
+    }
+
+    /// Custom effects need a RGB keyboard
+    fn check_lighting(&self) -> Result<(), String> {
+        if !self.lighting.has_effects() {
+            return Err(format!("Custom effects need a RGB keyboard, this one is {}", self.lighting.name()));
+        }
+        Ok(())
+    }
+
+    fn check_mask(&self, mask: &[bool]) -> Result<(), String> {
+        if mask.len() != self.layout.key_count() {
+            return Err(format!("Key mask has {} keys instead of {}", mask.len(), self.layout.key_count()));
         }
+        Ok(())
     }
 
-    pub fn push_effect(&mut self, effect: Box<dyn Effect>, mask: [bool; 90]) {
-        self.layers.push(EffectLayer::new(effect, mask))
+    pub fn get_layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    pub fn push_effect(&mut self, effect: Box<dyn Effect>, mask: Vec<bool>) {
+        self.layers.push(EffectLayer::new(effect, mask));
+        self.dirty = true;
     }
 
     pub fn pop_effect(&mut self, laptop: &mut device::RazerLaptop) {
         self.layers.pop();
+        self.dirty = true;
         // If no more layers, erase keyboard rendering and set it to black
         if self.layers.is_empty() {
             self.render_board.set_kbd_colour(0, 0, 0); 
@@ -174,30 +433,196 @@ impl EffectManager {
         }
     }
 
-    pub fn update(&mut self, laptop: &mut device::RazerLaptop) {
-        // Do nothing if we have no effects!
+    /// Layers from the bottom up
+    pub fn list_layers(&mut self) -> Vec<comms::LayerInfo> {
+        self.layers.iter_mut().map(|l| l.get_info()).collect()
+    }
+
+    /// Inserts a layer at `index`, the number of layers puts it on top
+    pub fn insert_layer(&mut self, index: usize, effect: Box<dyn Effect>, mask: Vec<bool>, blend: BlendMode, opacity: f32) -> Result<(), String> {
+        self.check_lighting()?;
+        self.check_mask(&mask)?;
+        if index > self.layers.len() {
+            return Err(format!("Layer {} is out of range, there are {} layers", index, self.layers.len()));
+        }
+        if !(0.0..=1.0).contains(&opacity) {
+            return Err(format!("Invalid opacity {}, expected 0.0 to 1.0", opacity));
+        }
+        let mut layer = EffectLayer::new(effect, mask);
+        layer.blend = blend;
+        layer.opacity = opacity;
+        self.layers.insert(index, layer);
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn remove_layer(&mut self, index: usize, laptop: &mut device::RazerLaptop) -> Result<(), String> {
+        self.check_index(index)?;
+        self.layers.remove(index);
         if self.layers.is_empty() {
-            return;
+            self.pop_effect(laptop); // turns the keyboard off
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Moves a layer so it ends up at index `to`
+    pub fn move_layer(&mut self, from: usize, to: usize) -> Result<(), String> {
+        self.check_index(from)?;
+        self.check_index(to)?;
+        let layer = self.layers.remove(from);
+        self.layers.insert(to, layer);
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn set_layer_mask(&mut self, index: usize, mask: Vec<bool>) -> Result<(), String> {
+        self.check_index(index)?;
+        self.check_mask(&mask)?;
+        self.layers[index].key_mask = mask;
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Changes the given settings of a layer, new effect parameters restart its animation
+    pub fn update_layer(&mut self, index: usize, params: Option<Vec<u8>>, blend: Option<BlendMode>, opacity: Option<f32>) -> Result<(), String> {
+        self.check_index(index)?;
+        if let Some(o) = opacity {
+            if !(0.0..=1.0).contains(&o) {
+                return Err(format!("Invalid opacity {}, expected 0.0 to 1.0", o));
+            }
+        }
+        let layer = &mut self.layers[index];
+        if let Some(args) = params {
+            let name = layer.get_info().effect;
+            match new_effect(&name, args) {
+                Some(e) => layer.effect = e,
+                None => return Err(format!("Effect {} has no parameters to change", name)),
+            }
+        }
+        layer.blend = blend.unwrap_or(layer.blend);
+        layer.opacity = opacity.unwrap_or(layer.opacity);
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn check_index(&self, index: usize) -> Result<(), String> {
+        if index >= self.layers.len() {
+            return Err(format!("Layer {} is out of range, there are {} layers", index, self.layers.len()));
+        }
+        Ok(())
+    }
+
+    /// Paints keys of the script overlay (`layer` None) or of a per key layer.
+    /// Overlay keys go back to transparent after `timeout_ms` unless it is 0.
+    pub fn set_keys(&mut self, layer: Option<usize>, keys: &[(usize, board::KeyColour)], timeout_ms: u32) -> Result<(), String> {
+        self.check_lighting()?;
+        let index = match layer {
+            Some(idx) => idx,
+            None => {
+                let until = if timeout_ms == 0 { 0 } else { get_millis() + timeout_ms as u128 };
+                for (key, colour) in keys {
+                    self.overlay.keys[*key] = Some((*colour, until));
+                }
+                return Ok(());
+            }
+        };
+        self.check_index(index)?;
+        if timeout_ms != 0 {
+            return Err(String::from("Only keys of the script overlay can time out"));
+        }
+        for (key, colour) in keys {
+            if !self.layers[index].effect.set_key(*key, *colour) {
+                return Err(format!("Layer {} is not a per_key layer", index));
+            }
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Makes the script overlay transparent or a per key layer black
+    pub fn clear_keys(&mut self, layer: Option<usize>) -> Result<(), String> {
+        match layer {
+            Some(idx) => {
+                let black: Vec<(usize, board::KeyColour)> = (0..self.layout.key_count())
+                    .map(|k| (k, board::KeyColour { red: 0, green: 0, blue: 0 }))
+                    .collect();
+                self.set_keys(Some(idx), &black, 0)
+            }
+            None => {
+                self.overlay.keys.iter_mut().for_each(|k| *k = None);
+                Ok(())
+            }
         }
+    }
+
+    /// Passes a key press, as a Linux input key code, to the layers.
+    /// Layers react to keys outside their mask too, a ripple can run into it.
+    pub fn key_pressed(&mut self, code: u16) {
+        if let Some(index) = self.layout.evdev_index(code) {
+            for layer in self.layers.iter_mut() {
+                layer.effect.key_pressed(index);
+            }
+        }
+    }
+
+    /// Whether a layer shows the system state
+    pub fn uses_system_state(&self) -> bool {
+        self.lighting.has_effects() && self.layers.iter().any(|l| l.effect.uses_system_state())
+    }
+
+    /// Whether a layer shows the audio spectrum
+    pub fn uses_audio(&self) -> bool {
+        self.lighting.has_effects() && self.layers.iter().any(|l| l.effect.uses_audio())
+    }
+
+    /// Renders a frame. Returns true once the script overlay is gone while there
+    /// are no layers, the keyboard then needs its standard effect back.
+    pub fn update(&mut self, laptop: &mut device::RazerLaptop) -> bool {
+        if !self.lighting.has_effects() {
+            return false; // the keyboard only has its hardware effects
+        }
+        let overlay = self.overlay.expire(get_millis());
+        let overlay_gone = self.overlay.drawn && !overlay;
+        self.overlay.drawn = overlay;
+        // Do nothing if we have no effects!
+        if self.layers.is_empty() && !overlay {
+            return overlay_gone;
+        }
+        // Layers are blended bottom to top in float space, so rounding
+        // only happens once per frame
+        let mut composite = vec![board::AnimatorKeyColour::new_u(0, 0, 0); self.render_board.key_count()];
         for layer in self.layers.iter_mut() {
             let tmp_board = layer.update();
             for (pos, state) in layer.key_mask.iter().enumerate() {
                 if *state {
-                    self.render_board.set_key_at(pos, tmp_board.get_key_at(pos))
+                    let key = tmp_board.get_key_at(pos);
+                    let top = board::AnimatorKeyColour::new_u(key.red, key.green, key.blue);
+                    composite[pos] = layer.blend.blend(composite[pos], top, layer.opacity);
                 }
             }
         }
+        for (pos, colour) in composite.iter().enumerate() {
+            self.render_board.set_key_at(pos, colour.get_clamped_colour());
+        }
+        for (pos, key) in self.overlay.keys.iter().enumerate() {
+            if let Some((colour, _)) = key {
+                self.render_board.set_key_at(pos, *colour);
+            }
+        }
         // Don't forget to actually render the board
         self.last_update_ms = get_millis();
         self.render_board.update_kbd(laptop);
         self.render_board.update_custom_mode(laptop);
+        return false;
     }
 
     pub fn save(&mut self) -> serde_json::value::Value {
         let mut save_json = json!({"effects" : []});
 
+        let layout = &self.layout;
         let tmp_saves: Vec<Option<serde_json::Value>> =
-            self.layers.iter_mut().map(|l| l.get_save()).collect();
+            self.layers.iter_mut().map(|l| l.get_save(layout)).collect();
 
         if let Some(arr) = save_json["effects"].as_array_mut() {
             for save in tmp_saves {
@@ -211,6 +636,39 @@ impl EffectManager {
         return save_json;
     }
 
+    /// Makes the next `take_dirty_save` return the current layers
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Returns the save if the layers changed since it was last taken
+    pub fn take_dirty_save(&mut self) -> Option<serde_json::value::Value> {
+        if !self.dirty {
+            return None;
+        }
+        self.dirty = false;
+        Some(self.save())
+    }
+
+    /// Replaces all layers with the ones in `json`, leaving the current ones
+    /// untouched if any of them is invalid
+    pub fn replace_from_save(&mut self, json: serde_json::Value) -> Result<(), String> {
+        let effects = match json["effects"].as_array() {
+            Some(e) => e,
+            None => return Err("effects field is missing or not an array".to_string()),
+        };
+        let mut layers = vec![];
+        for (idx, e) in effects.iter().enumerate() {
+            match EffectLayer::from_save(e.clone(), &self.layout) {
+                Some(x) => layers.push(x),
+                None => return Err(format!("effect {} is invalid", idx)),
+            }
+        }
+        self.layers = layers;
+        self.dirty = false;
+        Ok(())
+    }
+
     pub fn load_from_save(&mut self, mut json: serde_json::Value) {
         if json["effects"].is_null() {
             eprintln!("Invalid json. No effects field!");
@@ -218,7 +676,7 @@ impl EffectManager {
         }
         if let Some(effects) = json["effects"].as_array_mut() {
             for e in effects {
-                if let Some(x) = EffectLayer::from_save(e.clone()) {
+                if let Some(x) = EffectLayer::from_save(e.clone(), &self.layout) {
                     self.layers.push(x);
                 } else {
                     eprintln!("Error adding effect");
@@ -242,3 +700,72 @@ impl EffectManager {
         vec![]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Boards take the matrix size set by the last test
+    static MATRIX: Mutex<()> = Mutex::new(());
+
+    /// Params come straight from IPC clients, no length may panic the daemon
+    #[test]
+    fn effects_accept_any_params() {
+        let _matrix = MATRIX.lock().unwrap_or_else(|e| e.into_inner());
+        let keys = board::KeyboardData::new().key_count();
+        for (name, saved) in EFFECT_NAMES {
+            for args in [vec![], vec![1, 2, 3], vec![255; 4 * 3 * keys]] {
+                let mut effect = new_effect(name, args).unwrap_or_else(|| panic!("{} is not created", name));
+                effect.update();
+                effect.key_pressed(0);
+                effect.key_pressed(keys); // out of range
+                assert_eq!(effect.update().get_curr_state().len(), 3 * keys, "{}", name);
+                assert_eq!(effect.save().name, saved);
+            }
+        }
+        assert!(new_effect("unknown", vec![]).is_none());
+    }
+
+    #[test]
+    fn spectrum_is_black_in_silence() {
+        let _matrix = MATRIX.lock().unwrap_or_else(|e| e.into_inner());
+        let draw = |levels: Vec<f32>| {
+            audio::set_levels(levels);
+            new_effect("spectrum", vec![]).unwrap().update().get_curr_state()
+        };
+        assert!(draw(vec![]).iter().all(|c| *c == 0), "nothing captured");
+        assert!(draw(vec![audio::FLOOR_DB; audio::BANDS]).iter().all(|c| *c == 0), "silence");
+        assert!(draw(vec![-20.0; audio::BANDS]).iter().any(|c| *c != 0), "-20 dB");
+        audio::set_levels(vec![]);
+    }
+
+    #[test]
+    fn layers_survive_a_layout_change() {
+        let _matrix = MATRIX.lock().unwrap_or_else(|e| e.into_inner());
+        let mut manager = EffectManager::new();
+        let wasd = manager.get_layout().parse_mask("W,A,S,D").unwrap();
+        let all = manager.get_layout().parse_mask("all").unwrap();
+        manager.push_effect(new_effect("static", vec![255, 0, 0]).unwrap(), wasd);
+        manager.push_effect(new_effect("static", vec![0, 255, 0]).unwrap(), all);
+
+        // The keys of the smaller matrix keep their names
+        let small = Layout::for_device("ansi", Lighting::PerKey, [4, board::KEYS_PER_ROW]);
+        let small_wasd = small.parse_mask("W,A,S,D").unwrap();
+        manager.set_layout(small, Lighting::PerKey);
+        assert_eq!(manager.list_layers().len(), 2);
+        assert_eq!(manager.layers[0].key_mask, small_wasd);
+        assert!(!manager.layers[1].key_mask.contains(&false));
+
+        // A zone keyboard has none of them
+        manager.set_layout(Layout::for_device("ansi", Lighting::Zone, [1, 1]), Lighting::Zone);
+        assert_eq!(manager.list_layers().len(), 2);
+        assert_eq!(manager.layers[0].key_mask, vec![true]);
+
+        // Masks that don't parse light all keys
+        let json = json!({ "name": "Static", "args": [1, 2, 3], "keys": "W,A,S,D" });
+        let layer = EffectLayer::from_save(json, manager.get_layout()).unwrap();
+        assert_eq!(layer.key_mask, vec![true]);
+        board::set_matrix(board::ROWS, board::KEYS_PER_ROW);
+    }
+}
@@ -0,0 +1,36 @@
+// Live system state for the metric effects. A daemon task samples it while
+// a layer shows it, the effects only read the last sample when drawing.
+use std::sync::Mutex;
+
+#[derive(Clone)]
+pub struct SystemState {
+    pub cpu_load: Option<f32>, // percent of all cores
+    pub core_loads: Vec<f32>, // percent per core
+    pub cpu_temp: Option<f32>,
+    pub gpu_temp: Option<f32>, // the dGPU, the iGPU while it sleeps
+    pub battery: Option<f32>, // charge in percent
+    pub on_ac: bool,
+}
+
+static STATE: Mutex<SystemState> = Mutex::new(SystemState {
+    cpu_load: None,
+    core_loads: vec![],
+    cpu_temp: None,
+    gpu_temp: None,
+    battery: None,
+    on_ac: true,
+});
+
+/// The last sample, empty until the first one is taken
+pub fn current() -> SystemState {
+    match STATE.lock() {
+        Ok(state) => state.clone(),
+        Err(e) => e.into_inner().clone(),
+    }
+}
+
+pub fn set(state: SystemState) {
+    if let Ok(mut s) = STATE.lock() {
+        *s = state;
+    }
+}
@@ -0,0 +1,48 @@
+// Keyboard backlight LED for the desktop brightness keys and OSD.
+// GNOME and KDE go through UPower's KbdBacklight, which only knows LED class
+// devices named *::kbd_backlight. The kernel's userspace LED driver (uleds)
+// creates one for us: UPower writes its brightness file and the value is
+// read back here to drive the HID backlight.
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+
+const ULEDS: &str = "/dev/uleds";
+pub const LED_NAME: &str = "razer::kbd_backlight";
+const MAX_BRIGHTNESS: i32 = 100; // percent, like DaemonCommand::SetBrightness
+const NAME_LEN: usize = 64; // LED_MAX_NAME_SIZE
+
+pub struct KbdBacklight {
+    file: File,
+}
+
+impl KbdBacklight {
+    /// Creates the LED, it is removed again when this is dropped
+    pub fn new() -> io::Result<KbdBacklight> {
+        let mut file = OpenOptions::new().read(true).write(true).open(ULEDS)?;
+        // struct uleds_user_dev { char name[64]; int max_brightness; }
+        let mut dev = [0u8; NAME_LEN + 4];
+        dev[..LED_NAME.len()].copy_from_slice(LED_NAME.as_bytes());
+        dev[NAME_LEN..].copy_from_slice(&MAX_BRIGHTNESS.to_ne_bytes());
+        file.write_all(&dev)?;
+        Ok(KbdBacklight { file })
+    }
+
+    /// Blocks until the brightness file is written and returns the new percentage
+    pub fn wait(&mut self) -> io::Result<u8> {
+        let mut buf = [0u8; 4];
+        self.file.read_exact(&mut buf)?;
+        Ok(i32::from_ne_bytes(buf).clamp(0, MAX_BRIGHTNESS) as u8)
+    }
+}
+
+/// Shows a brightness change made through the daemon on the LED, so the
+/// desktop OSD starts from the right value. The udev rules make the brightness
+/// file writable for the razercontrol group, without them this does nothing.
+pub fn publish(percent: u8) {
+    let path = format!("/sys/class/leds/{}/brightness", LED_NAME);
+    if let Err(e) = fs::write(&path, percent.min(MAX_BRIGHTNESS as u8).to_string()) {
+        if e.kind() != io::ErrorKind::NotFound && e.kind() != io::ErrorKind::PermissionDenied {
+            eprintln!("Cannot write {}: {}", path, e);
+        }
+    }
+}
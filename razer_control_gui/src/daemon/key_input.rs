@@ -0,0 +1,113 @@
+// Key presses of the laptop keyboard for the reactive effects, read from its
+// evdev node. Only the key codes of presses are passed on, nothing is kept.
+// Reading the Razer keyboard as the user needs the opt-in
+// 99-razercontrol-key-input.rules, which install.sh leaves out.
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+const INPUT_CLASS: &str = "/sys/class/input";
+const RAZER_VENDOR: &str = "1532";
+const EV_KEY: u16 = 0x01;
+const KEY_PRESS: i32 = 1; // 0 is a release, 2 a repeat
+const KEY_A: usize = 30;
+
+pub struct KeyInput {
+    file: File,
+}
+
+impl KeyInput {
+    pub fn open(path: &Path) -> io::Result<KeyInput> {
+        Ok(KeyInput { file: File::open(path)? })
+    }
+
+    /// Blocks until a key is pressed and returns its Linux input key code
+    pub fn wait(&mut self) -> io::Result<u16> {
+        let mut buf = [0u8; mem::size_of::<libc::input_event>()];
+        loop {
+            self.file.read_exact(&mut buf)?;
+            if let Some(code) = key_press(&buf) {
+                return Ok(code);
+            }
+        }
+    }
+}
+
+/// Key code of a `struct input_event` that presses a key
+pub fn key_press(event: &[u8]) -> Option<u16> {
+    // struct input_event { struct timeval time; __u16 type; __u16 code; __s32 value; }
+    let at = mem::size_of::<libc::timeval>();
+    let field = |offset: usize, len: usize| event.get(at + offset..at + offset + len);
+    let kind = u16::from_ne_bytes(field(0, 2)?.try_into().ok()?);
+    let code = u16::from_ne_bytes(field(2, 2)?.try_into().ok()?);
+    let value = i32::from_ne_bytes(field(4, 4)?.try_into().ok()?);
+    if kind == EV_KEY && value == KEY_PRESS {
+        return Some(code);
+    }
+    None
+}
+
+/// Event node of the built in Razer keyboard: the one with letter keys,
+/// the other nodes of the device only send media and power keys
+pub fn find_keyboard() -> Option<PathBuf> {
+    let mut nodes: Vec<PathBuf> = fs::read_dir(INPUT_CLASS).ok()?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.file_name().map_or(false, |n| n.to_string_lossy().starts_with("event")))
+        .collect();
+    nodes.sort();
+    for node in nodes {
+        let device = node.join("device");
+        let vendor = fs::read_to_string(device.join("id/vendor")).unwrap_or_default();
+        if vendor.trim() != RAZER_VENDOR || !has_key(&device, KEY_A) {
+            continue;
+        }
+        if let Some(name) = node.file_name() {
+            return Some(Path::new("/dev/input").join(name));
+        }
+    }
+    None
+}
+
+/// Whether the capabilities of an input device list a key code. The bitmap
+/// is written as hex words of the kernel's long, the highest word first.
+fn has_key(device: &Path, code: usize) -> bool {
+    let caps = fs::read_to_string(device.join("capabilities/key")).unwrap_or_default();
+    let bits = mem::size_of::<libc::c_ulong>() * 8;
+    let word = caps.split_whitespace().rev().nth(code / bits);
+    match word.and_then(|w| u64::from_str_radix(w, 16).ok()) {
+        Some(w) => w & (1 << (code % bits)) != 0,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: u16, code: u16, value: i32) -> Vec<u8> {
+        let mut bytes = vec![0u8; mem::size_of::<libc::timeval>()];
+        bytes.extend_from_slice(&kind.to_ne_bytes());
+        bytes.extend_from_slice(&code.to_ne_bytes());
+        bytes.extend_from_slice(&value.to_ne_bytes());
+        assert_eq!(bytes.len(), mem::size_of::<libc::input_event>());
+        bytes
+    }
+
+    #[test]
+    fn only_presses_are_passed_on() {
+        assert_eq!(key_press(&event(EV_KEY, 30, 1)), Some(30));
+        assert_eq!(key_press(&event(EV_KEY, 30, 0)), None); // release
+        assert_eq!(key_press(&event(EV_KEY, 30, 2)), None); // repeat
+        assert_eq!(key_press(&event(0x00, 0, 1)), None); // EV_SYN
+        assert_eq!(key_press(&event(0x04, 4, 1)), None); // EV_MSC scan code
+    }
+
+    #[test]
+    fn short_events_are_ignored() {
+        let bytes = event(EV_KEY, 30, 1);
+        assert_eq!(key_press(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(key_press(&[]), None);
+    }
+}
@@ -580,6 +580,28 @@ impl dbus::message::SignalArgs for OrgFreedesktopDBusPropertiesPropertiesChanged
     // }
 // }
 
+// The Manager properties the daemon reads. LidClosed does not emit
+// PropertiesChanged, so callers have to poll it.
+pub trait OrgFreedesktopLogin1ManagerState {
+    fn idle_hint(&self) -> Result<bool, dbus::Error>;
+    fn idle_since_hint_monotonic(&self) -> Result<u64, dbus::Error>;
+    fn lid_closed(&self) -> Result<bool, dbus::Error>;
+}
+
+impl<'a, T: blocking::BlockingSender, C: ::std::ops::Deref<Target=T>> OrgFreedesktopLogin1ManagerState for blocking::Proxy<'a, C> {
+    fn idle_hint(&self) -> Result<bool, dbus::Error> {
+        <Self as blocking::stdintf::org_freedesktop_dbus::Properties>::get(&self, "org.freedesktop.login1.Manager", "IdleHint")
+    }
+
+    fn idle_since_hint_monotonic(&self) -> Result<u64, dbus::Error> {
+        <Self as blocking::stdintf::org_freedesktop_dbus::Properties>::get(&self, "org.freedesktop.login1.Manager", "IdleSinceHintMonotonic")
+    }
+
+    fn lid_closed(&self) -> Result<bool, dbus::Error> {
+        <Self as blocking::stdintf::org_freedesktop_dbus::Properties>::get(&self, "org.freedesktop.login1.Manager", "LidClosed")
+    }
+}
+
 #[derive(Debug)]
 pub struct OrgFreedesktopLogin1ManagerSessionNew {
     pub session_id: String,
@@ -0,0 +1,270 @@
+// Metrics for monitoring systems, in the OpenMetrics text format for scrapers
+// that ask for it and in the Prometheus text format for everything else,
+// the node_exporter textfile collector included. Served over HTTP on a Unix
+// socket or a loopback port, e.g.
+//   curl --unix-socket /run/user/1000/razercontrol-metrics.sock http://localhost/metrics
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::time::Duration;
+use crate::comms::{GpuInfo, TelemetrySample};
+
+const MAX_REQUEST: usize = 8192;
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+const POWER_MODES: [&str; 5] = ["balanced", "gaming", "creator", "silent", "custom"];
+const RUNTIME_STATES: [&str; 5] = ["active", "suspended", "suspending", "resuming", "unsupported"];
+
+/// Daemon state at the time of a scrape
+pub struct Snapshot {
+    pub fan_rpm: i32, // measured
+    pub fan_target_rpm: i32, // configured, 0 = automatic
+    pub power_mode: u8, // in effect, the low battery rule can differ from the configuration
+    pub configured_power_mode: u8,
+    pub cpu_boost: u8,
+    pub gpu_boost: u8,
+    pub bho_on: bool,
+    pub bho_threshold: u8,
+    pub ac_online: bool,
+    pub gpus: Vec<GpuInfo>,
+    pub sensors: Option<TelemetrySample>, // latest telemetry sample
+}
+
+struct Writer {
+    out: String,
+    openmetrics: bool,
+}
+
+impl Writer {
+    fn family(&mut self, name: &str, kind: &str, unit: Option<&str>, help: &str) {
+        // Prometheus text has no state sets and no units
+        let kind = if kind == "stateset" && !self.openmetrics { "gauge" } else { kind };
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+        if let (Some(unit), true) = (unit, self.openmetrics) {
+            let _ = writeln!(self.out, "# UNIT {} {}", name, unit);
+        }
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+                .collect();
+            let _ = write!(self.out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.out, " {}", value);
+    }
+
+    fn gauge(&mut self, name: &str, unit: Option<&str>, help: &str, value: f64) {
+        self.family(name, "gauge", unit, help);
+        self.sample(name, &[], value);
+    }
+
+    fn stateset(&mut self, name: &str, help: &str, states: &[&str], state: &str) {
+        self.family(name, "stateset", None, help);
+        for s in states {
+            self.sample(name, &[(name, s)], if *s == state { 1.0 } else { 0.0 });
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn bool_value(value: bool) -> f64 {
+    if value { 1.0 } else { 0.0 }
+}
+
+pub fn render(s: &Snapshot, openmetrics: bool) -> String {
+    let mut w = Writer { out: String::new(), openmetrics };
+    let mode = |mode: u8| POWER_MODES.get(mode as usize).copied().unwrap_or("unknown");
+
+    w.gauge("razer_fan_rpm", Some("rpm"), "Fan speed measured by the embedded controller.", s.fan_rpm as f64);
+    w.gauge("razer_fan_target_rpm", Some("rpm"), "Configured fan speed, 0 in automatic mode.", s.fan_target_rpm as f64);
+    w.stateset("razer_fan_mode", "Configured fan mode.", &["auto", "manual"],
+        if s.fan_target_rpm == 0 { "auto" } else { "manual" });
+    w.stateset("razer_power_mode", "Power mode in effect.", &POWER_MODES, mode(s.power_mode));
+    w.stateset("razer_configured_power_mode", "Power mode configured for the current power source.",
+        &POWER_MODES, mode(s.configured_power_mode));
+    w.gauge("razer_cpu_boost", None, "Configured CPU boost of the custom power mode, 0 (low) to 3 (boost).", s.cpu_boost as f64);
+    w.gauge("razer_gpu_boost", None, "Configured GPU boost of the custom power mode, 0 (low) to 2 (high).", s.gpu_boost as f64);
+    w.gauge("razer_bho_enabled", None, "1 if the battery health optimizer limits the charge.", bool_value(s.bho_on));
+    w.gauge("razer_bho_threshold_percent", Some("percent"), "Charge limit of the battery health optimizer.", s.bho_threshold as f64);
+    w.gauge("razer_ac_online", None, "1 on mains power.", bool_value(s.ac_online));
+
+    w.family("razer_gpu_runtime_status", "stateset", None, "Runtime power management status of each GPU.");
+    for gpu in &s.gpus {
+        for state in RUNTIME_STATES {
+            let labels = [
+                ("pci_slot", gpu.pci_slot.as_str()),
+                ("name", gpu.name.as_str()),
+                ("driver", gpu.driver.as_str()),
+                ("type", gpu.gpu_type.as_str()),
+                ("razer_gpu_runtime_status", state),
+            ];
+            w.sample("razer_gpu_runtime_status", &labels, bool_value(gpu.runtime_status == state));
+        }
+    }
+
+    if let Some(sensors) = &s.sensors {
+        w.family("razer_temperature_celsius", "gauge", Some("celsius"), "Sensor temperatures, see razer-cli read sensors.");
+        for (sensor, value) in [("cpu", sensors.cpu_temp), ("igpu", sensors.igpu_temp), ("dgpu", sensors.dgpu_temp)] {
+            if let Some(value) = value {
+                w.sample("razer_temperature_celsius", &[("sensor", sensor)], value as f64);
+            }
+        }
+        w.family("razer_power_watts", "gauge", Some("watts"), "CPU package power and battery power, negative while discharging.");
+        for (sensor, value) in [("cpu_package", sensors.package_w), ("battery", sensors.battery_w)] {
+            if let Some(value) = value {
+                w.sample("razer_power_watts", &[("sensor", sensor)], value as f64);
+            }
+        }
+    }
+
+    if openmetrics {
+        w.out.push_str("# EOF\n");
+    }
+    return w.out;
+}
+
+/// Binds the socket, replacing one left behind by a daemon that did not exit cleanly
+pub fn bind_unix(path: &str) -> io::Result<UnixListener> {
+    if fs::symlink_metadata(path).map_or(false, |m| m.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+pub fn serve_unix(listener: UnixListener, render: impl Fn(bool) -> String) {
+    for stream in listener.incoming().flatten() {
+        let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+        if let Err(e) = respond(stream, &render) {
+            eprintln!("Metrics request failed: {}", e);
+        }
+    }
+}
+
+pub fn serve_http(listener: TcpListener, render: impl Fn(bool) -> String) {
+    for stream in listener.incoming().flatten() {
+        let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+        if let Err(e) = respond(stream, &render) {
+            eprintln!("Metrics request failed: {}", e);
+        }
+    }
+}
+
+fn respond(mut stream: impl Read + Write, render: &impl Fn(bool) -> String) -> io::Result<()> {
+    let mut request: Vec<u8> = vec![];
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut line = request.lines().next().unwrap_or("").split_whitespace();
+    let (method, path) = (line.next().unwrap_or(""), line.next().unwrap_or(""));
+    if method != "GET" || !(path == "/metrics" || path == "/") {
+        let body = "Not found, metrics are at /metrics\n";
+        return write!(stream, "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(), body);
+    }
+    // Prometheus asks for OpenMetrics in its Accept header
+    let openmetrics = request.lines()
+        .any(|l| l.to_ascii_lowercase().starts_with("accept:") && l.contains("application/openmetrics-text"));
+    let content_type = if openmetrics {
+        "application/openmetrics-text; version=1.0.0; charset=utf-8"
+    } else {
+        "text/plain; version=0.0.4; charset=utf-8"
+    };
+    let body = render(openmetrics);
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        content_type, body.len(), body)
+}
+
+/// Replaces the file in one step, node_exporter must not read half of it
+pub fn write_textfile(path: &str, body: &str) -> io::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, body)?;
+    fs::rename(&tmp, Path::new(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            fan_rpm: 3500,
+            fan_target_rpm: 0,
+            power_mode: 1,
+            configured_power_mode: 4,
+            cpu_boost: 2,
+            gpu_boost: 1,
+            bho_on: true,
+            bho_threshold: 80,
+            ac_online: true,
+            gpus: vec![GpuInfo {
+                name: String::from("RTX \"4070\""),
+                pci_slot: String::from("0000:01:00.0"),
+                driver: String::from("nvidia"),
+                gpu_type: String::from("discrete"),
+                runtime_status: String::from("suspended"),
+            }],
+            sensors: Some(TelemetrySample {
+                time: 0,
+                cpu_temp: Some(65.5),
+                igpu_temp: None,
+                dgpu_temp: Some(50.0),
+                fan_rpm: 3500,
+                power_mode: 1,
+                package_w: Some(12.0),
+                battery_w: None,
+                on_ac: true,
+            }),
+        }
+    }
+
+    #[test]
+    fn openmetrics_has_units_state_sets_and_eof() {
+        let out = render(&snapshot(), true);
+        assert!(out.ends_with("\n# EOF\n"));
+        assert_eq!(out.matches("# EOF").count(), 1);
+        assert!(out.contains("# TYPE razer_fan_rpm gauge\n# UNIT razer_fan_rpm rpm\n# HELP razer_fan_rpm "));
+        assert!(out.contains("# TYPE razer_power_mode stateset\n"));
+        // The state label is named after its family
+        assert!(out.contains("razer_power_mode{razer_power_mode=\"gaming\"} 1\n"));
+        assert!(out.contains("razer_power_mode{razer_power_mode=\"balanced\"} 0\n"));
+        assert!(out.contains("razer_configured_power_mode{razer_configured_power_mode=\"custom\"} 1\n"));
+        assert!(out.contains("razer_fan_mode{razer_fan_mode=\"auto\"} 1\n"));
+        assert!(out.contains("razer_gpu_runtime_status{pci_slot=\"0000:01:00.0\",name=\"RTX \\\"4070\\\"\",driver=\"nvidia\",type=\"discrete\",razer_gpu_runtime_status=\"suspended\"} 1\n"));
+        assert!(out.contains("razer_temperature_celsius{sensor=\"cpu\"} 65.5\n"));
+        assert!(!out.contains("sensor=\"igpu\""));
+        assert!(out.contains("razer_power_watts{sensor=\"cpu_package\"} 12\n"));
+    }
+
+    #[test]
+    fn prometheus_text_has_gauges_only() {
+        let out = render(&snapshot(), false);
+        assert!(!out.contains("# EOF"));
+        assert!(!out.contains("# UNIT"));
+        assert!(!out.contains("stateset"));
+        assert!(out.contains("# TYPE razer_power_mode gauge\n"));
+        assert!(out.contains("razer_power_mode{razer_power_mode=\"gaming\"} 1\n"));
+    }
+
+    #[test]
+    fn sensors_are_left_out_before_the_first_sample() {
+        let out = render(&Snapshot { sensors: None, ..snapshot() }, true);
+        assert!(!out.contains("razer_temperature_celsius"));
+        assert!(out.ends_with("# EOF\n"));
+    }
+}
@@ -0,0 +1,129 @@
+// Minimal binding for the ActiveProfile property of power-profiles-daemon,
+// the service behind the power profile menus of GNOME and KDE
+use dbus::blocking;
+
+pub const BUS_NAME: &str = "net.hadess.PowerProfiles";
+pub const PATH: &str = "/net/hadess/PowerProfiles";
+pub const INTERFACE: &str = "net.hadess.PowerProfiles";
+
+pub const POWER_SAVER: &str = "power-saver";
+pub const BALANCED: &str = "balanced";
+pub const PERFORMANCE: &str = "performance";
+
+pub trait NetHadessPowerProfiles {
+    fn active_profile(&self) -> Result<String, dbus::Error>;
+    fn set_active_profile(&self, value: String) -> Result<(), dbus::Error>;
+}
+
+impl<'a, C: ::std::ops::Deref<Target=blocking::Connection>> NetHadessPowerProfiles for blocking::Proxy<'a, C> {
+    fn active_profile(&self) -> Result<String, dbus::Error> {
+        <Self as blocking::stdintf::org_freedesktop_dbus::Properties>::get(self, INTERFACE, "ActiveProfile")
+    }
+
+    fn set_active_profile(&self, value: String) -> Result<(), dbus::Error> {
+        <Self as blocking::stdintf::org_freedesktop_dbus::Properties>::set(self, INTERFACE, "ActiveProfile", value)
+    }
+}
+
+/// Connects to the bus power-profiles-daemon lives on. Setting
+/// RAZERCONTROL_PPD_BUS=session points the daemon to a stand-in service on
+/// the session bus for testing.
+pub fn connect() -> Result<blocking::Connection, dbus::Error> {
+    match std::env::var("RAZERCONTROL_PPD_BUS") {
+        Ok(bus) if bus == "session" => blocking::Connection::new_session(),
+        _ => blocking::Connection::new_system(),
+    }
+}
+
+/// Calls the daemon makes outside the device manager lock, a D-Bus call
+/// can take up to its timeout
+#[derive(Clone, PartialEq, Debug)]
+pub enum Request {
+    /// Set the desktop profile
+    Set(String),
+    /// Read the desktop profile and follow it
+    Read,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbus::arg::{PropMap, RefArg, Variant};
+    use dbus::blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+    use dbus::channel::{MatchingReceiver, Sender};
+    use dbus::message::{MatchRule, SignalArgs};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::{thread, time};
+
+    /// Stand-in for power-profiles-daemon on the session bus, answers
+    /// Get and Set of ActiveProfile and signals changes like the real one
+    fn stand_in(stop: Arc<AtomicBool>, ready: mpsc::Sender<()>) {
+        let conn = blocking::Connection::new_session().unwrap();
+        conn.request_name(BUS_NAME, false, true, false).unwrap();
+        let active = Arc::new(Mutex::new(BALANCED.to_string()));
+        conn.start_receive(MatchRule::new_method_call(), Box::new(move |msg, conn| {
+            let reply = match msg.member().as_deref() {
+                Some("Get") => msg.method_return().append1(Variant(active.lock().unwrap().clone())),
+                Some("Set") => {
+                    let (_, _, profile): (String, String, Variant<String>) = msg.read3().unwrap();
+                    *active.lock().unwrap() = profile.0.clone();
+                    let mut changed = PropMap::new();
+                    changed.insert("ActiveProfile".to_string(), Variant(Box::new(profile.0) as Box<dyn RefArg>));
+                    let signal = PropertiesPropertiesChanged {
+                        interface_name: INTERFACE.to_string(),
+                        changed_properties: changed,
+                        invalidated_properties: vec![],
+                    };
+                    let _ = conn.send(signal.to_emit_message(&PATH.into()));
+                    msg.method_return()
+                }
+                _ => return true,
+            };
+            let _ = conn.send(reply);
+            true
+        }));
+        ready.send(()).unwrap();
+        while !stop.load(Ordering::Relaxed) {
+            conn.process(time::Duration::from_millis(50)).unwrap();
+        }
+    }
+
+    /// Needs a session bus to host the stand-in, run it with
+    /// `RAZERCONTROL_PPD_BUS=session dbus-run-session cargo test -- --ignored`
+    #[test]
+    #[ignore = "needs dbus-run-session"]
+    fn follows_a_stand_in_service() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let service = {
+            let stop = stop.clone();
+            thread::spawn(move || stand_in(stop, ready_tx))
+        };
+        ready_rx.recv().unwrap();
+
+        let conn = connect().unwrap();
+        let proxy = conn.with_proxy(BUS_NAME, PATH, time::Duration::from_millis(1000));
+        assert_eq!(proxy.active_profile().unwrap(), BALANCED);
+
+        let seen = Arc::new(Mutex::new(None));
+        let signal_seen = seen.clone();
+        proxy.match_signal(move |h: PropertiesPropertiesChanged, _: &blocking::Connection, _: &dbus::Message| {
+            let profile: Option<&String> = dbus::arg::prop_cast(&h.changed_properties, "ActiveProfile");
+            *signal_seen.lock().unwrap() = profile.cloned();
+            true
+        }).unwrap();
+        proxy.set_active_profile(PERFORMANCE.to_string()).unwrap();
+        assert_eq!(proxy.active_profile().unwrap(), PERFORMANCE);
+        for _ in 0..20 {
+            if seen.lock().unwrap().is_some() {
+                break;
+            }
+            conn.process(time::Duration::from_millis(50)).unwrap();
+        }
+        assert_eq!(seen.lock().unwrap().as_deref(), Some(PERFORMANCE));
+
+        stop.store(true, Ordering::Relaxed);
+        service.join().unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+use std::time;
+use dbus::blocking::Connection;
+use service::sensors;
+use crate::battery::OrgFreedesktopUPowerDevice;
+
+pub const UPOWER: &str = "org.freedesktop.UPower";
+const UPOWER_PATH: &str = "/org/freedesktop/UPower";
+
+// UPower device types
+const TYPE_LINE_POWER: u32 = 1;
+const TYPE_BATTERY: u32 = 2;
+
+// UPower battery states in which the charger has stopped
+pub const STATE_FULLY_CHARGED: u32 = 4;
+pub const STATE_PENDING_CHARGE: u32 = 5;
+
+/// The mains adapters and the laptop battery, found through UPower or,
+/// when UPower is not running, with the sysfs readers of `service::sensors`.
+/// Adapter names differ between models (AC0, ADP1, ACAD, ...), so nothing
+/// is hardcoded.
+#[derive(Clone)]
+pub struct PowerSupplies {
+    pub upower: bool, // false: sysfs names that have to be polled
+    pub adapters: Vec<String>, // UPower object paths or sysfs names
+    pub battery: Option<String>,
+}
+
+impl PowerSupplies {
+    pub fn discover(conn: &Connection) -> PowerSupplies {
+        match PowerSupplies::discover_upower(conn) {
+            Some(res) if !res.adapters.is_empty() => return res,
+            Some(_) => println!("UPower reports no AC adapter, reading sysfs"),
+            None => println!("UPower not available, reading sysfs"),
+        }
+        return PowerSupplies::discover_sysfs();
+    }
+
+    fn discover_upower(conn: &Connection) -> Option<PowerSupplies> {
+        let proxy = conn.with_proxy(UPOWER, UPOWER_PATH, time::Duration::from_millis(5000));
+        let (paths,): (Vec<dbus::Path<'static>>,) = proxy
+            .method_call("org.freedesktop.UPower", "EnumerateDevices", ())
+            .ok()?;
+        let mut res = PowerSupplies { upower: true, adapters: vec![], battery: None };
+        for path in paths {
+            let device = conn.with_proxy(UPOWER, &path, time::Duration::from_millis(5000));
+            // Mice and headsets report batteries too, they are not a power supply
+            if !device.power_supply().unwrap_or(false) {
+                continue;
+            }
+            match device.type_() {
+                Ok(TYPE_LINE_POWER) => res.adapters.push(path.to_string()),
+                Ok(TYPE_BATTERY) if res.battery.is_none() => res.battery = Some(path.to_string()),
+                _ => {}
+            }
+        }
+        println!("power supplies: adapters {:?}, battery {:?}", res.adapters, res.battery);
+        return Some(res);
+    }
+
+    fn discover_sysfs() -> PowerSupplies {
+        let res = PowerSupplies { upower: false, adapters: sensors::mains_adapters(), battery: sensors::battery_name() };
+        println!("power supplies: adapters {:?}, battery {:?}", res.adapters, res.battery);
+        return res;
+    }
+
+    /// True if any adapter is online, None if it cannot be read
+    pub fn ac_online(&self, conn: &Connection) -> Option<bool> {
+        let mut states = self.adapters.iter().filter_map(|adapter| {
+            if self.upower {
+                conn.with_proxy(UPOWER, adapter.as_str(), time::Duration::from_millis(5000))
+                    .online()
+                    .ok()
+            } else {
+                sensors::adapter_online(adapter)
+            }
+        }).peekable();
+        states.peek()?;
+        return Some(states.any(|online| online));
+    }
+
+    pub fn battery_percentage(&self, conn: &Connection) -> Option<f64> {
+        let battery = self.battery.as_ref()?;
+        if self.upower {
+            conn.with_proxy(UPOWER, battery.as_str(), time::Duration::from_millis(5000))
+                .percentage()
+                .ok()
+        } else {
+            sensors::read_battery(battery).percent
+        }
+    }
+
+    /// Whether the charger has stopped: the battery is full, or holds its
+    /// charge on AC ("Not charging"), None if it cannot be read
+    pub fn battery_charge_done(&self, conn: &Connection) -> Option<bool> {
+        let battery = self.battery.as_ref()?;
+        if self.upower {
+            conn.with_proxy(UPOWER, battery.as_str(), time::Duration::from_millis(5000))
+                .state()
+                .ok()
+                .map(|state| state == STATE_FULLY_CHARGED || state == STATE_PENDING_CHARGE)
+        } else {
+            match sensors::read_battery(battery).status.as_str() {
+                "" => None,
+                status => Some(status == "Full" || status == "Not charging"),
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::{fs, io};
+use crate::comms::TelemetrySample;
+use crate::config;
+
+pub const TELEMETRY_FILE: &str = "telemetry.json"; // in the state directory
+pub const SAMPLE_SECS: u64 = 5;
+const MAX_SAMPLES: usize = (24 * 60 * 60 / SAMPLE_SECS) as usize; // one day
+const MAX_POINTS: u64 = 2000; // per reply, keeps it well below the socket message limit
+
+/// Sensor, fan and power mode samples of the last day, oldest first
+#[derive(Serialize, Deserialize)]
+#[serde(default = "Telemetry::new")]
+pub struct Telemetry {
+    samples: VecDeque<TelemetrySample>,
+}
+
+impl Telemetry {
+    pub fn new() -> Telemetry {
+        return Telemetry { samples: VecDeque::new() };
+    }
+
+    pub fn load() -> Telemetry {
+        let path = match config::state_dir() {
+            Ok(dir) => dir.join(TELEMETRY_FILE),
+            Err(_) => return Telemetry::new(),
+        };
+        match fs::read_to_string(&path) {
+            Ok(str) => serde_json::from_str(&str).unwrap_or_else(|e| {
+                eprintln!("Error reading {:?}: {}, starting without telemetry", path, e);
+                Telemetry::new()
+            }),
+            Err(_) => Telemetry::new(),
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let j = serde_json::to_string(self)?;
+        config::write_atomic(&config::state_dir()?.join(TELEMETRY_FILE), j.as_bytes())
+    }
+
+    pub fn record(&mut self, sample: TelemetrySample) {
+        // A clock set back would leave the buffer unordered
+        while self.samples.back().map_or(false, |s| s.time > sample.time) {
+            self.samples.pop_back();
+        }
+        self.samples.push_back(sample);
+        while self.samples.len() > MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn latest(&self) -> Option<&TelemetrySample> {
+        self.samples.back()
+    }
+
+    /// Samples from `since` on, merged into one per `resolution` seconds.
+    /// Long ranges get a coarser resolution to fit into one reply.
+    pub fn query(&self, since: u64, resolution: u64) -> Vec<TelemetrySample> {
+        let first = self.samples.partition_point(|s| s.time < since);
+        let samples: Vec<&TelemetrySample> = self.samples.range(first..).collect();
+        let span = match (samples.first(), samples.last()) {
+            (Some(a), Some(b)) => b.time - a.time,
+            _ => return vec![],
+        };
+        let resolution = resolution.max(span.div_ceil(MAX_POINTS));
+        if resolution <= SAMPLE_SECS {
+            return samples.into_iter().cloned().collect();
+        }
+        samples.chunk_by(|a, b| a.time / resolution == b.time / resolution)
+            .map(|chunk| merge(chunk, resolution))
+            .collect()
+    }
+}
+
+/// One sample for an interval. Temperatures are the peak, throttling
+/// follows those, everything else is the average or the last value.
+fn merge(chunk: &[&TelemetrySample], resolution: u64) -> TelemetrySample {
+    let peak = |value: fn(&TelemetrySample) -> Option<f32>| {
+        chunk.iter().filter_map(|s| value(s)).reduce(f32::max)
+    };
+    let mean = |value: fn(&TelemetrySample) -> Option<f32>| {
+        let values: Vec<f32> = chunk.iter().filter_map(|s| value(s)).collect();
+        if values.is_empty() {
+            return None;
+        }
+        Some(values.iter().sum::<f32>() / values.len() as f32)
+    };
+    let last = chunk[chunk.len() - 1];
+    TelemetrySample {
+        time: chunk[0].time / resolution * resolution,
+        cpu_temp: peak(|s| s.cpu_temp),
+        igpu_temp: peak(|s| s.igpu_temp),
+        dgpu_temp: peak(|s| s.dgpu_temp),
+        fan_rpm: chunk.iter().map(|s| s.fan_rpm).sum::<i32>() / chunk.len() as i32,
+        power_mode: last.power_mode,
+        package_w: mean(|s| s.package_w),
+        battery_w: mean(|s| s.battery_w),
+        on_ac: last.on_ac,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(time: u64, cpu_temp: f32, fan_rpm: i32) -> TelemetrySample {
+        TelemetrySample {
+            time,
+            cpu_temp: Some(cpu_temp),
+            igpu_temp: None,
+            dgpu_temp: None,
+            fan_rpm,
+            power_mode: (time / SAMPLE_SECS % 4) as u8,
+            package_w: Some(time as f32),
+            battery_w: None,
+            on_ac: true,
+        }
+    }
+
+    fn times(samples: &[TelemetrySample]) -> Vec<u64> {
+        samples.iter().map(|s| s.time).collect()
+    }
+
+    #[test]
+    fn query_starts_at_since() {
+        let mut telemetry = Telemetry::new();
+        for time in (1000..1100).step_by(SAMPLE_SECS as usize) {
+            telemetry.record(sample(time, 50.0, 3000));
+        }
+        assert_eq!(times(&telemetry.query(1090, 0)), vec![1090, 1095]);
+        assert_eq!(times(&telemetry.query(1091, 0)), vec![1095]);
+        assert_eq!(telemetry.query(1000, 0).len(), 20);
+        assert!(telemetry.query(2000, 0).is_empty());
+        assert!(Telemetry::new().query(0, 0).is_empty());
+    }
+
+    #[test]
+    fn query_merges_intervals() {
+        let mut telemetry = Telemetry::new();
+        for (time, temp, rpm) in [(1200, 60.0, 3000), (1205, 80.0, 4000), (1210, 70.0, 5000), (1260, 40.0, 2000)] {
+            telemetry.record(sample(time, temp, rpm));
+        }
+        let merged = telemetry.query(0, 60);
+        assert_eq!(times(&merged), vec![1200, 1260]);
+        // Peak temperature, mean power and fan, last power mode
+        assert_eq!(merged[0].cpu_temp, Some(80.0));
+        assert_eq!(merged[0].package_w, Some(1205.0));
+        assert_eq!(merged[0].fan_rpm, 4000);
+        assert_eq!(merged[0].power_mode, sample(1210, 0.0, 0).power_mode);
+        assert_eq!(merged[0].igpu_temp, None);
+        assert_eq!(merged[1].cpu_temp, Some(40.0));
+    }
+
+    #[test]
+    fn long_ranges_fit_into_a_reply() {
+        let mut telemetry = Telemetry::new();
+        for n in 0..MAX_SAMPLES as u64 + 10 {
+            telemetry.record(sample(n * SAMPLE_SECS, 50.0, 3000));
+        }
+        assert_eq!(telemetry.samples.len(), MAX_SAMPLES);
+        let all = telemetry.query(0, 0);
+        assert!(all.len() as u64 <= MAX_POINTS, "{} points", all.len());
+        assert!(all.len() as u64 > MAX_POINTS / 2, "{} points", all.len());
+        assert!(all.windows(2).all(|w| w[0].time < w[1].time));
+        // A finer resolution than a sample returns the samples themselves
+        assert_eq!(telemetry.query(telemetry.latest().unwrap().time - 20, 1).len(), 5);
+    }
+
+    #[test]
+    fn a_clock_set_back_drops_later_samples() {
+        let mut telemetry = Telemetry::new();
+        for time in [100, 105, 110, 115] {
+            telemetry.record(sample(time, 50.0, 3000));
+        }
+        telemetry.record(sample(107, 50.0, 3000));
+        assert_eq!(times(&telemetry.query(0, 0)), vec![100, 105, 107]);
+        assert_eq!(telemetry.latest().map(|s| s.time), Some(107));
+        telemetry.record(sample(107, 50.0, 3000));
+        assert_eq!(times(&telemetry.query(0, 0)), vec![100, 105, 107, 107]);
+    }
+}
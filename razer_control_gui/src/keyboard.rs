@@ -0,0 +1,352 @@
+//! Key layouts of the keyboard matrix: the name and physical place of
+//! every key. Matrix positions without a key have no entry.
+use serde::{Deserialize, Serialize};
+
+// The matrix of most per key RGB laptops, the layouts are drawn for it
+pub const ROWS: usize = 6;
+pub const COLUMNS: usize = 15;
+
+pub const LAYOUTS: [&str; 3] = ["ansi", "iso", "jis"];
+
+/// Backlight of a laptop keyboard, from the device database
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Lighting {
+    /// No backlight
+    None,
+    /// One fixed colour, only the brightness and a few hardware effects
+    SingleColour,
+    /// The whole keyboard in one RGB colour
+    Zone,
+    /// A colour per key
+    #[default]
+    PerKey,
+}
+
+impl Lighting {
+    pub fn name(self) -> &'static str {
+        match self {
+            Lighting::None => "none",
+            Lighting::SingleColour => "single_colour",
+            Lighting::Zone => "zone",
+            Lighting::PerKey => "per_key",
+        }
+    }
+
+    /// Whether the daemon can animate custom effects on the keyboard
+    pub fn has_effects(self) -> bool {
+        self == Lighting::Zone || self == Lighting::PerKey
+    }
+
+    /// Hardware effects the keyboard supports, by their razer-cli name
+    pub fn standard_effects(self) -> &'static [&'static str] {
+        match self {
+            Lighting::None => &[],
+            Lighting::SingleColour => &["off", "static", "breathing"],
+            Lighting::Zone => &["off", "static", "breathing", "spectrum"],
+            Lighting::PerKey => &["off", "wave", "reactive", "breathing", "spectrum", "static", "starlight"],
+        }
+    }
+}
+
+/// Rows and columns of the keyboard matrix for devices that do not list one
+pub fn default_matrix() -> [usize; 2] {
+    [ROWS, COLUMNS]
+}
+
+/// Name, matrix column, width and height in key units
+type KeyDef = (&'static str, usize, f32, f32);
+
+const ROW_FN: [KeyDef; 15] = [
+    ("Esc", 0, 1.0, 1.0), ("F1", 1, 1.0, 1.0), ("F2", 2, 1.0, 1.0), ("F3", 3, 1.0, 1.0), ("F4", 4, 1.0, 1.0),
+    ("F5", 5, 1.0, 1.0), ("F6", 6, 1.0, 1.0), ("F7", 7, 1.0, 1.0), ("F8", 8, 1.0, 1.0), ("F9", 9, 1.0, 1.0),
+    ("F10", 10, 1.0, 1.0), ("F11", 11, 1.0, 1.0), ("F12", 12, 1.0, 1.0), ("PrtSc", 13, 1.0, 1.0), ("Del", 14, 1.0, 1.0),
+];
+
+// Digits are named like Digit1 so they cannot be mistaken for an index
+const ROW_NUM: [KeyDef; 14] = [
+    ("Grave", 0, 1.0, 1.0), ("Digit1", 1, 1.0, 1.0), ("Digit2", 2, 1.0, 1.0), ("Digit3", 3, 1.0, 1.0),
+    ("Digit4", 4, 1.0, 1.0), ("Digit5", 5, 1.0, 1.0), ("Digit6", 6, 1.0, 1.0), ("Digit7", 7, 1.0, 1.0),
+    ("Digit8", 8, 1.0, 1.0), ("Digit9", 9, 1.0, 1.0), ("Digit0", 10, 1.0, 1.0), ("Minus", 11, 1.0, 1.0),
+    ("Equal", 12, 1.0, 1.0), ("Backspace", 13, 2.0, 1.0),
+];
+
+const ROW_NUM_JIS: [KeyDef; 15] = [
+    ("Grave", 0, 1.0, 1.0), ("Digit1", 1, 1.0, 1.0), ("Digit2", 2, 1.0, 1.0), ("Digit3", 3, 1.0, 1.0),
+    ("Digit4", 4, 1.0, 1.0), ("Digit5", 5, 1.0, 1.0), ("Digit6", 6, 1.0, 1.0), ("Digit7", 7, 1.0, 1.0),
+    ("Digit8", 8, 1.0, 1.0), ("Digit9", 9, 1.0, 1.0), ("Digit0", 10, 1.0, 1.0), ("Minus", 11, 1.0, 1.0),
+    ("Equal", 12, 1.0, 1.0), ("Yen", 13, 1.0, 1.0), ("Backspace", 14, 1.0, 1.0),
+];
+
+const ROW_TAB: [KeyDef; 14] = [
+    ("Tab", 0, 1.5, 1.0), ("Q", 1, 1.0, 1.0), ("W", 2, 1.0, 1.0), ("E", 3, 1.0, 1.0), ("R", 4, 1.0, 1.0),
+    ("T", 5, 1.0, 1.0), ("Y", 6, 1.0, 1.0), ("U", 7, 1.0, 1.0), ("I", 8, 1.0, 1.0), ("O", 9, 1.0, 1.0),
+    ("P", 10, 1.0, 1.0), ("LBracket", 11, 1.0, 1.0), ("RBracket", 12, 1.0, 1.0), ("Backslash", 13, 1.5, 1.0),
+];
+
+// ISO and JIS have a tall Enter reaching into the home row
+const ROW_TAB_ISO: [KeyDef; 14] = [
+    ("Tab", 0, 1.5, 1.0), ("Q", 1, 1.0, 1.0), ("W", 2, 1.0, 1.0), ("E", 3, 1.0, 1.0), ("R", 4, 1.0, 1.0),
+    ("T", 5, 1.0, 1.0), ("Y", 6, 1.0, 1.0), ("U", 7, 1.0, 1.0), ("I", 8, 1.0, 1.0), ("O", 9, 1.0, 1.0),
+    ("P", 10, 1.0, 1.0), ("LBracket", 11, 1.0, 1.0), ("RBracket", 12, 1.0, 1.0), ("Enter", 13, 1.5, 2.0),
+];
+
+const ROW_CAPS: [KeyDef; 13] = [
+    ("CapsLock", 0, 1.75, 1.0), ("A", 1, 1.0, 1.0), ("S", 2, 1.0, 1.0), ("D", 3, 1.0, 1.0), ("F", 4, 1.0, 1.0),
+    ("G", 5, 1.0, 1.0), ("H", 6, 1.0, 1.0), ("J", 7, 1.0, 1.0), ("K", 8, 1.0, 1.0), ("L", 9, 1.0, 1.0),
+    ("Semicolon", 10, 1.0, 1.0), ("Apostrophe", 11, 1.0, 1.0), ("Enter", 12, 2.25, 1.0),
+];
+
+const ROW_CAPS_ISO: [KeyDef; 13] = [
+    ("CapsLock", 0, 1.5, 1.0), ("A", 1, 1.0, 1.0), ("S", 2, 1.0, 1.0), ("D", 3, 1.0, 1.0), ("F", 4, 1.0, 1.0),
+    ("G", 5, 1.0, 1.0), ("H", 6, 1.0, 1.0), ("J", 7, 1.0, 1.0), ("K", 8, 1.0, 1.0), ("L", 9, 1.0, 1.0),
+    ("Semicolon", 10, 1.0, 1.0), ("Apostrophe", 11, 1.0, 1.0), ("Hash", 12, 1.0, 1.0),
+];
+
+const ROW_CAPS_JIS: [KeyDef; 13] = [
+    ("CapsLock", 0, 1.5, 1.0), ("A", 1, 1.0, 1.0), ("S", 2, 1.0, 1.0), ("D", 3, 1.0, 1.0), ("F", 4, 1.0, 1.0),
+    ("G", 5, 1.0, 1.0), ("H", 6, 1.0, 1.0), ("J", 7, 1.0, 1.0), ("K", 8, 1.0, 1.0), ("L", 9, 1.0, 1.0),
+    ("Semicolon", 10, 1.0, 1.0), ("Apostrophe", 11, 1.0, 1.0), ("Backslash", 12, 1.0, 1.0),
+];
+
+const ROW_SHIFT: [KeyDef; 13] = [
+    ("LShift", 0, 2.25, 1.0), ("Z", 1, 1.0, 1.0), ("X", 2, 1.0, 1.0), ("C", 3, 1.0, 1.0), ("V", 4, 1.0, 1.0),
+    ("B", 5, 1.0, 1.0), ("N", 6, 1.0, 1.0), ("M", 7, 1.0, 1.0), ("Comma", 8, 1.0, 1.0), ("Period", 9, 1.0, 1.0),
+    ("Slash", 10, 1.0, 1.0), ("RShift", 11, 1.75, 1.0), ("Up", 12, 1.0, 1.0),
+];
+
+const ROW_SHIFT_ISO: [KeyDef; 14] = [
+    ("LShift", 0, 1.25, 1.0), ("NonUSBackslash", 1, 1.0, 1.0), ("Z", 2, 1.0, 1.0), ("X", 3, 1.0, 1.0),
+    ("C", 4, 1.0, 1.0), ("V", 5, 1.0, 1.0), ("B", 6, 1.0, 1.0), ("N", 7, 1.0, 1.0), ("M", 8, 1.0, 1.0),
+    ("Comma", 9, 1.0, 1.0), ("Period", 10, 1.0, 1.0), ("Slash", 11, 1.0, 1.0), ("RShift", 12, 1.75, 1.0),
+    ("Up", 13, 1.0, 1.0),
+];
+
+const ROW_SHIFT_JIS: [KeyDef; 14] = [
+    ("LShift", 0, 2.0, 1.0), ("Z", 1, 1.0, 1.0), ("X", 2, 1.0, 1.0), ("C", 3, 1.0, 1.0), ("V", 4, 1.0, 1.0),
+    ("B", 5, 1.0, 1.0), ("N", 6, 1.0, 1.0), ("M", 7, 1.0, 1.0), ("Comma", 8, 1.0, 1.0), ("Period", 9, 1.0, 1.0),
+    ("Slash", 10, 1.0, 1.0), ("Ro", 11, 1.0, 1.0), ("RShift", 12, 1.0, 1.0), ("Up", 13, 1.0, 1.0),
+];
+
+const ROW_SPACE: [KeyDef; 10] = [
+    ("LCtrl", 0, 1.0, 1.0), ("Fn", 1, 1.0, 1.0), ("Super", 2, 1.0, 1.0), ("LAlt", 3, 1.0, 1.0),
+    ("Space", 4, 6.0, 1.0), ("RAlt", 5, 1.0, 1.0), ("RCtrl", 6, 1.0, 1.0), ("Left", 7, 1.0, 1.0),
+    ("Down", 8, 1.0, 1.0), ("Right", 9, 1.0, 1.0),
+];
+
+const ROW_SPACE_JIS: [KeyDef; 13] = [
+    ("LCtrl", 0, 1.0, 1.0), ("Fn", 1, 1.0, 1.0), ("Super", 2, 1.0, 1.0), ("LAlt", 3, 1.0, 1.0),
+    ("Muhenkan", 4, 1.0, 1.0), ("Space", 5, 3.0, 1.0), ("Henkan", 6, 1.0, 1.0), ("Kana", 7, 1.0, 1.0),
+    ("RAlt", 8, 1.0, 1.0), ("RCtrl", 9, 1.0, 1.0), ("Left", 10, 1.0, 1.0), ("Down", 11, 1.0, 1.0),
+    ("Right", 12, 1.0, 1.0),
+];
+
+/// Linux input key codes (KEY_* in linux/input-event-codes.h) and the keys
+/// they are on. Codes listed twice are on differently named keys per layout.
+const EVDEV_KEYS: [(u16, &str); 85] = [
+    (1, "Esc"), (2, "Digit1"), (3, "Digit2"), (4, "Digit3"), (5, "Digit4"), (6, "Digit5"),
+    (7, "Digit6"), (8, "Digit7"), (9, "Digit8"), (10, "Digit9"), (11, "Digit0"), (12, "Minus"),
+    (13, "Equal"), (14, "Backspace"), (15, "Tab"), (16, "Q"), (17, "W"), (18, "E"), (19, "R"),
+    (20, "T"), (21, "Y"), (22, "U"), (23, "I"), (24, "O"), (25, "P"), (26, "LBracket"),
+    (27, "RBracket"), (28, "Enter"), (29, "LCtrl"), (30, "A"), (31, "S"), (32, "D"), (33, "F"),
+    (34, "G"), (35, "H"), (36, "J"), (37, "K"), (38, "L"), (39, "Semicolon"), (40, "Apostrophe"),
+    (41, "Grave"), (42, "LShift"), (43, "Backslash"), (43, "Hash"), (44, "Z"), (45, "X"), (46, "C"),
+    (47, "V"), (48, "B"), (49, "N"), (50, "M"), (51, "Comma"), (52, "Period"), (53, "Slash"),
+    (54, "RShift"), (56, "LAlt"), (57, "Space"), (58, "CapsLock"), (59, "F1"), (60, "F2"),
+    (61, "F3"), (62, "F4"), (63, "F5"), (64, "F6"), (65, "F7"), (66, "F8"), (67, "F9"), (68, "F10"),
+    (86, "NonUSBackslash"), (87, "F11"), (88, "F12"), (89, "Ro"), (92, "Henkan"), (93, "Kana"),
+    (94, "Muhenkan"), (97, "RCtrl"), (99, "PrtSc"), (100, "RAlt"), (103, "Up"), (105, "Left"),
+    (106, "Right"), (108, "Down"), (111, "Del"), (124, "Yen"), (125, "Super"),
+];
+
+/// A key, placed in key units from the top left corner
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Key {
+    pub name: String,
+    pub index: usize, // in the matrix, row by row
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Layout {
+    pub name: String,
+    pub rows: usize, // of the matrix the key indexes are in
+    pub columns: usize,
+    pub keys: Vec<Key>,
+}
+
+impl Layout {
+    /// Layout by name, see `LAYOUTS`
+    pub fn new(name: &str) -> Option<Layout> {
+        let rows: [&[KeyDef]; ROWS] = match name {
+            "ansi" => [&ROW_FN, &ROW_NUM, &ROW_TAB, &ROW_CAPS, &ROW_SHIFT, &ROW_SPACE],
+            "iso" => [&ROW_FN, &ROW_NUM, &ROW_TAB_ISO, &ROW_CAPS_ISO, &ROW_SHIFT_ISO, &ROW_SPACE],
+            "jis" => [&ROW_FN, &ROW_NUM_JIS, &ROW_TAB_ISO, &ROW_CAPS_JIS, &ROW_SHIFT_JIS, &ROW_SPACE_JIS],
+            _ => return None,
+        };
+        let mut keys = vec![];
+        for (row, defs) in rows.iter().enumerate() {
+            let mut x = 0.0;
+            for (name, col, width, height) in defs.iter() {
+                keys.push(Key {
+                    name: name.to_string(),
+                    index: row * COLUMNS + col,
+                    x,
+                    y: row as f32,
+                    width: *width,
+                    height: *height,
+                });
+                x += width;
+            }
+        }
+        Some(Layout { name: name.to_string(), rows: ROWS, columns: COLUMNS, keys })
+    }
+
+    /// Layout for the keyboard of a device. Single zone keyboards are one
+    /// key, per key ones keep the keys that fit into their matrix.
+    pub fn for_device(name: &str, lighting: Lighting, matrix: [usize; 2]) -> Layout {
+        if lighting != Lighting::PerKey {
+            let key = Key { name: String::from("Keyboard"), index: 0, x: 0.0, y: 0.0, width: COLUMNS as f32, height: ROWS as f32 };
+            return Layout { name: String::from("zone"), rows: 1, columns: 1, keys: vec![key] };
+        }
+        let layout = Layout::for_name(name);
+        let [rows, columns] = matrix;
+        if rows == layout.rows && columns == layout.columns {
+            return layout;
+        }
+        let keys = layout.keys.into_iter()
+            .filter(|k| k.index / layout.columns < rows && k.index % layout.columns < columns)
+            .map(|k| Key { index: k.index / layout.columns * columns + k.index % layout.columns, ..k })
+            .collect();
+        Layout { name: layout.name, rows, columns, keys }
+    }
+
+    /// Layout for the name from the device database or configuration,
+    /// ANSI for unknown names
+    pub fn for_name(name: &str) -> Layout {
+        Layout::new(name).unwrap_or_else(|| {
+            if !name.is_empty() {
+                eprintln!("Unknown key layout {:?}, using ansi", name);
+            }
+            Layout::new("ansi").unwrap()
+        })
+    }
+
+    /// Number of positions in the matrix, named or not
+    pub fn key_count(&self) -> usize {
+        self.rows * self.columns
+    }
+
+    /// Matrix index of a key given by its index (0-89 on 6x15) or name, names ignore case
+    pub fn key_index(&self, key: &str) -> Option<usize> {
+        if let Ok(idx) = key.parse::<usize>() {
+            if idx < self.key_count() {
+                return Some(idx);
+            }
+            return None;
+        }
+        self.keys.iter().find(|k| k.name.eq_ignore_ascii_case(key)).map(|k| k.index)
+    }
+
+    /// Matrix index of the key sending a Linux input key code, single zone
+    /// keyboards are one key for all codes. Fn is handled by the embedded
+    /// controller and sends nothing.
+    pub fn evdev_index(&self, code: u16) -> Option<usize> {
+        let mut names = EVDEV_KEYS.iter().filter(|(c, _)| *c == code).map(|(_, name)| *name).peekable();
+        if self.key_count() == 1 {
+            return names.peek().map(|_| 0);
+        }
+        names.find_map(|name| self.keys.iter().find(|k| k.name == name).map(|k| k.index))
+    }
+
+    pub fn key_name(&self, index: usize) -> Option<&str> {
+        self.keys.iter().find(|k| k.index == index).map(|k| k.name.as_str())
+    }
+
+    /// Parses "all", "none" or a list of keys like W,A,S,D or index ranges like 0-14
+    pub fn parse_mask(&self, keys: &str) -> Result<Vec<bool>, String> {
+        let count = self.key_count();
+        match keys.trim() {
+            "all" => return Ok(vec![true; count]),
+            "none" => return Ok(vec![false; count]),
+            _ => {}
+        }
+        let mut mask = vec![false; count];
+        for part in keys.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            if let Some(idx) = self.key_index(part) {
+                mask[idx] = true;
+                continue;
+            }
+            let (first, last) = match part.split_once('-').map(|(a, b)| (a.trim().parse::<usize>(), b.trim().parse::<usize>())) {
+                Some((Ok(a), Ok(b))) if a <= b && b < count => (a, b),
+                _ => return Err(format!("Unknown key {:?}, expected a key name or index 0-{}", part, count - 1)),
+            };
+            mask[first..=last].iter_mut().for_each(|k| *k = true);
+        }
+        Ok(mask)
+    }
+
+    /// The reverse of `parse_mask`, keys by name and positions without one by index
+    pub fn format_mask(&self, mask: &[bool]) -> String {
+        if mask.iter().all(|k| *k) {
+            return String::from("all");
+        }
+        let keys: Vec<String> = mask.iter()
+            .enumerate()
+            .filter(|(_, on)| **on)
+            .map(|(idx, _)| self.key_name(idx).map_or(idx.to_string(), |n| n.to_string()))
+            .collect();
+        if keys.is_empty() {
+            return String::from("none");
+        }
+        keys.join(",")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(row: usize, column: usize) -> Option<usize> {
+        Some(row * COLUMNS + column)
+    }
+
+    #[test]
+    fn evdev_codes_land_on_their_keys() {
+        let ansi = Layout::for_name("ansi");
+        assert_eq!(ansi.evdev_index(1), index(0, 0)); // Esc
+        assert_eq!(ansi.evdev_index(17), index(2, 2)); // W
+        assert_eq!(ansi.evdev_index(30), index(3, 1)); // A
+        assert_eq!(ansi.evdev_index(28), index(3, 12)); // Enter
+        assert_eq!(ansi.evdev_index(43), index(2, 13)); // Backslash
+        assert_eq!(ansi.evdev_index(86), None); // no key left of Z
+
+        let iso = Layout::for_name("iso");
+        assert_eq!(iso.evdev_index(28), index(2, 13)); // tall Enter
+        assert_eq!(iso.evdev_index(43), index(3, 12)); // Hash
+        assert_eq!(iso.evdev_index(86), index(4, 1)); // NonUSBackslash
+        assert_eq!(iso.evdev_index(44), index(4, 2)); // Z
+
+        let jis = Layout::for_name("jis");
+        assert_eq!(jis.evdev_index(124), index(1, 13)); // Yen
+        assert_eq!(jis.evdev_index(43), index(3, 12)); // Backslash
+        assert_eq!(jis.evdev_index(89), index(4, 11)); // Ro
+        assert_eq!(jis.evdev_index(94), index(5, 4)); // Muhenkan
+        assert_eq!(jis.evdev_index(57), index(5, 5)); // Space
+    }
+
+    #[test]
+    fn evdev_codes_follow_the_matrix() {
+        let zone = Layout::for_device("ansi", Lighting::Zone, [1, 1]);
+        assert_eq!(zone.evdev_index(17), Some(0));
+        assert_eq!(zone.evdev_index(0), None);
+
+        let wide = Layout::for_device("ansi", Lighting::PerKey, [6, 25]);
+        assert_eq!(wide.evdev_index(17), Some(2 * 25 + 2)); // W
+        let short = Layout::for_device("ansi", Lighting::PerKey, [4, COLUMNS]);
+        assert_eq!(short.evdev_index(44), None); // Z is in row 4
+    }
+}
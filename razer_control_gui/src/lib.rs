@@ -2,6 +2,9 @@
 
 use serde::{Serialize, Deserialize};
 
+pub mod keyboard;
+pub mod sensors;
+
 pub const DEVICE_FILE: &str = "/usr/share/razercontrol/laptops.json";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,6 +14,12 @@ pub struct SupportedDevice {
     pub pid: String,
     pub features: Vec<String>,
     pub fan: Vec<u16>,
+    #[serde(default)]
+    pub layout: String, // key layout, see keyboard::LAYOUTS (default ansi)
+    #[serde(default)]
+    pub lighting: keyboard::Lighting,
+    #[serde(default = "keyboard::default_matrix")]
+    pub matrix: [usize; 2], // rows and columns of a per key keyboard
 }
 
 impl SupportedDevice {
@@ -28,3 +37,46 @@ impl SupportedDevice {
     }
 
 }
+
+/// Unix time of the next `hour`:`minute` in local time, today if it is still
+/// ahead, tomorrow otherwise
+pub fn next_time_of_day(hour: u32, minute: u32) -> Option<u64> {
+    if hour > 23 || minute > 59 {
+        return None;
+    }
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            return None;
+        }
+        tm.tm_hour = hour as i32;
+        tm.tm_min = minute as i32;
+        tm.tm_sec = 0;
+        tm.tm_isdst = -1;
+        let mut at = libc::mktime(&mut tm);
+        if at <= now {
+            tm.tm_mday += 1; // mktime normalizes the day and DST
+            tm.tm_hour = hour as i32;
+            tm.tm_min = minute as i32;
+            tm.tm_isdst = -1;
+            at = libc::mktime(&mut tm);
+        }
+        if at < 0 {
+            return None;
+        }
+        Some(at as u64)
+    }
+}
+
+/// Local `HH:MM` of a unix time
+pub fn format_time_of_day(at: u64) -> String {
+    unsafe {
+        let t = at as libc::time_t;
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&t, &mut tm).is_null() {
+            return String::from("?");
+        }
+        format!("{:02}:{:02}", tm.tm_hour, tm.tm_min)
+    }
+}
@@ -5,7 +5,7 @@ use adw::prelude::*;
 use std::io::ErrorKind;
 use std::fs;
 use std::rc::Rc;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -17,9 +17,9 @@ mod util;
 mod tray;
 
 use service::SupportedDevice;
+use service::keyboard::Lighting;
 use error_handling::*;
 use widgets::*;
-use util::*;
 
 fn send_data(opt: comms::DaemonCommand) -> Option<comms::DaemonResponse> {
     match comms::try_bind() {
@@ -108,6 +108,158 @@ fn set_bho(is_on: bool, threshold: u8) -> Option<bool> {
     }
 }
 
+// Daemon names of the charge overrides, in the order of the Full Charge combo
+const CHARGE_OVERRIDES: [&str; 4] = ["off", "full-once", "until-unplug", "full-by"];
+
+fn get_charge_override() -> Option<(String, u64)> {
+    let response = send_data(comms::DaemonCommand::GetChargeThreshold)?;
+    use comms::DaemonResponse::*;
+    match response {
+        GetChargeThreshold { override_mode, override_at, .. } => Some((override_mode, override_at)),
+        response => {
+            println!("Instead of GetChargeThreshold got {response:?}");
+            None
+        }
+    }
+}
+
+fn set_charge_override(mode: &str, at: u64) -> Option<bool> {
+    let response = send_data(comms::DaemonCommand::SetChargeOverride { mode: mode.to_string(), at })?;
+    use comms::DaemonResponse::*;
+    match response {
+        SetChargeOverride { result } => Some(result),
+        response => {
+            println!("Instead of SetChargeOverride got {response:?}");
+            None
+        }
+    }
+}
+
+fn get_battery_history() -> Option<comms::BatteryHistoryInfo> {
+    let response = send_data(comms::DaemonCommand::GetBatteryHistory)?;
+    use comms::DaemonResponse::*;
+    match response {
+        GetBatteryHistory { history } => Some(history),
+        response => {
+            println!("Instead of GetBatteryHistory got {response:?}");
+            None
+        }
+    }
+}
+
+fn get_telemetry(since: u64, resolution: u64) -> Option<Vec<comms::TelemetrySample>> {
+    let response = send_data(comms::DaemonCommand::GetTelemetry { since, resolution })?;
+    use comms::DaemonResponse::*;
+    match response {
+        GetTelemetry { samples } => Some(samples),
+        response => {
+            println!("Instead of GetTelemetry got {response:?}");
+            None
+        }
+    }
+}
+
+/// Title and value of each row of the Battery Health section
+fn battery_history_rows(h: &comms::BatteryHistoryInfo) -> [(&'static str, String); 5] {
+    let opt = |v: Option<f64>, unit: &str| match v {
+        Some(v) => format!("{:.1} {}", v, unit),
+        None => String::from("unknown"),
+    };
+    let capacity = match (h.full_wh, h.design_wh, h.wear_percent) {
+        (Some(full), Some(design), Some(wear)) => format!("{:.1} of {:.1} Wh \u{00B7} {:.1}% wear", full, design, wear),
+        _ => String::from("unknown"),
+    };
+    let cycles = h.cycle_count.map_or(String::from("unknown"), |c| c.to_string());
+    let rates = format!("{} charging \u{00B7} {} on battery", opt(h.avg_charge_w, "W"), opt(h.avg_discharge_w, "W"));
+    let time = format!(
+        "{:.0} h recorded \u{00B7} {:.0} h at 95% or more \u{00B7} {:.0} h limited",
+        h.tracked_hours, h.high_soc_hours, h.limited_hours
+    );
+    let wear = format!(
+        "{}/month with the limit \u{00B7} {}/month without",
+        opt(h.wear_per_month_limited, "%"), opt(h.wear_per_month_unlimited, "%")
+    );
+    [
+        ("Capacity", capacity),
+        ("Charge Cycles", cycles),
+        ("Average Power", rates),
+        ("Time", time),
+        ("Capacity Loss", wear),
+    ]
+}
+
+fn get_battery_rules() -> Option<(u8, u8, u8)> {
+    let response = send_data(comms::DaemonCommand::GetBatteryRules)?;
+    use comms::DaemonResponse::*;
+    match response {
+        GetBatteryRules { no_light, low_power, power_mode } => Some((no_light, low_power, power_mode)),
+        response => {
+            println!("Instead of GetBatteryRules got {response:?}");
+            None
+        }
+    }
+}
+
+fn set_battery_rules(no_light: u8, low_power: u8, power_mode: u8) -> Option<bool> {
+    let response = send_data(comms::DaemonCommand::SetBatteryRules { no_light, low_power, power_mode })?;
+    use comms::DaemonResponse::*;
+    match response {
+        SetBatteryRules { result } => Some(result),
+        response => {
+            println!("Instead of SetBatteryRules got {response:?}");
+            None
+        }
+    }
+}
+
+fn export_settings() -> Option<String> {
+    let response = send_data(comms::DaemonCommand::ExportConfig)?;
+    use comms::DaemonResponse::*;
+    match response {
+        ExportConfig { bundle } => Some(bundle),
+        response => {
+            println!("Instead of ExportConfig got {response:?}");
+            None
+        }
+    }
+}
+
+fn import_settings(bundle: String) -> Option<(bool, Vec<String>)> {
+    let response = send_data(comms::DaemonCommand::ImportConfig { bundle })?;
+    use comms::DaemonResponse::*;
+    match response {
+        ImportConfig { result, messages } => Some((result, messages)),
+        response => {
+            println!("Instead of ImportConfig got {response:?}");
+            None
+        }
+    }
+}
+
+fn get_power_profiles() -> Option<(bool, bool)> {
+    let response = send_data(comms::DaemonCommand::GetPowerProfiles)?;
+    use comms::DaemonResponse::*;
+    match response {
+        GetPowerProfiles { follow, sync_back, .. } => Some((follow, sync_back)),
+        response => {
+            println!("Instead of GetPowerProfiles got {response:?}");
+            None
+        }
+    }
+}
+
+fn set_power_profiles(follow: bool, sync_back: bool) -> Option<bool> {
+    let response = send_data(comms::DaemonCommand::SetPowerProfiles { follow, sync_back })?;
+    use comms::DaemonResponse::*;
+    match response {
+        SetPowerProfiles { result } => Some(result),
+        response => {
+            println!("Instead of SetPowerProfiles got {response:?}");
+            None
+        }
+    }
+}
+
 fn get_brightness(ac: bool) -> Option<u8> {
     let ac = if ac { 1 } else { 0 };
     let response = send_data(comms::DaemonCommand::GetBrightness{ ac })?;
@@ -184,6 +336,55 @@ fn set_effect(name: &str, values: Vec<u8>) -> Option<bool> {
     }
 }
 
+fn get_layers() -> Option<Vec<comms::LayerInfo>> {
+    let response = send_data(comms::DaemonCommand::ListLayers)?;
+    use comms::DaemonResponse::*;
+    match response {
+        ListLayers { layers } => Some(layers),
+        response => {
+            println!("Instead of ListLayers got {response:?}");
+            None
+        }
+    }
+}
+
+fn set_layer_mask(index: usize, key_mask: Vec<bool>) -> Option<(bool, String)> {
+    let response = send_data(comms::DaemonCommand::SetLayerMask { index, key_mask })?;
+    use comms::DaemonResponse::*;
+    match response {
+        SetLayerMask { result, message } => Some((result, message)),
+        response => {
+            println!("Instead of SetLayerMask got {response:?}");
+            None
+        }
+    }
+}
+
+fn get_key_layout() -> Option<service::keyboard::Layout> {
+    let response = send_data(comms::DaemonCommand::GetKeyLayout)?;
+    use comms::DaemonResponse::*;
+    match response {
+        GetKeyLayout { layout } => Some(layout),
+        response => {
+            println!("Instead of GetKeyLayout got {response:?}");
+            None
+        }
+    }
+}
+
+/// Colours the keyboard shows, RGB of every key
+fn get_keyboard_rgb() -> Option<Vec<u8>> {
+    let response = send_data(comms::DaemonCommand::GetKeyboardRGB { layer: -1 })?;
+    use comms::DaemonResponse::*;
+    match response {
+        GetKeyboardRGB { rgbdata, .. } => Some(rgbdata),
+        response => {
+            println!("Instead of GetKeyboardRGB got {response:?}");
+            None
+        }
+    }
+}
+
 fn get_power(ac: bool) -> Option<(u8, u8, u8)> {
     let ac = if ac { 1 } else { 0 };
     let mut result = (0, 0, 0);
@@ -257,341 +458,6 @@ fn set_fan_speed(ac: bool, value: i32) -> Option<bool> {
     }
 }
 
-/// Read CPU temperature from hwmon (supports AMD k10temp/zenpower and Intel coretemp)
-fn get_cpu_temperature() -> Option<f64> {
-    if let Ok(entries) = fs::read_dir("/sys/class/hwmon") {
-        for entry in entries.flatten() {
-            let name_path = entry.path().join("name");
-            if let Ok(name) = fs::read_to_string(&name_path) {
-                let name = name.trim();
-                if name == "k10temp" || name == "zenpower" || name == "coretemp" {
-                    let temp_path = entry.path().join("temp1_input");
-                    if let Ok(content) = fs::read_to_string(&temp_path) {
-                        if let Ok(temp) = content.trim().parse::<f64>() {
-                            return Some(temp / 1000.0);
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    let paths = [
-        "/sys/class/thermal/thermal_zone0/temp",
-        "/sys/class/thermal/thermal_zone1/temp",
-        "/sys/class/thermal/thermal_zone2/temp",
-    ];
-
-    for path in paths {
-        if let Ok(content) = fs::read_to_string(path) {
-            if let Ok(temp) = content.trim().parse::<f64>() {
-                return Some(temp / 1000.0);
-            }
-        }
-    }
-    None
-}
-
-/// Read dGPU temperature (NVIDIA)
-fn get_gpu_temperature() -> Option<f64> {
-    if let Ok(output) = std::process::Command::new("nvidia-smi")
-        .args(["--query-gpu=temperature.gpu", "--format=csv,noheader,nounits"])
-        .output()
-    {
-        if output.status.success() {
-            if let Ok(temp_str) = String::from_utf8(output.stdout) {
-                if let Ok(temp) = temp_str.trim().parse::<f64>() {
-                    return Some(temp);
-                }
-            }
-        }
-    }
-
-    if let Ok(entries) = fs::read_dir("/sys/class/hwmon") {
-        for entry in entries.flatten() {
-            let name_path = entry.path().join("name");
-            if let Ok(name) = fs::read_to_string(&name_path) {
-                if name.trim() == "nvidia" {
-                    let temp_path = entry.path().join("temp1_input");
-                    if let Ok(content) = fs::read_to_string(&temp_path) {
-                        if let Ok(temp) = content.trim().parse::<f64>() {
-                            return Some(temp / 1000.0);
-                        }
-                    }
-                }
-            }
-        }
-    }
-    None
-}
-
-
-
-/// Read system/CPU power consumption from RAPL (supports AMD and Intel)
-fn get_system_power() -> Option<f64> {
-    let energy_paths = [
-        "/sys/class/powercap/amd-rapl:0/energy_uj",
-        "/sys/class/powercap/amd_rapl/amd-rapl:0/energy_uj",
-        "/sys/class/powercap/intel-rapl:0/energy_uj",
-        "/sys/class/powercap/intel-rapl/intel-rapl:0/energy_uj",
-    ];
-
-    for path in &energy_paths {
-        if let Ok(content) = fs::read_to_string(path) {
-            if let Ok(energy) = content.trim().parse::<u64>() {
-                static LAST_ENERGY: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
-                static LAST_TIME: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
-
-                let now = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_micros() as u64;
-
-                let prev_energy = LAST_ENERGY.swap(energy, std::sync::atomic::Ordering::Relaxed);
-                let prev_time = LAST_TIME.swap(now, std::sync::atomic::Ordering::Relaxed);
-
-                if prev_energy > 0 && prev_time > 0 && energy > prev_energy {
-                    let delta_energy = energy - prev_energy;
-                    let delta_time = now - prev_time;
-                    if delta_time > 0 {
-                        let power = delta_energy as f64 / delta_time as f64;
-                        return Some(power);
-                    }
-                }
-                return None; // Found path but need second reading
-            }
-        }
-    }
-    None
-}
-
-/// Read NVIDIA dGPU power consumption
-fn get_dgpu_power() -> Option<f64> {
-    if let Ok(output) = std::process::Command::new("nvidia-smi")
-        .args(["--query-gpu=power.draw", "--format=csv,noheader,nounits"])
-        .output()
-    {
-        if output.status.success() {
-            if let Ok(power_str) = String::from_utf8(output.stdout) {
-                if let Ok(power) = power_str.trim().parse::<f64>() {
-                    return Some(power);
-                }
-            }
-        }
-    }
-    None
-}
-
-/// Read NVIDIA dGPU utilization
-fn get_dgpu_utilization() -> Option<u32> {
-    if let Ok(output) = std::process::Command::new("nvidia-smi")
-        .args(["--query-gpu=utilization.gpu", "--format=csv,noheader,nounits"])
-        .output()
-    {
-        if output.status.success() {
-            if let Ok(util_str) = String::from_utf8(output.stdout) {
-                if let Ok(util) = util_str.trim().parse::<u32>() {
-                    return Some(util);
-                }
-            }
-        }
-    }
-    None
-}
-
-/// Read iGPU power from hwmon (AMD amdgpu) or Intel RAPL GT fallback
-fn get_igpu_power() -> Option<f64> {
-    if let Ok(entries) = fs::read_dir("/sys/class/hwmon") {
-        for entry in entries.flatten() {
-            let name_path = entry.path().join("name");
-            if let Ok(name) = fs::read_to_string(&name_path) {
-                if name.trim() == "amdgpu" {
-                    let power_path = entry.path().join("power1_average");
-                    if let Ok(content) = fs::read_to_string(&power_path) {
-                        if let Ok(power_uw) = content.trim().parse::<f64>() {
-                            return Some(power_uw / 1_000_000.0);
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    // Fallback: Intel RAPL GT domain
-    let paths = [
-        "/sys/class/powercap/intel-rapl:0:1/energy_uj",
-        "/sys/class/powercap/intel-rapl/intel-rapl:0/intel-rapl:0:1/energy_uj",
-    ];
-
-    for path in &paths {
-        if let Ok(content) = fs::read_to_string(path) {
-            if let Ok(energy) = content.trim().parse::<u64>() {
-                static LAST_IGPU_ENERGY: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
-                static LAST_IGPU_TIME: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
-
-                let now = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_micros() as u64;
-
-                let prev_energy = LAST_IGPU_ENERGY.swap(energy, std::sync::atomic::Ordering::Relaxed);
-                let prev_time = LAST_IGPU_TIME.swap(now, std::sync::atomic::Ordering::Relaxed);
-
-                if prev_energy > 0 && prev_time > 0 && energy > prev_energy {
-                    let delta_energy = energy - prev_energy;
-                    let delta_time = now - prev_time;
-                    if delta_time > 0 {
-                        return Some(delta_energy as f64 / delta_time as f64);
-                    }
-                }
-            }
-        }
-    }
-    None
-}
-
-/// Read iGPU utilization (AMD gpu_busy_percent or Intel freq-based fallback)
-fn get_igpu_utilization() -> Option<u32> {
-    for card in ["card0", "card1", "card2"] {
-        let busy_path = format!("/sys/class/drm/{}/device/gpu_busy_percent", card);
-        if let Ok(content) = fs::read_to_string(&busy_path) {
-            if let Ok(util) = content.trim().parse::<u32>() {
-                let driver_path = format!("/sys/class/drm/{}/device/driver", card);
-                if let Ok(driver_link) = fs::read_link(&driver_path) {
-                    if driver_link.to_string_lossy().contains("amdgpu") {
-                        return Some(util);
-                    }
-                }
-            }
-        }
-    }
-
-    // Fallback: frequency-based estimation for Intel
-    let paths = [
-        "/sys/class/drm/card0/gt/gt0/rps_act_freq_mhz",
-        "/sys/class/drm/card1/gt/gt0/rps_act_freq_mhz",
-    ];
-    let max_paths = [
-        "/sys/class/drm/card0/gt/gt0/rps_max_freq_mhz",
-        "/sys/class/drm/card1/gt/gt0/rps_max_freq_mhz",
-    ];
-
-    for (i, path) in paths.iter().enumerate() {
-        if let Ok(act_content) = fs::read_to_string(path) {
-            if let Ok(max_content) = fs::read_to_string(&max_paths[i]) {
-                if let (Ok(act), Ok(max)) = (
-                    act_content.trim().parse::<f64>(),
-                    max_content.trim().parse::<f64>()
-                ) {
-                    if max > 0.0 {
-                        return Some(((act / max) * 100.0) as u32);
-                    }
-                }
-            }
-        }
-    }
-    None
-}
-
-/// Read iGPU temperature from amdgpu hwmon
-fn get_igpu_temperature() -> Option<f64> {
-    if let Ok(entries) = fs::read_dir("/sys/class/hwmon") {
-        for entry in entries.flatten() {
-            let name_path = entry.path().join("name");
-            if let Ok(name) = fs::read_to_string(&name_path) {
-                if name.trim() == "amdgpu" {
-                    for temp_file in ["temp1_input", "temp2_input"] {
-                        let temp_path = entry.path().join(temp_file);
-                        if let Ok(content) = fs::read_to_string(&temp_path) {
-                            if let Ok(temp) = content.trim().parse::<f64>() {
-                                return Some(temp / 1000.0);
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-    None
-}
-
-/// Read battery percentage from /sys/class/power_supply/BAT{0,1}/capacity
-fn get_battery_percentage() -> Option<u8> {
-    for bat in ["BAT0", "BAT1"] {
-        let path = format!("/sys/class/power_supply/{}/capacity", bat);
-        if let Ok(content) = fs::read_to_string(&path) {
-            if let Ok(pct) = content.trim().parse::<u8>() {
-                return Some(pct);
-            }
-        }
-    }
-    None
-}
-
-/// Read battery status (Charging, Discharging, Full, Not charging)
-fn get_battery_status() -> Option<String> {
-    for bat in ["BAT0", "BAT1"] {
-        let path = format!("/sys/class/power_supply/{}/status", bat);
-        if let Ok(content) = fs::read_to_string(&path) {
-            let status = content.trim().to_string();
-            if !status.is_empty() {
-                return Some(status);
-            }
-        }
-    }
-    None
-}
-
-/// Read battery power draw in watts (current_now * voltage_now)
-fn get_battery_power() -> Option<f64> {
-    for bat in ["BAT0", "BAT1"] {
-        let current_path = format!("/sys/class/power_supply/{}/current_now", bat);
-        let voltage_path = format!("/sys/class/power_supply/{}/voltage_now", bat);
-        if let (Ok(c_str), Ok(v_str)) = (fs::read_to_string(&current_path), fs::read_to_string(&voltage_path)) {
-            if let (Ok(current_ua), Ok(voltage_uv)) = (c_str.trim().parse::<u64>(), v_str.trim().parse::<u64>()) {
-                if current_ua > 0 {
-                    return Some(current_ua as f64 * voltage_uv as f64 / 1e12);
-                }
-            }
-        }
-    }
-    None
-}
-
-/// Read CPU utilization from /proc/stat (delta-based)
-fn get_cpu_utilization() -> Option<u32> {
-    static LAST_IDLE: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
-    static LAST_TOTAL: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
-
-    if let Ok(content) = fs::read_to_string("/proc/stat") {
-        if let Some(line) = content.lines().next() {
-            let fields: Vec<&str> = line.split_whitespace().collect();
-            if fields.len() >= 5 && fields[0] == "cpu" {
-                let mut total: u64 = 0;
-                for f in &fields[1..] {
-                    if let Ok(v) = f.parse::<u64>() {
-                        total += v;
-                    }
-                }
-                let idle = fields[4].parse::<u64>().unwrap_or(0);
-
-                let prev_idle = LAST_IDLE.swap(idle, std::sync::atomic::Ordering::Relaxed);
-                let prev_total = LAST_TOTAL.swap(total, std::sync::atomic::Ordering::Relaxed);
-
-                if prev_total > 0 {
-                    let d_idle = idle.wrapping_sub(prev_idle);
-                    let d_total = total.wrapping_sub(prev_total);
-                    if d_total > 0 {
-                        let usage = 100.0 * (1.0 - d_idle as f64 / d_total as f64);
-                        return Some(usage.round() as u32);
-                    }
-                }
-            }
-        }
-    }
-    None
-}
-
 /// Create system monitor panel at the bottom (widget-style layout)
 fn create_system_monitor(shared_state: tray::SharedSensorState) -> gtk::Box {
     let main_box = gtk::Box::new(gtk::Orientation::Vertical, 0);
@@ -707,21 +573,13 @@ fn create_system_monitor(shared_state: tray::SharedSensorState) -> gtk::Box {
     main_box.append(&bottom_row);
 
     glib::timeout_add_local(Duration::from_secs(2), move || {
-        let cpu_temp = get_cpu_temperature();
-        let igpu_temp = get_igpu_temperature();
-        let dgpu_temp = get_gpu_temperature();
-        let on_ac = check_if_running_on_ac_power();
-        let ac = on_ac.unwrap_or(true);
-        let fan = get_fan_speed(ac);
-        let battery_pct = get_battery_percentage();
-        let battery_status = get_battery_status();
-        let battery_power = get_battery_power();
-        let sys_power = get_system_power();
-        let cpu_util = get_cpu_utilization();
-        let igpu_pwr = get_igpu_power();
-        let igpu_util = get_igpu_utilization();
-        let dgpu_pwr = get_dgpu_power();
-        let dgpu_util = get_dgpu_utilization();
+        let mut snapshot = tray::read_sensors().map(|r| tray::SensorState::from_readings(&r)).unwrap_or_default();
+        snapshot.fan_speed = get_fan_speed(snapshot.on_ac.unwrap_or(true));
+        let tray::SensorState {
+            cpu_temp, igpu_temp, dgpu_temp, fan_speed: fan, on_ac: _,
+            battery_pct, ref battery_status, battery_power, system_power: sys_power,
+            cpu_util, igpu_power: igpu_pwr, igpu_util, dgpu_power: dgpu_pwr, dgpu_util,
+        } = snapshot;
 
         // CPU
         match cpu_temp {
@@ -810,20 +668,7 @@ fn create_system_monitor(shared_state: tray::SharedSensorState) -> gtk::Box {
 
         // Write snapshot to shared state for tray tooltip
         if let Ok(mut state) = shared_state.lock() {
-            state.cpu_temp = cpu_temp;
-            state.igpu_temp = igpu_temp;
-            state.dgpu_temp = dgpu_temp;
-            state.fan_speed = fan;
-            state.on_ac = on_ac;
-            state.battery_pct = battery_pct;
-            state.battery_status = battery_status.clone();
-            state.battery_power = battery_power;
-            state.system_power = sys_power;
-            state.cpu_util = cpu_util;
-            state.igpu_power = igpu_pwr;
-            state.igpu_util = igpu_util;
-            state.dgpu_power = dgpu_pwr;
-            state.dgpu_util = dgpu_util;
+            *state = snapshot.clone();
         }
 
         glib::ControlFlow::Continue
@@ -1072,6 +917,55 @@ fn make_performance_page(device: SupportedDevice) -> SettingsPage {
     cpu_combo.set_visible(show_boost);
     gpu_combo.set_visible(show_boost);
 
+    // --- Desktop power profile section ---
+    let ppd_section = settings_page.add_section(Some("Desktop Power Profile"));
+    let ppd = get_power_profiles().unwrap_or((false, false));
+
+    let follow_switch = make_switch_row(
+        "Follow Desktop Profile",
+        "Switch profile with the GNOME/KDE power menu",
+        ppd.0,
+    );
+    ppd_section.add_row(&follow_switch);
+
+    let sync_switch = make_switch_row(
+        "Update Desktop Profile",
+        "Change the desktop power menu when the profile is set here",
+        ppd.1,
+    );
+    ppd_section.add_row(&sync_switch);
+
+    {
+        let ppd_refreshing = Rc::new(Cell::new(false));
+        let apply_ppd = {
+            let ppd_refreshing = ppd_refreshing.clone();
+            let follow_switch = follow_switch.clone();
+            let sync_switch = sync_switch.clone();
+            move || {
+                if ppd_refreshing.get() { return; }
+                set_power_profiles(follow_switch.is_active(), sync_switch.is_active());
+            }
+        };
+        {
+            let apply_ppd = apply_ppd.clone();
+            follow_switch.connect_active_notify(move |_| apply_ppd());
+        }
+        sync_switch.connect_active_notify(move |_| apply_ppd());
+
+        // Live-sync: poll daemon every 2s so CLI changes appear in GUI
+        let follow_switch = follow_switch.clone();
+        let sync_switch = sync_switch.clone();
+        glib::timeout_add_local(Duration::from_secs(2), move || {
+            if let Some((follow, sync_back)) = get_power_profiles() {
+                ppd_refreshing.set(true);
+                follow_switch.set_active(follow);
+                sync_switch.set_active(sync_back);
+                ppd_refreshing.set(false);
+            }
+            glib::ControlFlow::Continue
+        });
+    }
+
     // --- Cooling section ---
     let fan_section = settings_page.add_section(Some("Cooling"));
 
@@ -1255,6 +1149,8 @@ fn make_performance_page(device: SupportedDevice) -> SettingsPage {
         ));
     }
 
+    add_telemetry_section(&settings_page, max_fan_speed);
+
     // -----------------------------------------------------------------------
     // GPU sections (merged from former GPU page)
     // -----------------------------------------------------------------------
@@ -1448,6 +1344,131 @@ fn make_performance_page(device: SupportedDevice) -> SettingsPage {
     settings_page
 }
 
+/// Time ranges of the thermal history: label, seconds, seconds per point
+const TELEMETRY_RANGES: [(&str, u64, u64); 3] = [
+    ("Last 10 Minutes", 10 * 60, 0),
+    ("Last Hour", 60 * 60, 15),
+    ("Last 24 Hours", 24 * 60 * 60, 300),
+];
+
+/// Line colours of CPU and dGPU temperature, fan speed and CPU power
+const PLOT_COLOURS: [(f64, f64, f64); 4] = [(0.90, 0.38, 0.0), (0.46, 0.72, 0.0), (0.21, 0.52, 0.89), (0.57, 0.25, 0.68)];
+
+/// Band colours of the power modes, Balanced to Custom
+const MODE_COLOURS: [(f64, f64, f64); 5] = [(0.6, 0.6, 0.6), (0.88, 0.11, 0.14), (0.21, 0.52, 0.89), (0.2, 0.82, 0.48), (0.57, 0.25, 0.68)];
+
+fn add_telemetry_section(settings_page: &SettingsPage, max_fan: f64) {
+    let section = settings_page.add_section(Some("Thermal History"));
+    let labels: Vec<&str> = TELEMETRY_RANGES.iter().map(|r| r.0).collect();
+    let range_combo = make_combo_row("Range", "Recorded by the daemon, also while this window is closed", &labels, 0);
+    section.add_row(&range_combo);
+
+    let samples: Rc<RefCell<Vec<comms::TelemetrySample>>> = Rc::new(RefCell::new(vec![]));
+    let area = gtk::DrawingArea::new();
+    area.set_content_height(160);
+    area.set_margin_top(12);
+    area.set_margin_bottom(6);
+    {
+        let samples = samples.clone();
+        area.set_draw_func(move |_, cr, width, height| {
+            draw_telemetry(cr, width as f64, height as f64, &samples.borrow(), max_fan);
+        });
+    }
+    section.add_row(&area);
+
+    let dot = |(r, g, b): (f64, f64, f64)| {
+        format!("<span foreground=\"#{:02x}{:02x}{:02x}\">\u{25CF}</span>", (r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8)
+    };
+    let legend = gtk::Label::new(None);
+    legend.set_markup(&format!(
+        "{} CPU \u{00B0}C  {} dGPU \u{00B0}C  {} Fan  {} CPU W    Band: {} Balanced {} Gaming {} Creator {} Silent {} Custom",
+        dot(PLOT_COLOURS[0]), dot(PLOT_COLOURS[1]), dot(PLOT_COLOURS[2]), dot(PLOT_COLOURS[3]),
+        dot(MODE_COLOURS[0]), dot(MODE_COLOURS[1]), dot(MODE_COLOURS[2]), dot(MODE_COLOURS[3]), dot(MODE_COLOURS[4]),
+    ));
+    legend.add_css_class("caption");
+    legend.set_wrap(true);
+    legend.set_margin_bottom(6);
+    section.add_row(&legend);
+
+    let reload = Rc::new({
+        let range_combo = range_combo.clone();
+        move || {
+            let (_, secs, resolution) = TELEMETRY_RANGES[(range_combo.selected() as usize).min(TELEMETRY_RANGES.len() - 1)];
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            *samples.borrow_mut() = get_telemetry(now.saturating_sub(secs), resolution).unwrap_or_default();
+            area.queue_draw();
+        }
+    });
+    reload();
+    {
+        let reload = reload.clone();
+        range_combo.connect_selected_notify(move |_| reload());
+    }
+    glib::timeout_add_local(Duration::from_secs(10), move || {
+        reload();
+        glib::ControlFlow::Continue
+    });
+}
+
+/// Temperatures from 0 to 100 °C, fan speed up to the maximum RPM and CPU
+/// power up to its peak in the range, with the power mode as a band below
+fn draw_telemetry(cr: &gtk::cairo::Context, width: f64, height: f64, samples: &[comms::TelemetrySample], max_fan: f64) {
+    let band = 4.0;
+    let plot_height = height - band - 2.0;
+
+    cr.set_source_rgba(0.5, 0.5, 0.5, 0.2);
+    cr.set_line_width(1.0);
+    for i in 1..4 {
+        let y = plot_height * i as f64 / 4.0;
+        cr.move_to(0.0, y);
+        cr.line_to(width, y);
+    }
+    let _ = cr.stroke();
+
+    let (first, last) = match (samples.first(), samples.last()) {
+        (Some(a), Some(b)) if b.time > a.time => (a.time, b.time),
+        _ => return,
+    };
+    let x = |time: u64| (time - first) as f64 / (last - first) as f64 * width;
+    let max_w = samples.iter().filter_map(|s| s.package_w).fold(1.0, f32::max) as f64;
+    let series: [(fn(&comms::TelemetrySample) -> Option<f64>, f64); 4] = [
+        (|s| s.cpu_temp.map(f64::from), 100.0),
+        (|s| s.dgpu_temp.map(f64::from), 100.0),
+        (|s| Some(s.fan_rpm as f64), max_fan.max(1.0)),
+        (|s| s.package_w.map(f64::from), max_w),
+    ];
+
+    cr.set_line_width(1.5);
+    for ((value, scale), (r, g, b)) in series.iter().zip(PLOT_COLOURS) {
+        cr.set_source_rgb(r, g, b);
+        let mut drawing = false;
+        for s in samples {
+            match value(s) {
+                Some(v) => {
+                    let y = plot_height - (v / scale).clamp(0.0, 1.0) * plot_height;
+                    if drawing {
+                        cr.line_to(x(s.time), y);
+                    } else {
+                        cr.move_to(x(s.time), y);
+                    }
+                    drawing = true;
+                }
+                None => drawing = false, // sensor missing, leave a gap
+            }
+        }
+        let _ = cr.stroke();
+    }
+
+    for pair in samples.windows(2) {
+        let (r, g, b) = MODE_COLOURS[(pair[0].power_mode as usize).min(MODE_COLOURS.len() - 1)];
+        cr.set_source_rgb(r, g, b);
+        cr.rectangle(x(pair[0].time), height - band, x(pair[1].time) - x(pair[0].time), band);
+        let _ = cr.fill();
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Lighting page
 // ---------------------------------------------------------------------------
@@ -1478,6 +1499,7 @@ fn make_lighting_page(device: SupportedDevice) -> SettingsPage {
     brightness_slider.add_mark(50.0, Some("50%"));
     brightness_slider.add_mark(100.0, Some("100%"));
     brightness_section.add_row(&brightness_slider.container);
+    brightness_section.group.set_visible(device.lighting != Lighting::None);
 
     // --- Logo (conditional) ---
     let logo_combo: Option<adw::ComboRow> = if device.has_logo() {
@@ -1497,6 +1519,7 @@ fn make_lighting_page(device: SupportedDevice) -> SettingsPage {
 
     // --- Keyboard Effects (GLOBAL — not affected by AC/Battery toggle) ---
     let effects_section = settings_page.add_section(Some("Keyboard Effects"));
+    effects_section.group.set_visible(device.lighting.has_effects());
 
     let effect_combo = make_combo_row(
         "Effect Type",
@@ -1682,9 +1705,181 @@ fn make_lighting_page(device: SupportedDevice) -> SettingsPage {
         });
     }
 
+    // Single zone keyboards light all keys alike
+    if device.lighting == Lighting::PerKey {
+        if let Some(layout) = get_key_layout() {
+            add_layer_keys_section(&settings_page, layout);
+        }
+    }
+
     settings_page
 }
 
+/// Layers by position and effect, for the layer combo
+fn layer_labels(layers: &[comms::LayerInfo]) -> Vec<String> {
+    layers.iter().enumerate().map(|(idx, l)| format!("{}: {} ({})", idx, l.effect, l.blend)).collect()
+}
+
+/// A keyboard in the live colours, clicking keys picks the ones a layer lights
+fn add_layer_keys_section(settings_page: &SettingsPage, layout: service::keyboard::Layout) {
+    let section = settings_page.add_section(Some("Layer Keys"));
+    let layers = Rc::new(RefCell::new(get_layers().unwrap_or_default()));
+    let labels = layer_labels(&layers.borrow());
+    let label_refs: Vec<&str> = labels.iter().map(|l| l.as_str()).collect();
+    let layer_combo = make_combo_row("Layer", "Click keys to choose the ones this layer lights", &label_refs, 0);
+    section.add_row(&layer_combo);
+
+    let layout = Rc::new(layout);
+    let mask: Rc<RefCell<Vec<bool>>> = Rc::new(RefCell::new(
+        layers.borrow().first().map_or(vec![false; layout.key_count()], |l| l.key_mask.clone())
+    ));
+    let colours: Rc<RefCell<Vec<u8>>> = Rc::new(RefCell::new(get_keyboard_rgb().unwrap_or_default()));
+
+    let area = gtk::DrawingArea::new();
+    area.set_content_height(220);
+    area.set_margin_top(12);
+    area.set_margin_bottom(6);
+    {
+        let (layout, mask, colours) = (layout.clone(), mask.clone(), colours.clone());
+        area.set_draw_func(move |_, cr, width, height| {
+            draw_keyboard(cr, width as f64, height as f64, &layout, &mask.borrow(), &colours.borrow());
+        });
+    }
+    {
+        let (layout, mask) = (layout.clone(), mask.clone());
+        let click = gtk::GestureClick::new();
+        click.connect_pressed(move |gesture, _, x, y| {
+            let area = match gesture.widget() {
+                Some(w) => w,
+                None => return,
+            };
+            let (unit, left, top) = keyboard_scale(&layout, area.width() as f64, area.height() as f64);
+            let hit = layout.keys.iter().find(|k| {
+                let kx = left + k.x as f64 * unit;
+                let ky = top + k.y as f64 * unit;
+                x >= kx && x < kx + k.width as f64 * unit && y >= ky && y < ky + k.height as f64 * unit
+            });
+            if let Some(key) = hit {
+                let mut mask = mask.borrow_mut();
+                mask[key.index] = !mask[key.index];
+                area.queue_draw();
+            }
+        });
+        area.add_controller(click);
+    }
+    section.add_row(&area);
+
+    let button_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    button_box.set_margin_top(6);
+    button_box.set_margin_bottom(12);
+    button_box.set_halign(gtk::Align::End);
+    let all_button = gtk::Button::with_label("All");
+    let none_button = gtk::Button::with_label("None");
+    let apply_button = gtk::Button::with_label("Apply Keys");
+    apply_button.add_css_class("suggested-action");
+    button_box.append(&all_button);
+    button_box.append(&none_button);
+    button_box.append(&apply_button);
+    section.add_row(&button_box);
+
+    for (button, on) in [(&all_button, true), (&none_button, false)] {
+        let (mask, area) = (mask.clone(), area.clone());
+        button.connect_clicked(move |_| {
+            mask.borrow_mut().iter_mut().for_each(|k| *k = on);
+            area.queue_draw();
+        });
+    }
+
+    // Picking a layer shows its keys, unsaved clicks are dropped
+    {
+        let (layers, mask, area) = (layers.clone(), mask.clone(), area.clone());
+        layer_combo.connect_selected_notify(move |c| {
+            if let Some(layer) = layers.borrow().get(c.selected() as usize) {
+                *mask.borrow_mut() = layer.key_mask.clone();
+                area.queue_draw();
+            }
+        });
+    }
+    {
+        let (layer_combo, mask) = (layer_combo.clone(), mask.clone());
+        apply_button.connect_clicked(move |btn| {
+            let index = layer_combo.selected() as usize;
+            match set_layer_mask(index, mask.borrow().clone()) {
+                Some((true, _)) => show_toast(btn, "Layer keys applied"),
+                Some((false, message)) => show_toast(btn, &format!("Failed to set keys: {}", message)),
+                None => show_toast(btn, "Failed to set keys"),
+            }
+        });
+    }
+
+    // Live colours, and the layer list when layers are added elsewhere
+    glib::timeout_add_local(Duration::from_secs(1), move || {
+        if let Some(rgb) = get_keyboard_rgb() {
+            *colours.borrow_mut() = rgb;
+        }
+        if let Some(new_layers) = get_layers() {
+            let new_labels = layer_labels(&new_layers);
+            if new_labels != layer_labels(&layers.borrow()) {
+                let selected = layer_combo.selected().min(new_layers.len().saturating_sub(1) as u32);
+                *layers.borrow_mut() = new_layers;
+                let label_refs: Vec<&str> = new_labels.iter().map(|l| l.as_str()).collect();
+                layer_combo.set_model(Some(&gtk::StringList::new(&label_refs)));
+                layer_combo.set_selected(selected);
+            }
+        }
+        area.queue_draw();
+        glib::ControlFlow::Continue
+    });
+}
+
+/// Size of a key unit and the top left corner of the centred keyboard
+fn keyboard_scale(layout: &service::keyboard::Layout, width: f64, height: f64) -> (f64, f64, f64) {
+    let cols = layout.keys.iter().map(|k| (k.x + k.width) as f64).fold(1.0, f64::max);
+    let rows = layout.keys.iter().map(|k| (k.y + k.height) as f64).fold(1.0, f64::max);
+    let unit = (width / cols).min(height / rows);
+    return (unit, (width - cols * unit) / 2.0, (height - rows * unit) / 2.0);
+}
+
+/// Keys filled with their colour, the ones in `mask` outlined
+fn draw_keyboard(cr: &gtk::cairo::Context, width: f64, height: f64, layout: &service::keyboard::Layout, mask: &[bool], colours: &[u8]) {
+    let (unit, left, top) = keyboard_scale(layout, width, height);
+    let gap = (unit * 0.08).max(1.0);
+    cr.set_font_size(unit * 0.22);
+    for key in &layout.keys {
+        let x = left + key.x as f64 * unit + gap / 2.0;
+        let y = top + key.y as f64 * unit + gap / 2.0;
+        let w = key.width as f64 * unit - gap;
+        let h = key.height as f64 * unit - gap;
+        let rgb = colours.get(3 * key.index..3 * key.index + 3).unwrap_or(&[40, 40, 40]);
+        let (r, g, b) = (rgb[0] as f64 / 255.0, rgb[1] as f64 / 255.0, rgb[2] as f64 / 255.0);
+        cr.set_source_rgb(r, g, b);
+        cr.rectangle(x, y, w, h);
+        let _ = cr.fill();
+
+        let selected = mask.get(key.index).copied().unwrap_or(false);
+        if selected {
+            cr.set_source_rgb(0.21, 0.52, 0.89);
+            cr.set_line_width(3.0);
+        } else {
+            cr.set_source_rgba(0.5, 0.5, 0.5, 0.5);
+            cr.set_line_width(1.0);
+        }
+        cr.rectangle(x, y, w, h);
+        let _ = cr.stroke();
+
+        // Dark text on bright keys
+        let light = 0.299 * r + 0.587 * g + 0.114 * b > 0.5;
+        let shade = if light { 0.1 } else { 0.9 };
+        cr.set_source_rgb(shade, shade, shade);
+        let mut label = key.name.trim_start_matches("Digit").to_string();
+        while !label.is_empty() && cr.text_extents(&label).map_or(false, |e| e.width() > w - 4.0) {
+            label.pop();
+        }
+        cr.move_to(x + 3.0, y + unit * 0.3);
+        let _ = cr.show_text(&label);
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Battery page
 // ---------------------------------------------------------------------------
@@ -1717,6 +1912,65 @@ fn make_battery_page() -> SettingsPage {
         bho_slider.scale.set_sensitive(bho.0);
         section.add_row(&bho_slider.container);
 
+        let (mode, at) = get_charge_override().unwrap_or((String::from("off"), 0));
+        let mode_index = CHARGE_OVERRIDES.iter().position(|m| *m == mode).unwrap_or(0) as u32;
+        let override_combo = make_combo_row(
+            "Full Charge",
+            "Charge past the limit for a trip, the limit returns by itself",
+            &["Off", "Once", "Until Unplugged", "By Time"],
+            mode_index,
+        );
+        section.add_row(&override_combo);
+
+        let hour = match at {
+            0 => 7.0,
+            at => service::format_time_of_day(at).get(..2).and_then(|h| h.parse().ok()).unwrap_or(7.0),
+        };
+        let ready_slider = SliderRow::new(
+            "Ready By",
+            "Hour the battery should be full at",
+            0.0, 23.0, 1.0,
+            hour,
+        );
+        ready_slider.add_mark(7.0, Some("07:00"));
+        ready_slider.add_mark(12.0, Some("12:00"));
+        ready_slider.add_mark(18.0, Some("18:00"));
+        ready_slider.scale.set_sensitive(mode_index == 3);
+        section.add_row(&ready_slider.container);
+
+        let apply_override = {
+            let override_combo = override_combo.clone();
+            let ready_scale = ready_slider.scale.clone();
+            move || {
+                let index = override_combo.selected() as usize;
+                let mode = CHARGE_OVERRIDES.get(index).copied().unwrap_or("off");
+                let at = match mode {
+                    "full-by" => service::next_time_of_day(ready_scale.value() as u32, 0).unwrap_or(0),
+                    _ => 0,
+                };
+                ready_scale.set_sensitive(mode == "full-by");
+                if set_charge_override(mode, at) != Some(true) {
+                    show_toast(&override_combo, "Could not set the full charge override");
+                }
+            }
+        };
+        {
+            let refreshing = refreshing.clone();
+            let apply_override = apply_override.clone();
+            override_combo.connect_selected_notify(move |_| {
+                if refreshing.get() { return; }
+                apply_override();
+            });
+        }
+        {
+            let refreshing = refreshing.clone();
+            let override_combo = override_combo.clone();
+            ready_slider.scale.connect_value_changed(move |_| {
+                if refreshing.get() || override_combo.selected() != 3 { return; }
+                apply_override();
+            });
+        }
+
         {
             let bho_switch_ref = bho_switch.clone();
             let refreshing = refreshing.clone();
@@ -1747,6 +2001,8 @@ fn make_battery_page() -> SettingsPage {
         {
             let bho_switch = bho_switch.clone();
             let bho_scale = bho_slider.scale.clone();
+            let override_combo = override_combo.clone();
+            let ready_scale = ready_slider.scale.clone();
             glib::timeout_add_local(Duration::from_secs(2), move || {
                 if let Some((is_on, threshold)) = get_bho() {
                     refreshing.set(true);
@@ -1755,6 +2011,14 @@ fn make_battery_page() -> SettingsPage {
                     bho_scale.set_sensitive(is_on);
                     refreshing.set(false);
                 }
+                // The daemon ends overrides on its own
+                if let Some((mode, _)) = get_charge_override() {
+                    let index = CHARGE_OVERRIDES.iter().position(|m| *m == mode).unwrap_or(0) as u32;
+                    refreshing.set(true);
+                    override_combo.set_selected(index);
+                    ready_scale.set_sensitive(index == 3);
+                    refreshing.set(false);
+                }
                 glib::ControlFlow::Continue
             });
         }
@@ -1768,6 +2032,106 @@ fn make_battery_page() -> SettingsPage {
         section.add_row(&status);
     }
 
+    // --- Battery statistics recorded by the daemon ---
+    if let Some(history) = get_battery_history() {
+        let section = page.add_section(Some("Battery Health"));
+        let rows: Vec<adw::ActionRow> = battery_history_rows(&history).into_iter().map(|(title, value)| {
+            let row = adw::ActionRow::new();
+            row.set_title(title);
+            row.set_subtitle(&value);
+            section.add_row(&row);
+            row
+        }).collect();
+        glib::timeout_add_local(Duration::from_secs(60), move || {
+            if let Some(history) = get_battery_history() {
+                for (row, (_, value)) in rows.iter().zip(battery_history_rows(&history)) {
+                    row.set_subtitle(&value);
+                }
+            }
+            glib::ControlFlow::Continue
+        });
+    }
+
+    // --- Low battery rules ---
+    let rules = get_battery_rules().unwrap_or((0, 0, 3));
+    let refreshing = Rc::new(Cell::new(false));
+    let section = page.add_section(Some("Low Battery"));
+
+    let light_slider = SliderRow::new(
+        "Lights Off Below",
+        "Turn keyboard and logo lighting off on battery (%)",
+        0.0, 100.0, 5.0,
+        rules.0 as f64,
+    );
+    light_slider.add_mark(0.0, Some("Never"));
+    light_slider.add_mark(25.0, Some("25%"));
+    light_slider.add_mark(50.0, Some("50%"));
+    light_slider.add_mark(75.0, Some("75%"));
+    light_slider.add_mark(100.0, Some("100%"));
+    section.add_row(&light_slider.container);
+
+    let power_slider = SliderRow::new(
+        "Power Saving Below",
+        "Switch to a lower power mode on battery (%)",
+        0.0, 100.0, 5.0,
+        rules.1 as f64,
+    );
+    power_slider.add_mark(0.0, Some("Never"));
+    power_slider.add_mark(25.0, Some("25%"));
+    power_slider.add_mark(50.0, Some("50%"));
+    power_slider.add_mark(75.0, Some("75%"));
+    power_slider.add_mark(100.0, Some("100%"));
+    section.add_row(&power_slider.container);
+
+    let mode_combo = make_combo_row(
+        "Power Saving Mode",
+        "Profile used while the battery is low",
+        &["Balanced", "Gaming", "Creator", "Silent"],
+        rules.2 as u32,
+    );
+    section.add_row(&mode_combo);
+
+    let apply_rules = {
+        let refreshing = refreshing.clone();
+        let light_scale = light_slider.scale.clone();
+        let power_scale = power_slider.scale.clone();
+        let mode_combo = mode_combo.clone();
+        move || {
+            if refreshing.get() { return; }
+            set_battery_rules(
+                light_scale.value() as u8,
+                power_scale.value() as u8,
+                mode_combo.selected() as u8,
+            );
+        }
+    };
+    {
+        let apply_rules = apply_rules.clone();
+        light_slider.scale.connect_value_changed(move |_| apply_rules());
+    }
+    {
+        let apply_rules = apply_rules.clone();
+        power_slider.scale.connect_value_changed(move |_| apply_rules());
+    }
+    mode_combo.connect_selected_notify(move |_| apply_rules());
+
+    // Live-sync: poll daemon every 2s so widget changes appear in GUI
+    {
+        let light_scale = light_slider.scale.clone();
+        let power_scale = power_slider.scale.clone();
+        let mode_combo = mode_combo.clone();
+        glib::timeout_add_local(Duration::from_secs(2), move || {
+            if let Some((no_light, low_power, power_mode)) = get_battery_rules() {
+                refreshing.set(true);
+                light_scale.set_value(no_light as f64);
+                power_scale.set_value(low_power as f64);
+                mode_combo.set_selected(power_mode as u32);
+                refreshing.set(false);
+            }
+            glib::ControlFlow::Continue
+        });
+    }
+
     page
 }
 
@@ -1786,6 +2150,22 @@ fn gpu_mode_description(index: u32) -> &'static str {
 // About page
 // ---------------------------------------------------------------------------
 
+/// Shows `msg` in the toast overlay of the window containing `widget`
+fn show_toast(widget: &impl IsA<gtk::Widget>, msg: &str) {
+    let overlay = widget.root()
+        .and_then(|r| r.downcast::<adw::ApplicationWindow>().ok())
+        .and_then(|w| w.content())
+        .and_then(|c| c.downcast::<adw::ToastOverlay>().ok());
+    match overlay {
+        Some(o) => {
+            let toast = adw::Toast::new(msg);
+            toast.set_timeout(4);
+            o.add_toast(toast);
+        }
+        None => eprintln!("{}", msg),
+    }
+}
+
 fn make_about_page(device: SupportedDevice) -> SettingsPage {
     let page = SettingsPage::new();
 
@@ -1833,6 +2213,70 @@ fn make_about_page(device: SupportedDevice) -> SettingsPage {
     row.set_subtitle("Minimum to maximum fan speed");
     section.add_row(&row.row);
 
+    // Backup Section
+    let section = page.add_section(Some("Backup"));
+
+    let export_button = gtk::Button::with_label("Export");
+    export_button.set_valign(gtk::Align::Center);
+    export_button.connect_clicked(|btn| {
+        let window = btn.root().and_then(|r| r.downcast::<gtk::Window>().ok());
+        let dialog = gtk::FileDialog::builder()
+            .title("Export Settings")
+            .initial_name("razercontrol.json")
+            .build();
+        let btn = btn.clone();
+        dialog.save(window.as_ref(), gtk::gio::Cancellable::NONE, move |res| {
+            let Ok(file) = res else { return }; // Dialog cancelled
+            let msg = match (export_settings(), file.path()) {
+                (Some(bundle), Some(path)) => match fs::write(&path, bundle) {
+                    Ok(_) => "Settings exported".to_string(),
+                    Err(e) => format!("Export failed: {}", e),
+                },
+                _ => "Export failed".to_string(),
+            };
+            show_toast(&btn, &msg);
+        });
+    });
+    let row = SettingsRow::new("Export Settings", &export_button);
+    row.set_subtitle("Save settings and lighting to a file for other laptops");
+    section.add_row(&row.row);
+
+    let import_button = gtk::Button::with_label("Import");
+    import_button.set_valign(gtk::Align::Center);
+    import_button.connect_clicked(|btn| {
+        let window = btn.root().and_then(|r| r.downcast::<gtk::Window>().ok());
+        let dialog = gtk::FileDialog::builder()
+            .title("Import Settings")
+            .build();
+        let btn = btn.clone();
+        dialog.open(window.as_ref(), gtk::gio::Cancellable::NONE, move |res| {
+            let Ok(file) = res else { return }; // Dialog cancelled
+            let bundle = match file.path().map(fs::read_to_string) {
+                Some(Ok(b)) => b,
+                Some(Err(e)) => {
+                    show_toast(&btn, &format!("Import failed: {}", e));
+                    return;
+                }
+                None => return,
+            };
+            let msg = match import_settings(bundle) {
+                Some((true, messages)) => match messages.first() {
+                    Some(m) => format!("Settings imported. {}", m),
+                    None => "Settings imported".to_string(),
+                },
+                Some((false, messages)) => format!(
+                    "Import failed: {}",
+                    messages.first().map_or("unknown error", |m| m.as_str())
+                ),
+                None => "Failed to communicate with daemon".to_string(),
+            };
+            show_toast(&btn, &msg);
+        });
+    });
+    let row = SettingsRow::new("Import Settings", &import_button);
+    row.set_subtitle("Apply a file exported on this or another laptop");
+    section.add_row(&row.row);
+
     // Support Section
     let section = page.add_section(Some("Support Development"));
 
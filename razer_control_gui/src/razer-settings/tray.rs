@@ -1,5 +1,5 @@
-use std::fs;
 use std::sync::{Arc, Mutex};
+use service::sensors::{Component, Quantity, Readings};
 
 #[derive(Default, Clone)]
 pub struct SensorState {
@@ -20,23 +20,24 @@ pub struct SensorState {
 }
 
 impl SensorState {
-    /// Read all sensors directly from sysfs/nvidia-smi
-    fn read_fresh() -> Self {
+    /// Snapshot of the sensor readings, the fan speed needs the daemon and is left out
+    pub fn from_readings(readings: &Readings) -> Self {
+        let percent = |component| readings.get(component, Quantity::Load).map(|u| u.round() as u32);
         SensorState {
-            cpu_temp: read_cpu_temp(),
-            igpu_temp: read_igpu_temp(),
-            dgpu_temp: read_dgpu_temp(),
-            fan_speed: None, // requires daemon, skip in tray
-            on_ac: read_ac_power(),
-            battery_pct: read_battery_pct(),
-            battery_status: read_battery_status(),
-            battery_power: read_battery_power(),
-            system_power: read_system_power(),
-            cpu_util: read_cpu_util(),
-            igpu_power: read_igpu_power(),
-            igpu_util: read_igpu_util(),
-            dgpu_power: read_dgpu_power(),
-            dgpu_util: read_dgpu_util(),
+            cpu_temp: readings.get(Component::Cpu, Quantity::Temperature),
+            igpu_temp: readings.get(Component::Igpu, Quantity::Temperature),
+            dgpu_temp: readings.get(Component::Dgpu, Quantity::Temperature),
+            fan_speed: None,
+            on_ac: readings.on_ac,
+            battery_pct: readings.get(Component::Battery, Quantity::Charge).map(|p| p.round() as u8),
+            battery_status: readings.battery_status.clone(),
+            battery_power: readings.get(Component::Battery, Quantity::Power),
+            system_power: readings.get(Component::Cpu, Quantity::Power),
+            cpu_util: percent(Component::Cpu),
+            igpu_power: readings.get(Component::Igpu, Quantity::Power),
+            igpu_util: percent(Component::Igpu),
+            dgpu_power: readings.get(Component::Dgpu, Quantity::Power),
+            dgpu_util: percent(Component::Dgpu),
         }
     }
 
@@ -142,6 +143,26 @@ impl RazerTray {
     pub fn new(state: SharedSensorState) -> Self {
         RazerTray { state }
     }
+
+    /// Asks the daemon while the settings window has not filled the state
+    fn read_fresh(&self) -> SensorState {
+        match read_sensors() {
+            Some(readings) => SensorState::from_readings(&readings),
+            None => SensorState::default(),
+        }
+    }
+}
+
+/// Sensor readings of the daemon, None when it is not running
+pub fn read_sensors() -> Option<Readings> {
+    let socket = crate::comms::try_bind().ok()?;
+    match crate::comms::send_to_daemon(crate::comms::DaemonCommand::GetSensors, socket)? {
+        crate::comms::DaemonResponse::GetSensors { readings } => Some(readings),
+        response => {
+            println!("Instead of GetSensors got {response:?}");
+            None
+        }
+    }
 }
 
 impl ksni::Tray for RazerTray {
@@ -158,16 +179,16 @@ impl ksni::Tray for RazerTray {
     }
 
     fn tool_tip(&self) -> ksni::ToolTip {
-        // Try shared state first (has fan speed from daemon); fall back to direct reads
+        // Try shared state first (has fan speed from daemon); fall back to asking the daemon
         let body = if let Ok(s) = self.state.lock() {
             if s.has_data() {
                 s.format_lines()
             } else {
                 drop(s);
-                SensorState::read_fresh().format_lines()
+                self.read_fresh().format_lines()
             }
         } else {
-            SensorState::read_fresh().format_lines()
+            self.read_fresh().format_lines()
         };
 
         ksni::ToolTip {
@@ -198,6 +219,16 @@ impl ksni::Tray for RazerTray {
                 }),
                 ..Default::default()
             }),
+            ksni::MenuItem::SubMenu(ksni::menu::SubMenu {
+                label: "Full Charge".into(),
+                submenu: vec![
+                    charge_override_item("Once", "full-once"),
+                    charge_override_item("Until Unplugged", "until-unplug"),
+                    charge_override_item("By 07:00", "full-by"),
+                    charge_override_item("Back to the Limit", "off"),
+                ],
+                ..Default::default()
+            }),
             ksni::MenuItem::Separator,
             ksni::MenuItem::Standard(ksni::menu::StandardItem {
                 label: "Quit".into(),
@@ -210,247 +241,26 @@ impl ksni::Tray for RazerTray {
     }
 }
 
-// --- Sensor reading functions (standalone, no daemon dependency) ---
-
-fn read_cpu_temp() -> Option<f64> {
-    if let Ok(entries) = fs::read_dir("/sys/class/hwmon") {
-        for entry in entries.flatten() {
-            let name_path = entry.path().join("name");
-            if let Ok(name) = fs::read_to_string(&name_path) {
-                let name = name.trim();
-                if name == "k10temp" || name == "zenpower" || name == "coretemp" {
-                    let temp_path = entry.path().join("temp1_input");
-                    if let Ok(content) = fs::read_to_string(&temp_path) {
-                        if let Ok(temp) = content.trim().parse::<f64>() {
-                            return Some(temp / 1000.0);
-                        }
-                    }
-                }
-            }
-        }
-    }
-    for path in ["/sys/class/thermal/thermal_zone0/temp", "/sys/class/thermal/thermal_zone1/temp"] {
-        if let Ok(content) = fs::read_to_string(path) {
-            if let Ok(temp) = content.trim().parse::<f64>() {
-                return Some(temp / 1000.0);
-            }
-        }
-    }
-    None
-}
-
-fn read_igpu_temp() -> Option<f64> {
-    if let Ok(entries) = fs::read_dir("/sys/class/hwmon") {
-        for entry in entries.flatten() {
-            let name_path = entry.path().join("name");
-            if let Ok(name) = fs::read_to_string(&name_path) {
-                if name.trim() == "amdgpu" {
-                    for f in ["temp1_input", "temp2_input"] {
-                        let p = entry.path().join(f);
-                        if let Ok(c) = fs::read_to_string(&p) {
-                            if let Ok(t) = c.trim().parse::<f64>() {
-                                return Some(t / 1000.0);
-                            }
-                        }
+/// Menu entry setting a battery health optimizer override in the daemon
+fn charge_override_item(label: &str, mode: &'static str) -> ksni::MenuItem<RazerTray> {
+    ksni::MenuItem::Standard(ksni::menu::StandardItem {
+        label: label.into(),
+        activate: Box::new(move |_| {
+            let at = match mode {
+                "full-by" => service::next_time_of_day(7, 0).unwrap_or(0),
+                _ => 0,
+            };
+            let command = crate::comms::DaemonCommand::SetChargeOverride { mode: mode.to_string(), at };
+            match crate::comms::try_bind() {
+                Ok(socket) => {
+                    if !matches!(crate::comms::send_to_daemon(command, socket),
+                            Some(crate::comms::DaemonResponse::SetChargeOverride { result: true })) {
+                        eprintln!("Failed to set the charge override");
                     }
                 }
+                Err(e) => eprintln!("Can't connect to the daemon: {}", e),
             }
-        }
-    }
-    None
-}
-
-fn read_dgpu_temp() -> Option<f64> {
-    if let Ok(output) = std::process::Command::new("nvidia-smi")
-        .args(["--query-gpu=temperature.gpu", "--format=csv,noheader,nounits"])
-        .output()
-    {
-        if output.status.success() {
-            if let Ok(s) = String::from_utf8(output.stdout) {
-                if let Ok(t) = s.trim().parse::<f64>() {
-                    return Some(t);
-                }
-            }
-        }
-    }
-    None
-}
-
-fn read_ac_power() -> Option<bool> {
-    for name in ["AC0", "ADP0", "ADP1", "ACAD"] {
-        let path = format!("/sys/class/power_supply/{}/online", name);
-        if let Ok(content) = fs::read_to_string(&path) {
-            return Some(content.trim() == "1");
-        }
-    }
-    None
-}
-
-fn read_battery_pct() -> Option<u8> {
-    for bat in ["BAT0", "BAT1"] {
-        let path = format!("/sys/class/power_supply/{}/capacity", bat);
-        if let Ok(content) = fs::read_to_string(&path) {
-            if let Ok(pct) = content.trim().parse::<u8>() {
-                return Some(pct);
-            }
-        }
-    }
-    None
-}
-
-fn read_system_power() -> Option<f64> {
-    let paths = [
-        "/sys/class/powercap/amd-rapl:0/energy_uj",
-        "/sys/class/powercap/amd_rapl/amd-rapl:0/energy_uj",
-        "/sys/class/powercap/intel-rapl:0/energy_uj",
-        "/sys/class/powercap/intel-rapl/intel-rapl:0/energy_uj",
-    ];
-    for path in &paths {
-        if let Ok(content) = fs::read_to_string(path) {
-            if let Ok(energy) = content.trim().parse::<u64>() {
-                use std::sync::atomic::{AtomicU64, Ordering};
-                static LAST_E: AtomicU64 = AtomicU64::new(0);
-                static LAST_T: AtomicU64 = AtomicU64::new(0);
-                let now = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_micros() as u64;
-                let pe = LAST_E.swap(energy, Ordering::Relaxed);
-                let pt = LAST_T.swap(now, Ordering::Relaxed);
-                if pe > 0 && pt > 0 && energy > pe {
-                    let dt = now - pt;
-                    if dt > 0 {
-                        return Some((energy - pe) as f64 / dt as f64);
-                    }
-                }
-                return None;
-            }
-        }
-    }
-    None
-}
-
-fn read_dgpu_power() -> Option<f64> {
-    if let Ok(output) = std::process::Command::new("nvidia-smi")
-        .args(["--query-gpu=power.draw", "--format=csv,noheader,nounits"])
-        .output()
-    {
-        if output.status.success() {
-            if let Ok(s) = String::from_utf8(output.stdout) {
-                if let Ok(p) = s.trim().parse::<f64>() {
-                    return Some(p);
-                }
-            }
-        }
-    }
-    None
-}
-
-fn read_dgpu_util() -> Option<u32> {
-    if let Ok(output) = std::process::Command::new("nvidia-smi")
-        .args(["--query-gpu=utilization.gpu", "--format=csv,noheader,nounits"])
-        .output()
-    {
-        if output.status.success() {
-            if let Ok(s) = String::from_utf8(output.stdout) {
-                if let Ok(u) = s.trim().parse::<u32>() {
-                    return Some(u);
-                }
-            }
-        }
-    }
-    None
-}
-
-fn read_igpu_power() -> Option<f64> {
-    if let Ok(entries) = fs::read_dir("/sys/class/hwmon") {
-        for entry in entries.flatten() {
-            let name_path = entry.path().join("name");
-            if let Ok(name) = fs::read_to_string(&name_path) {
-                if name.trim() == "amdgpu" {
-                    let p = entry.path().join("power1_average");
-                    if let Ok(c) = fs::read_to_string(&p) {
-                        if let Ok(uw) = c.trim().parse::<f64>() {
-                            return Some(uw / 1_000_000.0);
-                        }
-                    }
-                }
-            }
-        }
-    }
-    None
-}
-
-fn read_igpu_util() -> Option<u32> {
-    for card in ["card0", "card1", "card2"] {
-        let busy_path = format!("/sys/class/drm/{}/device/gpu_busy_percent", card);
-        if let Ok(content) = fs::read_to_string(&busy_path) {
-            if let Ok(util) = content.trim().parse::<u32>() {
-                let driver_path = format!("/sys/class/drm/{}/device/driver", card);
-                if let Ok(link) = fs::read_link(&driver_path) {
-                    if link.to_string_lossy().contains("amdgpu") {
-                        return Some(util);
-                    }
-                }
-            }
-        }
-    }
-    None
-}
-
-fn read_battery_status() -> Option<String> {
-    for bat in ["BAT0", "BAT1"] {
-        let path = format!("/sys/class/power_supply/{}/status", bat);
-        if let Ok(content) = fs::read_to_string(&path) {
-            let s = content.trim().to_string();
-            if !s.is_empty() {
-                return Some(s);
-            }
-        }
-    }
-    None
-}
-
-fn read_battery_power() -> Option<f64> {
-    for bat in ["BAT0", "BAT1"] {
-        let c_path = format!("/sys/class/power_supply/{}/current_now", bat);
-        let v_path = format!("/sys/class/power_supply/{}/voltage_now", bat);
-        if let (Ok(c_str), Ok(v_str)) = (fs::read_to_string(&c_path), fs::read_to_string(&v_path)) {
-            if let (Ok(c), Ok(v)) = (c_str.trim().parse::<u64>(), v_str.trim().parse::<u64>()) {
-                if c > 0 {
-                    return Some(c as f64 * v as f64 / 1e12);
-                }
-            }
-        }
-    }
-    None
-}
-
-fn read_cpu_util() -> Option<u32> {
-    use std::sync::atomic::{AtomicU64, Ordering};
-    static LAST_IDLE: AtomicU64 = AtomicU64::new(0);
-    static LAST_TOTAL: AtomicU64 = AtomicU64::new(0);
-
-    if let Ok(content) = fs::read_to_string("/proc/stat") {
-        if let Some(line) = content.lines().next() {
-            let fields: Vec<&str> = line.split_whitespace().collect();
-            if fields.len() >= 5 && fields[0] == "cpu" {
-                let mut total: u64 = 0;
-                for f in &fields[1..] {
-                    if let Ok(v) = f.parse::<u64>() { total += v; }
-                }
-                let idle = fields[4].parse::<u64>().unwrap_or(0);
-                let prev_idle = LAST_IDLE.swap(idle, Ordering::Relaxed);
-                let prev_total = LAST_TOTAL.swap(total, Ordering::Relaxed);
-                if prev_total > 0 {
-                    let d_idle = idle.wrapping_sub(prev_idle);
-                    let d_total = total.wrapping_sub(prev_total);
-                    if d_total > 0 {
-                        return Some((100.0 * (1.0 - d_idle as f64 / d_total as f64)).round() as u32);
-                    }
-                }
-            }
-        }
-    }
-    None
+        }),
+        ..Default::default()
+    })
 }
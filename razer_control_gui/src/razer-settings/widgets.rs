@@ -145,7 +145,7 @@ impl ColorRow {
 /// AC/Battery profile toggle — two linked ToggleButtons sharing an Rc<Cell<bool>> for the current AC state.
 /// Returns (toggle_box, is_ac) where toggle_box is the widget to insert and is_ac tracks the state.
 pub fn make_profile_toggle() -> (gtk::Box, Rc<Cell<bool>>) {
-    let on_ac = super::util::check_if_running_on_ac_power().unwrap_or(true);
+    let on_ac = service::sensors::ac_online().unwrap_or(true);
     let is_ac = Rc::new(Cell::new(on_ac));
 
     let ac_btn = gtk::ToggleButton::with_label("AC Power");
@@ -0,0 +1,554 @@
+//! Temperature, power and load sensors of the laptop, shared by the daemon,
+//! the CLI, the tray and the GUI. Sources are found by driver name, hwmon
+//! and card numbers change between boots and kernels.
+
+use serde::{Serialize, Deserialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+
+const HWMON_DIR: &str = "/sys/class/hwmon";
+const POWERCAP_DIR: &str = "/sys/class/powercap";
+const DRM_DIR: &str = "/sys/class/drm";
+const PCI_DIR: &str = "/sys/bus/pci/devices";
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+const THERMAL_ZONE: &str = "/sys/class/thermal/thermal_zone0/temp";
+
+const CPU_HWMON: [&str; 3] = ["k10temp", "zenpower", "coretemp"];
+const VENDOR_NVIDIA: &str = "0x10de";
+const CHARGE_THRESHOLD: &str = "charge_control_end_threshold";
+
+// Since CVE-2020-8694 (PLATYPUS) the kernel lets only root read energy_uj
+const RAPL_NOTE: &str = "CPU package and Intel iGPU power are not shown: RAPL energy counters are readable by root only (CVE-2020-8694)";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    Cpu,
+    Igpu,
+    Dgpu,
+    Battery,
+}
+
+impl Component {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Component::Cpu => "CPU",
+            Component::Igpu => "iGPU",
+            Component::Dgpu => "dGPU",
+            Component::Battery => "Battery",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantity {
+    Temperature,
+    Power,
+    Load,
+    Charge,
+}
+
+impl Quantity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Quantity::Temperature => "temperature",
+            Quantity::Power => "power",
+            Quantity::Load => "load",
+            Quantity::Charge => "charge",
+        }
+    }
+
+    pub fn unit(&self) -> Unit {
+        match self {
+            Quantity::Temperature => Unit::Celsius,
+            Quantity::Power => Unit::Watt,
+            Quantity::Load | Quantity::Charge => Unit::Percent,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Celsius,
+    Watt,
+    Percent,
+}
+
+impl Unit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Unit::Celsius => "\u{00B0}C",
+            Unit::Watt => "W",
+            Unit::Percent => "%",
+        }
+    }
+}
+
+/// One sensor value in the unit of its quantity
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Reading {
+    pub component: Component,
+    pub quantity: Quantity,
+    pub value: f64,
+    pub source: String, // driver or sysfs node the value comes from
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Readings {
+    pub values: Vec<Reading>,
+    pub on_ac: Option<bool>,
+    pub battery_status: Option<String>, // Charging, Discharging, Full, Not charging
+    pub notes: Vec<String>, // sensors that exist but cannot be read
+}
+
+impl Readings {
+    pub fn get(&self, component: Component, quantity: Quantity) -> Option<f64> {
+        self.values.iter()
+            .find(|r| r.component == component && r.quantity == quantity)
+            .map(|r| r.value)
+    }
+}
+
+/// A RAPL energy counter, its power is the energy used between two reads
+struct EnergyCounter {
+    name: String,
+    path: PathBuf,
+    range: u64, // max_energy_range_uj, the counter wraps there
+    last: Option<(u64, Instant)>,
+}
+
+impl EnergyCounter {
+    fn new(name: &str, dir: &Path) -> EnergyCounter {
+        let mut counter = EnergyCounter {
+            name: name.to_string(),
+            path: dir.join("energy_uj"),
+            range: read_value(&dir.join("max_energy_range_uj")).unwrap_or(0),
+            last: None,
+        };
+        counter.watts(); // the first read only sets the start
+        counter
+    }
+
+    fn watts(&mut self) -> Option<f64> {
+        let energy: u64 = read_value(&self.path)?;
+        let now = Instant::now();
+        let (last_energy, last_time) = self.last.replace((energy, now))?;
+        let secs = now.duration_since(last_time).as_secs_f64();
+        let used = if energy >= last_energy {
+            energy - last_energy
+        } else {
+            (energy + self.range).checked_sub(last_energy)?
+        };
+        if secs <= 0.0 {
+            return None;
+        }
+        Some(used as f64 / 1_000_000.0 / secs)
+    }
+}
+
+/// The integrated GPU as DRM reports it
+enum IgpuLoad {
+    Busy(PathBuf), // amdgpu gpu_busy_percent
+    Frequency(PathBuf, PathBuf), // Intel has no busy counter, the clock is used instead
+}
+
+pub struct Sensors {
+    cpu_temp: Option<(PathBuf, String)>,
+    igpu_hwmon: Option<PathBuf>, // amdgpu
+    igpu_load: Option<IgpuLoad>,
+    dgpu_hwmon: Option<(PathBuf, String)>, // nouveau
+    nvidia: Option<PathBuf>, // PCI device of the NVIDIA driver, read with nvidia-smi
+    package: Option<EnergyCounter>,
+    uncore: Option<EnergyCounter>, // Intel iGPU
+    battery: Option<String>,
+    cpu_times: CpuTimes,
+    notes: Vec<String>,
+}
+
+impl Default for Sensors {
+    fn default() -> Sensors {
+        Sensors::new()
+    }
+}
+
+impl Sensors {
+    /// Finds the sensors of this machine. Power and load are measured
+    /// between two reads, so the first values come with the next `read`.
+    pub fn new() -> Sensors {
+        let mut sensors = Sensors {
+            cpu_temp: None,
+            igpu_hwmon: None,
+            igpu_load: None,
+            dgpu_hwmon: None,
+            nvidia: None,
+            package: None,
+            uncore: None,
+            battery: None,
+            cpu_times: CpuTimes::new(),
+            notes: vec![],
+        };
+
+        for (_, dir) in list_dir(HWMON_DIR) {
+            let driver = read_string(&dir.join("name")).unwrap_or_default();
+            if CPU_HWMON.contains(&driver.as_str()) && sensors.cpu_temp.is_none() {
+                sensors.cpu_temp = Some((dir.join("temp1_input"), driver));
+            } else if driver == "amdgpu" && sensors.igpu_hwmon.is_none() {
+                // Razer laptops pair an AMD APU with an NVIDIA card, so amdgpu is the iGPU
+                sensors.igpu_hwmon = Some(dir);
+            } else if driver == "nouveau" && sensors.dgpu_hwmon.is_none() {
+                sensors.dgpu_hwmon = Some((dir, driver));
+            }
+        }
+        if sensors.cpu_temp.is_none() && Path::new(THERMAL_ZONE).exists() {
+            sensors.cpu_temp = Some((PathBuf::from(THERMAL_ZONE), String::from("thermal_zone0")));
+        }
+
+        for (card, dir) in list_dir(DRM_DIR) {
+            // card0-eDP-1 and the like are connectors
+            if !card.starts_with("card") || card.contains('-') || sensors.igpu_load.is_some() {
+                continue;
+            }
+            match driver_name(&dir.join("device")).as_deref() {
+                Some("amdgpu") => {
+                    let busy = dir.join("device/gpu_busy_percent");
+                    if busy.exists() {
+                        sensors.igpu_load = Some(IgpuLoad::Busy(busy));
+                    }
+                }
+                Some("i915") | Some("xe") => {
+                    let gt = dir.join("gt/gt0");
+                    if gt.join("rps_act_freq_mhz").exists() {
+                        sensors.igpu_load = Some(IgpuLoad::Frequency(
+                            gt.join("rps_act_freq_mhz"), gt.join("rps_max_freq_mhz")));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        sensors.nvidia = list_dir(PCI_DIR).into_iter()
+            .map(|(_, dir)| dir)
+            .find(|dir| read_string(&dir.join("vendor")).as_deref() == Some(VENDOR_NVIDIA)
+                && driver_name(dir).as_deref() == Some("nvidia"));
+
+        for (node, dir) in list_dir(POWERCAP_DIR) {
+            // The MMIO interface repeats the package domain of the MSR one
+            if node.starts_with("intel-rapl-mmio") {
+                continue;
+            }
+            let name = read_string(&dir.join("name"));
+            if matches!(name.as_deref(), Some("package-0") | Some("uncore")) && fs::File::open(dir.join("energy_uj")).is_err() {
+                if !sensors.notes.iter().any(|n| n == RAPL_NOTE) {
+                    sensors.notes.push(RAPL_NOTE.to_string());
+                }
+                continue;
+            }
+            match name.as_deref() {
+                Some("package-0") if sensors.package.is_none() => {
+                    sensors.package = Some(EnergyCounter::new("package-0", &dir));
+                }
+                Some("uncore") if sensors.uncore.is_none() => {
+                    sensors.uncore = Some(EnergyCounter::new("uncore", &dir));
+                }
+                _ => {}
+            }
+        }
+
+        sensors.battery = battery_name();
+        sensors
+    }
+
+    pub fn read(&mut self) -> Readings {
+        let mut values: Vec<Reading> = vec![];
+        let mut add = |component: Component, quantity: Quantity, value: Option<f64>, source: &str| {
+            if let Some(value) = value {
+                values.push(Reading { component, quantity, value, source: source.to_string() });
+            }
+        };
+
+        if let Some((path, driver)) = &self.cpu_temp {
+            add(Component::Cpu, Quantity::Temperature, read_milli(path), driver);
+        }
+        if let Some(package) = &mut self.package {
+            add(Component::Cpu, Quantity::Power, package.watts(), &package.name);
+        }
+        add(Component::Cpu, Quantity::Load, self.cpu_times.sample().0, "/proc/stat");
+
+        if let Some(dir) = &self.igpu_hwmon {
+            let temp = read_milli(&dir.join("temp1_input")).or_else(|| read_milli(&dir.join("temp2_input")));
+            add(Component::Igpu, Quantity::Temperature, temp, "amdgpu");
+            // APUs report power1_input on newer kernels
+            let power = read_micro(&dir.join("power1_average")).or_else(|| read_micro(&dir.join("power1_input")));
+            add(Component::Igpu, Quantity::Power, power, "amdgpu");
+        }
+        if let Some(uncore) = &mut self.uncore {
+            add(Component::Igpu, Quantity::Power, uncore.watts(), &uncore.name);
+        }
+        match &self.igpu_load {
+            Some(IgpuLoad::Busy(path)) => {
+                add(Component::Igpu, Quantity::Load, read_value(path), "amdgpu");
+            }
+            Some(IgpuLoad::Frequency(act, max)) => {
+                let load = match (read_value::<f64>(act), read_value::<f64>(max)) {
+                    (Some(act), Some(max)) if max > 0.0 => Some(act / max * 100.0),
+                    _ => None,
+                };
+                add(Component::Igpu, Quantity::Load, load, "i915");
+            }
+            None => {}
+        }
+
+        if let Some((dir, driver)) = &self.dgpu_hwmon {
+            add(Component::Dgpu, Quantity::Temperature, read_milli(&dir.join("temp1_input")), driver);
+            add(Component::Dgpu, Quantity::Power, read_micro(&dir.join("power1_input")), driver);
+        }
+        if let Some(dir) = &self.nvidia {
+            // nvidia-smi wakes a suspended card and keeps it from sleeping again
+            if read_string(&dir.join("power/runtime_status")).as_deref() != Some("suspended")
+                && let Some((temp, power, load)) = nvidia_smi()
+            {
+                add(Component::Dgpu, Quantity::Temperature, temp, "nvidia");
+                add(Component::Dgpu, Quantity::Power, power, "nvidia");
+                add(Component::Dgpu, Quantity::Load, load, "nvidia");
+            }
+        }
+
+        let mut battery_status = None;
+        if let Some(name) = &self.battery {
+            let battery = read_battery(name);
+            add(Component::Battery, Quantity::Charge, battery.percent, name);
+            add(Component::Battery, Quantity::Power, battery.power_w.filter(|w| *w > 0.0), name);
+            battery_status = Some(battery.status).filter(|s| !s.is_empty());
+        }
+
+        Readings { values, on_ac: ac_online(), battery_status, notes: self.notes.clone() }
+    }
+}
+
+/// Load of all CPUs and of each core between two samples, from /proc/stat
+pub struct CpuTimes {
+    last: Vec<(u64, u64)>, // idle and total time, all CPUs first
+}
+
+impl Default for CpuTimes {
+    fn default() -> CpuTimes {
+        CpuTimes::new()
+    }
+}
+
+impl CpuTimes {
+    /// Takes the first sample, loads come with the next `sample`
+    pub fn new() -> CpuTimes {
+        let mut times = CpuTimes { last: vec![] };
+        times.sample();
+        times
+    }
+
+    /// Returns the load of all CPUs and the load per core since the last call
+    pub fn sample(&mut self) -> (Option<f64>, Vec<f64>) {
+        let stat = fs::read_to_string("/proc/stat").unwrap_or_default();
+        self.update(&stat)
+    }
+
+    fn update(&mut self, stat: &str) -> (Option<f64>, Vec<f64>) {
+        // cpu, then cpu0, cpu1, ...: user nice system idle iowait irq softirq steal
+        let times: Vec<(u64, u64)> = stat.lines()
+            .take_while(|l| l.starts_with("cpu"))
+            .filter_map(|l| {
+                let fields: Vec<u64> = l.split_whitespace().skip(1).filter_map(|f| f.parse().ok()).collect();
+                Some((*fields.get(3)?, fields.iter().sum()))
+            })
+            .collect();
+        // A core that went offline or came back shifts the list, start over
+        let last = std::mem::replace(&mut self.last, times);
+        if last.len() != self.last.len() {
+            return (None, vec![]);
+        }
+        let mut loads: Vec<f64> = self.last.iter().zip(last.iter())
+            .map(|((idle, total), (last_idle, last_total))| {
+                let total = total.saturating_sub(*last_total);
+                if total == 0 {
+                    return 0.0;
+                }
+                let busy = total.saturating_sub(idle.saturating_sub(*last_idle));
+                busy as f64 / total as f64 * 100.0
+            })
+            .collect();
+        if loads.is_empty() {
+            return (None, vec![]);
+        }
+        let all = loads.remove(0);
+        (Some(all), loads)
+    }
+}
+
+/// The laptop battery as the kernel reports it, energies in Wh and power in W
+#[derive(Debug, Clone)]
+pub struct BatteryReading {
+    pub percent: Option<f64>,
+    pub status: String, // Charging, Discharging, Full, Not charging
+    pub power_w: Option<f64>, // charging or discharging, without a sign
+    pub full_wh: Option<f64>,
+    pub design_wh: Option<f64>,
+    pub cycle_count: Option<u32>,
+}
+
+/// Power supplies of one type ("Mains" or "Battery") in the power supply
+/// class. Adapter names differ between models (AC0, ADP1, ACAD, ...), mice
+/// and headsets report batteries with scope "Device", those are left out.
+fn power_supplies(kind: &str) -> Vec<(String, PathBuf)> {
+    list_dir(POWER_SUPPLY_DIR).into_iter()
+        .filter(|(_, dir)| read_string(&dir.join("type")).as_deref() == Some(kind)
+            && read_string(&dir.join("scope")).as_deref() != Some("Device"))
+        .collect()
+}
+
+fn power_supply_path(name: &str, attr: &str) -> PathBuf {
+    Path::new(POWER_SUPPLY_DIR).join(name).join(attr)
+}
+
+/// sysfs names of the mains adapters
+pub fn mains_adapters() -> Vec<String> {
+    power_supplies("Mains").into_iter().map(|(name, _)| name).collect()
+}
+
+/// sysfs name of the laptop battery
+pub fn battery_name() -> Option<String> {
+    power_supplies("Battery").into_iter().map(|(name, _)| name).next()
+}
+
+pub fn adapter_online(adapter: &str) -> Option<bool> {
+    read_string(&power_supply_path(adapter, "online")).map(|s| s == "1")
+}
+
+/// True if any mains adapter is online, None without one
+pub fn ac_online() -> Option<bool> {
+    let mut states = mains_adapters().into_iter()
+        .filter_map(|adapter| adapter_online(&adapter))
+        .peekable();
+    states.peek()?;
+    Some(states.any(|online| online))
+}
+
+/// Charge of the laptop battery in percent
+pub fn battery_capacity() -> Option<f64> {
+    read_value(&power_supply_path(&battery_name()?, "capacity"))
+}
+
+pub fn read_battery(battery: &str) -> BatteryReading {
+    let micro = |attr: &str| read_micro(&power_supply_path(battery, attr));
+    // Batteries report either energy (µWh) or charge (µAh), the latter is
+    // converted with the design voltage
+    let voltage = micro("voltage_min_design").or_else(|| micro("voltage_now"));
+    let wh = |energy: &str, charge: &str| -> Option<f64> {
+        micro(energy).or_else(|| Some(micro(charge)? * voltage?))
+    };
+    // Drivers report power directly or as current times voltage, some with a sign
+    let power_w = micro("power_now")
+        .or_else(|| Some(micro("current_now")? * micro("voltage_now")?))
+        .map(f64::abs);
+    BatteryReading {
+        percent: read_value(&power_supply_path(battery, "capacity")),
+        status: read_string(&power_supply_path(battery, "status")).unwrap_or_default(),
+        power_w,
+        full_wh: wh("energy_full", "charge_full"),
+        design_wh: wh("energy_full_design", "charge_full_design"),
+        // Some firmware reports 0 when it does not count cycles
+        cycle_count: read_value(&power_supply_path(battery, "cycle_count")).filter(|c| *c > 0),
+    }
+}
+
+/// Laptop batteries whose charge limit the kernel exposes, the one TLP,
+/// UPower and the desktop battery settings read
+pub fn threshold_batteries() -> Vec<String> {
+    power_supplies("Battery").into_iter()
+        .filter(|(_, dir)| dir.join(CHARGE_THRESHOLD).exists())
+        .map(|(name, _)| name)
+        .collect()
+}
+
+pub fn read_charge_threshold(battery: &str) -> Option<u8> {
+    read_value(&power_supply_path(battery, CHARGE_THRESHOLD))
+}
+
+pub fn write_charge_threshold(battery: &str, percent: u8) -> std::io::Result<()> {
+    fs::write(power_supply_path(battery, CHARGE_THRESHOLD), percent.to_string())
+}
+
+/// Temperature, power and load of the NVIDIA card in one call
+fn nvidia_smi() -> Option<(Option<f64>, Option<f64>, Option<f64>)> {
+    let output = Command::new("nvidia-smi")
+        .args(["--query-gpu=temperature.gpu,power.draw,utilization.gpu", "--format=csv,noheader,nounits"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    // Unsupported fields are "[N/A]"
+    let fields: Vec<Option<f64>> = text.lines().next()?
+        .split(',')
+        .map(|f| f.trim().parse().ok())
+        .collect();
+    Some((*fields.get(0)?, *fields.get(1)?, *fields.get(2)?))
+}
+
+/// Entries of a sysfs class directory, sorted by name
+fn list_dir(dir: &str) -> Vec<(String, PathBuf)> {
+    let mut entries: Vec<(String, PathBuf)> = match fs::read_dir(dir) {
+        Ok(dir) => dir.filter_map(|e| e.ok())
+            .map(|e| (e.file_name().to_string_lossy().to_string(), e.path()))
+            .collect(),
+        Err(_) => return vec![],
+    };
+    entries.sort();
+    entries
+}
+
+fn driver_name(device: &Path) -> Option<String> {
+    let link = fs::read_link(device.join("driver")).ok()?;
+    Some(link.file_name()?.to_string_lossy().to_string())
+}
+
+fn read_string(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+fn read_value<T: std::str::FromStr>(path: &Path) -> Option<T> {
+    read_string(path)?.parse().ok()
+}
+
+fn read_milli(path: &Path) -> Option<f64> {
+    read_value::<f64>(path).map(|v| v / 1000.0)
+}
+
+fn read_micro(path: &Path) -> Option<f64> {
+    read_value::<f64>(path).map(|v| v / 1_000_000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_times_give_total_and_core_loads() {
+        let mut times = CpuTimes { last: vec![] };
+        let first = "cpu  100 0 100 800 0 0 0 0\ncpu0 50 0 50 400 0 0 0 0\ncpu1 50 0 50 400 0 0 0 0\nintr 1\n";
+        assert_eq!(times.update(first), (None, vec![]));
+        // cpu0 fully busy, cpu1 idle for 100 ticks each
+        let second = "cpu  200 0 100 900 0 0 0 0\ncpu0 150 0 50 400 0 0 0 0\ncpu1 50 0 50 500 0 0 0 0\nintr 2\n";
+        assert_eq!(times.update(second), (Some(50.0), vec![100.0, 0.0]));
+        // No time passed
+        assert_eq!(times.update(second), (Some(0.0), vec![0.0, 0.0]));
+    }
+
+    #[test]
+    fn cpu_times_restart_when_cores_change() {
+        let mut times = CpuTimes { last: vec![] };
+        times.update("cpu  1 0 1 8\ncpu0 1 0 1 8\n");
+        assert_eq!(times.update("cpu  2 0 2 16\ncpu0 1 0 1 8\ncpu1 1 0 1 8\n"), (None, vec![]));
+        assert_eq!(times.update("cpu  3 0 2 17\ncpu0 2 0 1 8\ncpu1 1 0 1 9\n"), (Some(50.0), vec![100.0, 0.0]));
+        assert_eq!(times.update(""), (None, vec![]));
+    }
+}